@@ -0,0 +1,6 @@
+-- Add an initial admin user.
+-- Its password ('everythinghastostartsomewhere') must be rotated after the first deploy.
+INSERT INTO users (user_id, username, password_hash)
+VALUES ('ddf8994f-d522-4659-8d02-c1d479057be6',
+        'admin',
+        '$argon2id$v=19$m=15000,t=2,p=1$B3ihtpi+ZeaG83iHsyYnrA$5FS9MWKdOTYf4gq15kFSwnCUngPTiIt2PPH4Ci2k9zk');
@@ -0,0 +1,19 @@
+-- Create Idempotency Table
+-- The response columns are left empty while the first request for a given key
+-- is still being processed: concurrent duplicates block on the primary key
+-- until it commits and then read the saved response.
+CREATE TYPE header_pair AS
+(
+    name  TEXT,
+    value BYTEA
+);
+CREATE TABLE idempotency
+(
+    user_id              uuid        NOT NULL REFERENCES users (user_id),
+    idempotency_key      TEXT        NOT NULL,
+    response_status_code SMALLINT    NULL,
+    response_headers     header_pair[] NULL,
+    response_body        BYTEA       NULL,
+    created_at           timestamptz NOT NULL,
+    PRIMARY KEY (user_id, idempotency_key)
+);
@@ -0,0 +1,13 @@
+-- Every subscriber gets a secret token used to build their unsubscribe link.
+BEGIN;
+ALTER TABLE subscriptions
+    ADD COLUMN unsubscribe_token TEXT NULL;
+-- Backfill historical entries with a random token
+UPDATE subscriptions
+SET unsubscribe_token = replace(gen_random_uuid()::text, '-', '')
+WHERE unsubscribe_token IS NULL;
+ALTER TABLE subscriptions
+    ALTER COLUMN unsubscribe_token SET NOT NULL;
+ALTER TABLE subscriptions
+    ADD CONSTRAINT subscriptions_unsubscribe_token_key UNIQUE (unsubscribe_token);
+COMMIT;
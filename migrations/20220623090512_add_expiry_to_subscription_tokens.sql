@@ -0,0 +1,17 @@
+-- Confirmation tokens expire and can only be used once.
+BEGIN;
+ALTER TABLE subscription_tokens
+    ADD COLUMN created_at timestamptz NOT NULL DEFAULT now();
+ALTER TABLE subscription_tokens
+    ADD COLUMN expires_at timestamptz NULL;
+-- Give historical tokens the default time to live, starting from now
+UPDATE subscription_tokens
+SET expires_at = created_at + interval '24 hours'
+WHERE expires_at IS NULL;
+ALTER TABLE subscription_tokens
+    ALTER COLUMN expires_at SET NOT NULL;
+ALTER TABLE subscription_tokens
+    ALTER COLUMN created_at DROP DEFAULT;
+ALTER TABLE subscription_tokens
+    ADD COLUMN consumed_at timestamptz NULL;
+COMMIT;
@@ -0,0 +1,14 @@
+-- Issues are addressed by a human readable slug in the public archive.
+BEGIN;
+ALTER TABLE newsletter_issues
+    ADD COLUMN slug TEXT NULL;
+-- Backfill historical issues: slugified title followed by the start of their id
+UPDATE newsletter_issues
+SET slug = trim(BOTH '-' FROM lower(regexp_replace(title, '[^a-zA-Z0-9]+', '-', 'g')))
+    || '-' || left(newsletter_issue_id::text, 8)
+WHERE slug IS NULL;
+ALTER TABLE newsletter_issues
+    ALTER COLUMN slug SET NOT NULL;
+ALTER TABLE newsletter_issues
+    ADD CONSTRAINT newsletter_issues_slug_key UNIQUE (slug);
+COMMIT;
@@ -0,0 +1,14 @@
+-- Issues can be scheduled to be sent later.
+-- `published_at` is only set once an issue has actually been sent.
+BEGIN;
+ALTER TABLE newsletter_issues
+    ADD COLUMN status TEXT NOT NULL DEFAULT 'published';
+ALTER TABLE newsletter_issues
+    ALTER COLUMN status DROP DEFAULT;
+ALTER TABLE newsletter_issues
+    ADD COLUMN send_at timestamptz NULL;
+ALTER TABLE newsletter_issues
+    ALTER COLUMN published_at DROP NOT NULL;
+CREATE INDEX newsletter_issues_send_at_idx ON newsletter_issues (send_at)
+    WHERE status = 'scheduled';
+COMMIT;
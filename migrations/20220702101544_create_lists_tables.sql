@@ -0,0 +1,48 @@
+-- Subscribers join one or more mailing lists, and issues are sent to lists.
+-- Everything that existed before joins the `newsletter` list.
+BEGIN;
+CREATE TABLE lists
+(
+    list_id    uuid        NOT NULL,
+    slug       TEXT        NOT NULL UNIQUE,
+    name       TEXT        NOT NULL,
+    created_at timestamptz NOT NULL,
+    PRIMARY KEY (list_id)
+);
+INSERT INTO lists (list_id, slug, name, created_at)
+VALUES (gen_random_uuid(), 'newsletter', 'Newsletter', now());
+
+-- Confirmation happens per list: `subscriptions.status` only tells whether the
+-- address itself is reachable.
+CREATE TABLE list_memberships
+(
+    subscriber_id uuid        NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+    list_id       uuid        NOT NULL REFERENCES lists (list_id),
+    status        TEXT        NOT NULL,
+    subscribed_at timestamptz NOT NULL,
+    PRIMARY KEY (subscriber_id, list_id)
+);
+INSERT INTO list_memberships (subscriber_id, list_id, status, subscribed_at)
+SELECT s.id, l.list_id, s.status, s.subscribed_at
+FROM subscriptions s, lists l
+WHERE l.slug = 'newsletter';
+
+ALTER TABLE subscription_tokens
+    ADD COLUMN list_id uuid NULL REFERENCES lists (list_id);
+UPDATE subscription_tokens
+SET list_id = (SELECT list_id FROM lists WHERE slug = 'newsletter')
+WHERE list_id IS NULL;
+ALTER TABLE subscription_tokens
+    ALTER COLUMN list_id SET NOT NULL;
+
+CREATE TABLE newsletter_issue_lists
+(
+    newsletter_issue_id uuid NOT NULL REFERENCES newsletter_issues (newsletter_issue_id),
+    list_id             uuid NOT NULL REFERENCES lists (list_id),
+    PRIMARY KEY (newsletter_issue_id, list_id)
+);
+INSERT INTO newsletter_issue_lists (newsletter_issue_id, list_id)
+SELECT i.newsletter_issue_id, l.list_id
+FROM newsletter_issues i, lists l
+WHERE l.slug = 'newsletter';
+COMMIT;
@@ -0,0 +1,13 @@
+-- Subscribers pick how often they want to hear from us in the preference center.
+BEGIN;
+ALTER TABLE subscriptions
+    ADD COLUMN frequency TEXT NULL;
+UPDATE subscriptions
+SET frequency = 'every_issue'
+WHERE frequency IS NULL;
+ALTER TABLE subscriptions
+    ALTER COLUMN frequency SET NOT NULL;
+-- When they were last sent an issue, to honour their frequency.
+ALTER TABLE subscriptions
+    ADD COLUMN last_emailed_at timestamptz NULL;
+COMMIT;
@@ -0,0 +1,19 @@
+-- Erasing a subscriber removes their confirmation tokens along with them.
+BEGIN;
+ALTER TABLE subscription_tokens
+    DROP CONSTRAINT subscription_tokens_subscriber_id_fkey;
+ALTER TABLE subscription_tokens
+    ADD CONSTRAINT subscription_tokens_subscriber_id_fkey
+        FOREIGN KEY (subscriber_id) REFERENCES subscriptions (id) ON DELETE CASCADE;
+
+-- Addresses we must not subscribe again, e.g. after an erasure request.
+-- Only a hash of the address is kept.
+CREATE TABLE suppressions
+(
+    email_hash TEXT        NOT NULL,
+    reason     TEXT        NOT NULL,
+    source     TEXT        NOT NULL,
+    created_at timestamptz NOT NULL,
+    PRIMARY KEY (email_hash)
+);
+COMMIT;
@@ -0,0 +1,9 @@
+-- Token buckets limiting how often `POST /subscriptions` can be called,
+-- shared by every replica when `rate_limit.backend` is `postgres`.
+CREATE TABLE rate_limit_buckets
+(
+    key        TEXT             NOT NULL,
+    tokens     DOUBLE PRECISION NOT NULL,
+    updated_at timestamptz      NOT NULL,
+    PRIMARY KEY (key)
+);
@@ -0,0 +1,22 @@
+-- Bounces reported by the email provider's webhook.
+-- `bounce_id` is the provider's: webhooks may be delivered more than once.
+BEGIN;
+CREATE TABLE email_bounces
+(
+    bounce_id     TEXT        NOT NULL,
+    subscriber_id uuid        NOT NULL
+        REFERENCES subscriptions (id) ON DELETE CASCADE,
+    -- `hard` or `soft`
+    kind          TEXT        NOT NULL,
+    -- As reported by the provider, e.g. `HardBounce` or `Transient`
+    bounce_type   TEXT        NOT NULL,
+    details       TEXT        NOT NULL,
+    bounced_at    timestamptz NOT NULL,
+    received_at   timestamptz NOT NULL,
+    PRIMARY KEY (bounce_id)
+);
+
+-- Soft bounces since the subscriber last (re)subscribed: past a threshold, they are
+-- marked as `bounced`.
+ALTER TABLE subscriptions ADD COLUMN soft_bounce_count INTEGER NOT NULL DEFAULT 0;
+COMMIT;
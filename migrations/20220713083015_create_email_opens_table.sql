@@ -0,0 +1,17 @@
+-- Opens recorded by the tracking pixel of each delivered issue, one row per recipient.
+BEGIN;
+CREATE TABLE email_opens
+(
+    newsletter_issue_id uuid        NOT NULL
+        REFERENCES newsletter_issues (newsletter_issue_id) ON DELETE CASCADE,
+    subscriber_id       uuid        NOT NULL
+        REFERENCES subscriptions (id) ON DELETE CASCADE,
+    open_count          INTEGER     NOT NULL,
+    first_opened_at     timestamptz NOT NULL,
+    last_opened_at      timestamptz NOT NULL,
+    PRIMARY KEY (newsletter_issue_id, subscriber_id)
+);
+
+-- Issues sent to privacy-sensitive lists can be published without the pixel.
+ALTER TABLE newsletter_issues ADD COLUMN track_opens BOOLEAN NOT NULL DEFAULT TRUE;
+COMMIT;
@@ -0,0 +1,19 @@
+-- Clicks on the links of each delivered issue, one row per recipient and link.
+BEGIN;
+CREATE TABLE email_clicks
+(
+    newsletter_issue_id uuid        NOT NULL
+        REFERENCES newsletter_issues (newsletter_issue_id) ON DELETE CASCADE,
+    subscriber_id       uuid        NOT NULL
+        REFERENCES subscriptions (id) ON DELETE CASCADE,
+    -- The link as it appears in the issue
+    url                 TEXT        NOT NULL,
+    click_count         INTEGER     NOT NULL,
+    first_clicked_at    timestamptz NOT NULL,
+    last_clicked_at     timestamptz NOT NULL,
+    PRIMARY KEY (newsletter_issue_id, subscriber_id, url)
+);
+
+-- Issues sent to privacy-sensitive lists can be published with their links left as they are.
+ALTER TABLE newsletter_issues ADD COLUMN track_clicks BOOLEAN NOT NULL DEFAULT TRUE;
+COMMIT;
@@ -0,0 +1,19 @@
+-- What happened to each recipient of each issue, for delivery statistics.
+BEGIN;
+CREATE TABLE issue_deliveries
+(
+    newsletter_issue_id uuid        NOT NULL
+        REFERENCES newsletter_issues (newsletter_issue_id) ON DELETE CASCADE,
+    subscriber_id       uuid        NOT NULL
+        REFERENCES subscriptions (id) ON DELETE CASCADE,
+    -- `pending` until the worker picks the task, then `delivered`, `failed` or `skipped`
+    status              TEXT        NOT NULL,
+    enqueued_at         timestamptz NOT NULL,
+    attempted_at        timestamptz NULL,
+    -- Bounces and unsubscribes are attributed to the last issue delivered to the subscriber
+    bounced_at          timestamptz NULL,
+    unsubscribed_at     timestamptz NULL,
+    PRIMARY KEY (newsletter_issue_id, subscriber_id)
+);
+CREATE INDEX issue_deliveries_subscriber_id_idx ON issue_deliveries (subscriber_id, attempted_at);
+COMMIT;
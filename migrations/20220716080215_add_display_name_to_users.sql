@@ -0,0 +1,2 @@
+-- Shown as the author of published issues: usernames are logins and must stay private.
+ALTER TABLE users ADD COLUMN display_name TEXT NULL;
@@ -0,0 +1,2 @@
+-- Deliveries are held back until the subscriber's frequency lets us email them again.
+ALTER TABLE issue_delivery_queue ADD COLUMN execute_after timestamptz NOT NULL DEFAULT now();
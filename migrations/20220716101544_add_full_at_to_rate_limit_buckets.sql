@@ -0,0 +1,7 @@
+-- A full bucket behaves exactly like a missing one: it is deleted once `full_at` has passed.
+-- Buckets created before this column existed are forgotten by the next sweep.
+BEGIN;
+ALTER TABLE rate_limit_buckets ADD COLUMN full_at timestamptz NOT NULL DEFAULT now();
+ALTER TABLE rate_limit_buckets ALTER COLUMN full_at DROP DEFAULT;
+CREATE INDEX rate_limit_buckets_full_at_idx ON rate_limit_buckets (full_at);
+COMMIT;
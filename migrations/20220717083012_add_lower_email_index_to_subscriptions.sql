@@ -0,0 +1,2 @@
+-- Email webhooks look subscribers up ignoring the case of their address.
+CREATE INDEX subscriptions_lower_email_idx ON subscriptions (lower(email));
@@ -0,0 +1,269 @@
+//! Checks telling scripted signups apart from people filling in the form.
+//!
+//! - a honeypot field, hidden from people, that bots fill in;
+//! - a minimum time between handing out the form and getting it back,
+//!   measured with a signed `form_token` so that bots cannot backdate it;
+//! - optionally, a challenge (hCaptcha, Turnstile, ...) verified against
+//!   the provider's `siteverify` endpoint.
+use anyhow::Context;
+use chrono::{Duration, TimeZone, Utc};
+use reqwest::Client;
+use secrecy::{ExposeSecret, Secret};
+
+use crate::signed_token;
+
+const PURPOSE: &str = "signup_form";
+
+/// What a signup form carries for the checks to look at.
+#[derive(Default)]
+pub struct Submission<'a> {
+    pub honeypot: Option<&'a str>,
+    pub form_token: Option<&'a str>,
+    pub challenge_response: Option<&'a str>,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum BotCheckError {
+    /// The submission looks automated, for the reason given.
+    #[error("{0}")]
+    Suspicious(String),
+    #[error(transparent)]
+    Unexpected(#[from] anyhow::Error),
+}
+
+pub struct BotProtection {
+    hmac_secret: Secret<String>,
+    /// Zero disables the check, and makes `form_token` optional.
+    min_submit_time: Duration,
+    form_token_ttl: Duration,
+    challenge: Option<ChallengeVerifier>,
+}
+
+impl BotProtection {
+    pub fn new(
+        hmac_secret: Secret<String>,
+        min_submit_time: Duration,
+        form_token_ttl: Duration,
+        challenge: Option<ChallengeVerifier>,
+    ) -> Self {
+        Self {
+            hmac_secret,
+            min_submit_time,
+            form_token_ttl,
+            challenge,
+        }
+    }
+
+    /// A token recording when the form was handed out.
+    pub fn form_token(&self) -> String {
+        let now = Utc::now();
+        signed_token::sign(
+            &self.hmac_secret,
+            PURPOSE,
+            &now.timestamp_millis().to_string(),
+            now + self.form_token_ttl,
+        )
+    }
+
+    #[tracing::instrument(name = "Checking a signup for bots", skip(self, submission))]
+    pub async fn check(&self, submission: &Submission<'_>) -> Result<(), BotCheckError> {
+        if submission.honeypot.is_some_and(|v| !v.trim().is_empty()) {
+            return Err(BotCheckError::Suspicious(
+                "The honeypot field was filled in.".into(),
+            ));
+        }
+        if self.min_submit_time > Duration::zero() {
+            self.check_submit_time(submission.form_token)?;
+        }
+        if let Some(challenge) = &self.challenge {
+            let response = submission
+                .challenge_response
+                .filter(|r| !r.is_empty())
+                .ok_or_else(|| BotCheckError::Suspicious("The challenge was not solved.".into()))?;
+            if !challenge.verify(response).await? {
+                return Err(BotCheckError::Suspicious(
+                    "The challenge response was rejected.".into(),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn check_submit_time(&self, form_token: Option<&str>) -> Result<(), BotCheckError> {
+        let form_token = form_token
+            .ok_or_else(|| BotCheckError::Suspicious("The form token is missing.".into()))?;
+        let issued_at = signed_token::verify(&self.hmac_secret, PURPOSE, form_token)
+            .map_err(|e| BotCheckError::Suspicious(format!("Bad form token: {}", e)))?
+            .parse::<i64>()
+            .context("The form token does not carry a timestamp.")?;
+        if Utc::now() - Utc.timestamp_millis(issued_at) < self.min_submit_time {
+            return Err(BotCheckError::Suspicious(
+                "The form was submitted too quickly.".into(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Verifies challenge responses with the `siteverify` protocol shared by
+/// hCaptcha, Turnstile and reCAPTCHA.
+pub struct ChallengeVerifier {
+    http_client: Client,
+    verify_url: String,
+    secret: Secret<String>,
+}
+
+impl ChallengeVerifier {
+    pub fn new(verify_url: String, secret: Secret<String>, timeout: std::time::Duration) -> Self {
+        Self {
+            http_client: Client::builder().timeout(timeout).build().unwrap(),
+            verify_url,
+            secret,
+        }
+    }
+
+    /// Whether the provider confirms `response` solved the challenge.
+    #[tracing::instrument(name = "Verifying a challenge response", skip(self, response))]
+    pub async fn verify(&self, response: &str) -> Result<bool, anyhow::Error> {
+        let outcome: VerifyResponse = self
+            .http_client
+            .post(&self.verify_url)
+            .form(&VerifyRequest {
+                secret: self.secret.expose_secret(),
+                response,
+            })
+            .send()
+            .await
+            .context("Failed to reach the challenge provider.")?
+            .error_for_status()
+            .context("The challenge provider returned an error.")?
+            .json()
+            .await
+            .context("Failed to parse the challenge provider's answer.")?;
+        Ok(outcome.success)
+    }
+}
+
+#[derive(serde::Serialize)]
+struct VerifyRequest<'a> {
+    secret: &'a str,
+    response: &'a str,
+}
+
+#[derive(serde::Deserialize)]
+struct VerifyResponse {
+    success: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use claim::{assert_err, assert_ok};
+    use secrecy::Secret;
+    use wiremock::matchers::{body_string_contains, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use super::{BotCheckError, BotProtection, ChallengeVerifier, Submission};
+
+    fn protection(
+        min_submit_time: Duration,
+        challenge: Option<ChallengeVerifier>,
+    ) -> BotProtection {
+        BotProtection::new(
+            Secret::new("secret".into()),
+            min_submit_time,
+            Duration::hours(1),
+            challenge,
+        )
+    }
+
+    fn verifier(uri: String) -> ChallengeVerifier {
+        ChallengeVerifier::new(
+            format!("{}/siteverify", uri),
+            Secret::new("challenge-secret".into()),
+            std::time::Duration::from_millis(200),
+        )
+    }
+
+    #[tokio::test]
+    async fn a_filled_in_honeypot_is_suspicious() {
+        let protection = protection(Duration::zero(), None);
+        let submission = Submission {
+            honeypot: Some("https://spam.example.com"),
+            ..Default::default()
+        };
+
+        let outcome = protection.check(&submission).await;
+
+        assert!(matches!(outcome, Err(BotCheckError::Suspicious(_))));
+    }
+
+    #[tokio::test]
+    async fn a_form_submitted_too_quickly_is_suspicious() {
+        let protection = protection(Duration::hours(1), None);
+        let form_token = protection.form_token();
+        let submission = Submission {
+            form_token: Some(&form_token),
+            ..Default::default()
+        };
+
+        let outcome = protection.check(&submission).await;
+
+        assert!(matches!(outcome, Err(BotCheckError::Suspicious(_))));
+    }
+
+    #[tokio::test]
+    async fn a_form_token_signed_with_another_key_is_suspicious() {
+        let protection = protection(Duration::milliseconds(1), None);
+        let form_token = BotProtection::new(
+            Secret::new("another-secret".into()),
+            Duration::milliseconds(1),
+            Duration::hours(1),
+            None,
+        )
+        .form_token();
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        let submission = Submission {
+            form_token: Some(&form_token),
+            ..Default::default()
+        };
+
+        let outcome = protection.check(&submission).await;
+
+        assert!(matches!(outcome, Err(BotCheckError::Suspicious(_))));
+    }
+
+    #[tokio::test]
+    async fn the_challenge_response_is_sent_to_the_provider_with_our_secret() {
+        let mock_server = MockServer::start().await;
+        Mock::given(path("/siteverify"))
+            .and(method("POST"))
+            .and(body_string_contains("secret=challenge-secret"))
+            .and(body_string_contains("response=solved"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "success": true
+            })))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        let protection = protection(Duration::zero(), Some(verifier(mock_server.uri())));
+        let submission = Submission {
+            challenge_response: Some("solved"),
+            ..Default::default()
+        };
+
+        assert_ok!(protection.check(&submission).await);
+    }
+
+    #[tokio::test]
+    async fn an_unavailable_provider_is_an_unexpected_error() {
+        let mock_server = MockServer::start().await;
+        Mock::given(path("/siteverify"))
+            .respond_with(ResponseTemplate::new(500))
+            .mount(&mock_server)
+            .await;
+        let verifier = verifier(mock_server.uri());
+
+        assert_err!(verifier.verify("solved").await);
+    }
+}
@@ -1,17 +1,33 @@
 use std::convert::{TryFrom, TryInto};
+use std::sync::Arc;
 
 use secrecy::{ExposeSecret, Secret};
 use serde_aux::field_attributes::deserialize_number_from_string;
 use sqlx::ConnectOptions;
 use sqlx::postgres::{PgConnectOptions, PgSslMode};
+use sqlx::PgPool;
 
+use crate::bot_protection::{BotProtection, ChallengeVerifier};
 use crate::domain::SubscriberEmail;
+use crate::email_client::{
+    EmailSender, MailgunClient, PostmarkClient, RetryPolicy, SendGridClient, SmtpAuthMechanism,
+    SmtpClient, SmtpConnectionOptions, SmtpTls,
+};
+use crate::rate_limit::{BucketLimit, InMemoryStore, PostgresStore, RateLimitStore, RateLimiter};
+use crate::suppressions::SuppressionFilter;
+use crate::templates::Templates;
+use crate::tracking::Tracking;
 
 #[derive(serde::Deserialize, Clone)]
 pub struct Settings {
     pub database: DatabaseSettings,
     pub application: ApplicationSettings,
     pub email_client: EmailClientSettings,
+    pub templates: TemplateSettings,
+    pub rate_limit: RateLimitSettings,
+    pub bot_protection: BotProtectionSettings,
+    pub email_webhooks: EmailWebhookSettings,
+    pub tracking: TrackingSettings,
 }
 
 #[derive(serde::Deserialize, Clone)]
@@ -20,6 +36,142 @@ pub struct ApplicationSettings {
     pub port: u16,
     pub host: String,
     pub base_url: String,
+    /// How long a confirmation link stays valid.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub subscription_token_ttl_hours: i64,
+    /// The key signing the links we email out.
+    pub hmac_secret: Secret<String>,
+    /// How long a link to the preference center stays valid.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub preferences_link_ttl_minutes: i64,
+    /// How long a link to export or erase a subscriber's data stays valid.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub privacy_link_ttl_minutes: i64,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct TemplateSettings {
+    /// Where email templates are loaded from, relative to the working directory.
+    pub directory: String,
+}
+
+impl TemplateSettings {
+    pub fn load(&self) -> Result<Templates, anyhow::Error> {
+        Templates::load(&self.directory)
+    }
+}
+
+/// How the email provider's webhooks reach us, see `routes::webhooks`.
+#[derive(serde::Deserialize, Clone)]
+pub struct EmailWebhookSettings {
+    /// The credentials webhooks carry, over Basic auth.
+    pub username: String,
+    pub password: Secret<String>,
+    /// Soft bounces after which we stop emailing an address.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub soft_bounce_threshold: i32,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct RateLimitSettings {
+    pub backend: RateLimitBackend,
+    /// Read the client IP from `X-Forwarded-For` rather than from the peer address.
+    pub trust_forwarded_for: bool,
+    pub per_ip: BucketSettings,
+    pub per_email: BucketSettings,
+}
+
+/// Where the rate limiting buckets are kept.
+#[derive(serde::Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum RateLimitBackend {
+    /// In each replica, which enforces its own limits.
+    Memory,
+    /// In the database, shared by every replica.
+    Postgres,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct BucketSettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub capacity: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub refill_interval_seconds: i64,
+}
+
+impl BucketSettings {
+    pub fn limit(&self) -> BucketLimit {
+        BucketLimit {
+            capacity: self.capacity,
+            refill_interval: chrono::Duration::seconds(self.refill_interval_seconds),
+        }
+    }
+}
+
+impl RateLimitSettings {
+    pub fn limiter(&self, db_pool: &PgPool) -> RateLimiter {
+        let store: Arc<dyn RateLimitStore> = match self.backend {
+            RateLimitBackend::Memory => Arc::new(InMemoryStore::new()),
+            RateLimitBackend::Postgres => Arc::new(PostgresStore::new(db_pool.clone())),
+        };
+        RateLimiter::new(
+            store,
+            self.per_ip.limit(),
+            self.per_email.limit(),
+            self.trust_forwarded_for,
+        )
+    }
+}
+
+/// Engagement tracking of newsletter issues, see `tracking`.
+#[derive(serde::Deserialize, Clone)]
+pub struct TrackingSettings {
+    /// How long after sending an issue its tracking URLs keep working.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub link_ttl_days: i64,
+}
+
+impl TrackingSettings {
+    pub fn tracking(&self, hmac_secret: Secret<String>) -> Tracking {
+        Tracking::new(hmac_secret, chrono::Duration::days(self.link_ttl_days))
+    }
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct BotProtectionSettings {
+    /// Reject signup forms sent back sooner than this after their `form_token`
+    /// was handed out. Zero disables the check.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub min_submit_seconds: i64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub form_token_ttl_hours: i64,
+    /// No challenge is required when missing.
+    pub challenge: Option<ChallengeSettings>,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct ChallengeSettings {
+    pub verify_url: String,
+    pub secret: Secret<String>,
+    pub timeout_milliseconds: u64,
+}
+
+impl BotProtectionSettings {
+    pub fn protection(self, hmac_secret: Secret<String>) -> BotProtection {
+        let challenge = self.challenge.map(|c| {
+            ChallengeVerifier::new(
+                c.verify_url,
+                c.secret,
+                std::time::Duration::from_millis(c.timeout_milliseconds),
+            )
+        });
+        BotProtection::new(
+            hmac_secret,
+            chrono::Duration::seconds(self.min_submit_seconds),
+            chrono::Duration::hours(self.form_token_ttl_hours),
+            challenge,
+        )
+    }
 }
 
 #[derive(serde::Deserialize, Clone)]
@@ -35,13 +187,148 @@ pub struct DatabaseSettings {
 
 #[derive(serde::Deserialize, Clone)]
 pub struct EmailClientSettings {
-    pub base_url: String,
+    pub provider: EmailProvider,
+    /// Required by every provider but `smtp`.
+    pub base_url: Option<String>,
     pub sender_email: String,
-    pub authorization_token: Secret<String>,
+    /// Required by every provider but `smtp`.
+    pub authorization_token: Option<Secret<String>>,
     pub timeout_milliseconds: u64,
+    pub retry: EmailRetrySettings,
+    /// Only required when `provider` is `smtp`.
+    pub smtp: Option<SmtpSettings>,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct SmtpSettings {
+    pub host: String,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub port: u16,
+    pub tls: SmtpTls,
+    pub username: Option<String>,
+    pub password: Option<Secret<String>>,
+    #[serde(default = "default_smtp_authentication")]
+    pub authentication: Vec<SmtpAuthMechanism>,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub pool_max_size: u32,
+}
+
+fn default_smtp_authentication() -> Vec<SmtpAuthMechanism> {
+    vec![SmtpAuthMechanism::Plain, SmtpAuthMechanism::Login]
+}
+
+impl SmtpSettings {
+    pub fn connection_options(self) -> SmtpConnectionOptions {
+        let credentials = match (self.username, self.password) {
+            (Some(username), Some(password)) => Some((username, password)),
+            _ => None,
+        };
+        SmtpConnectionOptions {
+            host: self.host,
+            port: self.port,
+            tls: self.tls,
+            credentials,
+            mechanisms: self.authentication,
+            pool_max_size: self.pool_max_size,
+        }
+    }
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct EmailRetrySettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_attempts: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub base_delay_milliseconds: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_delay_milliseconds: u64,
+    pub jitter: bool,
+}
+
+/// The email delivery vendor behind `EmailSender`.
+#[derive(serde::Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum EmailProvider {
+    Postmark,
+    SendGrid,
+    Mailgun,
+    Smtp,
+}
+
+impl EmailRetrySettings {
+    pub fn policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: self.max_attempts.max(1),
+            base_delay: std::time::Duration::from_millis(self.base_delay_milliseconds),
+            max_delay: std::time::Duration::from_millis(self.max_delay_milliseconds),
+            jitter: self.jitter,
+        }
+    }
 }
 
 impl EmailClientSettings {
+    /// The configured provider, refusing to email suppressed addresses.
+    pub fn client(self, db_pool: &PgPool) -> Arc<dyn EmailSender> {
+        Arc::new(SuppressionFilter::new(self.provider_client(), db_pool.clone()))
+    }
+    fn provider_client(self) -> Arc<dyn EmailSender> {
+        let sender_email = self.sender().expect("Invalid sender email address.");
+        let timeout = self.timeout();
+        let retry_policy = self.retry.policy();
+        match self.provider {
+            EmailProvider::Postmark => {
+                let (base_url, authorization_token) = self.http_api();
+                Arc::new(PostmarkClient::new(
+                    base_url,
+                    sender_email,
+                    authorization_token,
+                    timeout,
+                    retry_policy,
+                ))
+            }
+            EmailProvider::SendGrid => {
+                let (base_url, authorization_token) = self.http_api();
+                Arc::new(SendGridClient::new(
+                    base_url,
+                    sender_email,
+                    authorization_token,
+                    timeout,
+                    retry_policy,
+                ))
+            }
+            EmailProvider::Mailgun => {
+                let (base_url, authorization_token) = self.http_api();
+                Arc::new(MailgunClient::new(
+                    base_url,
+                    sender_email,
+                    authorization_token,
+                    timeout,
+                    retry_policy,
+                ))
+            }
+            EmailProvider::Smtp => {
+                let smtp = self
+                    .smtp
+                    .expect("The `smtp` settings are required by the SMTP email provider.");
+                Arc::new(
+                    SmtpClient::new(smtp.connection_options(), sender_email, timeout, retry_policy)
+                        .expect("Failed to build the SMTP email client."),
+                )
+            }
+        }
+    }
+    /// Where the HTTP API of the provider is, and how to authenticate to it.
+    fn http_api(&self) -> (String, Secret<String>) {
+        let base_url = self
+            .base_url
+            .clone()
+            .expect("`base_url` is required by HTTP API email providers.");
+        let authorization_token = self
+            .authorization_token
+            .clone()
+            .expect("`authorization_token` is required by HTTP API email providers.");
+        (base_url, authorization_token)
+    }
     pub fn sender(&self) -> Result<SubscriberEmail, String> {
         SubscriberEmail::parse(self.sender_email.clone())
     }
@@ -0,0 +1,130 @@
+//! What happened to each recipient of each issue.
+//!
+//! A row is written as `pending` when an issue is enqueued, and updated by the
+//! delivery worker once it has tried to send it. Bounces and unsubscribes
+//! carry no reference to the email that prompted them: they are attributed to
+//! the last issue delivered to the subscriber.
+use sqlx::{Postgres, Transaction};
+use uuid::Uuid;
+
+/// What the delivery worker did with a recipient of an issue.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DeliveryStatus {
+    /// The email provider accepted the email.
+    Delivered,
+    /// The email provider refused the email, or the address is invalid.
+    Failed,
+    /// The recipient was not emailed: they left or are suppressed.
+    Skipped,
+}
+
+impl DeliveryStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeliveryStatus::Delivered => "delivered",
+            DeliveryStatus::Failed => "failed",
+            DeliveryStatus::Skipped => "skipped",
+        }
+    }
+}
+
+/// Log every recipient an issue is enqueued for as `pending`.
+#[tracing::instrument(skip_all, fields(n_recipients = recipients.len()))]
+pub async fn log_enqueued(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+    recipients: &[String],
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_deliveries (newsletter_issue_id, subscriber_id, status, enqueued_at)
+        SELECT $1, s.id, 'pending', now()
+        FROM subscriptions s
+        WHERE s.email = ANY($2::text[])
+        ON CONFLICT (newsletter_issue_id, subscriber_id) DO NOTHING
+        "#,
+        newsletter_issue_id,
+        recipients
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+/// Committed along with the removal of the delivery task.
+#[tracing::instrument(skip(transaction, email))]
+pub async fn log_attempt(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+    email: &str,
+    status: DeliveryStatus,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE issue_deliveries d
+        SET status = $3, attempted_at = now()
+        FROM subscriptions s
+        WHERE
+            d.newsletter_issue_id = $1 AND
+            d.subscriber_id = s.id AND
+            s.email = $2
+        "#,
+        newsletter_issue_id,
+        email,
+        status.as_str()
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+/// Attribute a bounce to the last issue delivered to the subscriber.
+#[tracing::instrument(skip(transaction))]
+pub async fn log_bounce(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE issue_deliveries SET bounced_at = now()
+        WHERE (newsletter_issue_id, subscriber_id) = (
+            SELECT newsletter_issue_id, subscriber_id
+            FROM issue_deliveries
+            WHERE subscriber_id = $1 AND status = 'delivered'
+            ORDER BY attempted_at DESC
+            LIMIT 1
+        ) AND bounced_at IS NULL
+        "#,
+        subscriber_id
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+/// Attribute an unsubscribe to the last issue delivered to the subscriber.
+///
+/// Leaving a list from the preference center and complaining about spam
+/// count as unsubscribing too.
+#[tracing::instrument(skip(transaction))]
+pub async fn log_unsubscribe(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE issue_deliveries SET unsubscribed_at = now()
+        WHERE (newsletter_issue_id, subscriber_id) = (
+            SELECT newsletter_issue_id, subscriber_id
+            FROM issue_deliveries
+            WHERE subscriber_id = $1 AND status = 'delivered'
+            ORDER BY attempted_at DESC
+            LIMIT 1
+        ) AND unsubscribed_at IS NULL
+        "#,
+        subscriber_id
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
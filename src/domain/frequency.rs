@@ -0,0 +1,61 @@
+/// How often a subscriber is willing to hear from us.
+///
+/// Issues published while a subscriber is still within their window since
+/// the last email they received are held back until it is over. Only the
+/// newest of them is delivered then, the others remain available in the
+/// archive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Frequency {
+    EveryIssue,
+    Daily,
+    Weekly,
+}
+
+impl Frequency {
+    pub const ALL: [Frequency; 3] = [Frequency::EveryIssue, Frequency::Daily, Frequency::Weekly];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Frequency::EveryIssue => "every_issue",
+            Frequency::Daily => "daily",
+            Frequency::Weekly => "weekly",
+        }
+    }
+
+    pub fn parse(s: String) -> Result<Frequency, String> {
+        Self::ALL
+            .into_iter()
+            .find(|f| f.as_str() == s)
+            .ok_or_else(|| format!("{} is not a valid frequency.", s))
+    }
+
+    /// How the frequency is described to subscribers.
+    pub fn label(&self) -> &'static str {
+        match self {
+            Frequency::EveryIssue => "Every issue",
+            Frequency::Daily => "At most one email a day",
+            Frequency::Weekly => "At most one email a week",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claim::{assert_err, assert_ok_eq};
+
+    use super::Frequency;
+
+    #[test]
+    fn every_frequency_parses_back_from_its_name() {
+        for frequency in Frequency::ALL {
+            assert_ok_eq!(Frequency::parse(frequency.as_str().into()), frequency);
+        }
+    }
+
+    #[test]
+    fn unknown_frequencies_are_rejected() {
+        for frequency in ["", "monthly", "Weekly", " daily"] {
+            assert_err!(Frequency::parse(frequency.into()));
+        }
+    }
+}
@@ -0,0 +1,72 @@
+use uuid::Uuid;
+
+/// Longest title prefix kept in a slug.
+const MAX_TITLE_LENGTH: usize = 60;
+
+/// How an issue is addressed in the public archive, e.g.
+/// `our-very-first-issue-1f0e3c5a`: the slugified title, followed by the start
+/// of the issue id to keep slugs unique when titles repeat.
+#[derive(Debug)]
+pub struct IssueSlug(String);
+
+impl AsRef<str> for IssueSlug {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl IssueSlug {
+    pub fn new(title: &str, newsletter_issue_id: Uuid) -> Self {
+        let mut slug = String::new();
+        for c in title.chars() {
+            if c.is_ascii_alphanumeric() {
+                slug.push(c.to_ascii_lowercase());
+            } else if !slug.is_empty() && !slug.ends_with('-') {
+                slug.push('-');
+            }
+        }
+        slug.truncate(MAX_TITLE_LENGTH);
+        let slug = slug.trim_end_matches('-');
+        let id = newsletter_issue_id.to_simple().to_string();
+        if slug.is_empty() {
+            Self(id[..8].to_owned())
+        } else {
+            Self(format!("{}-{}", slug, &id[..8]))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::IssueSlug;
+
+    fn id() -> Uuid {
+        Uuid::parse_str("1f0e3c5a-2b4d-4e6f-8a9b-0c1d2e3f4a5b").unwrap()
+    }
+
+    #[test]
+    fn the_title_is_lowercased_and_separated_by_dashes() {
+        let slug = IssueSlug::new("Our  very FIRST issue!", id());
+        assert_eq!(slug.as_ref(), "our-very-first-issue-1f0e3c5a");
+    }
+
+    #[test]
+    fn non_ascii_characters_are_dropped() {
+        let slug = IssueSlug::new("¡Édition spéciale!", id());
+        assert_eq!(slug.as_ref(), "dition-sp-ciale-1f0e3c5a");
+    }
+
+    #[test]
+    fn long_titles_are_truncated() {
+        let slug = IssueSlug::new(&"a".repeat(200), id());
+        assert_eq!(slug.as_ref().len(), 60 + 1 + 8);
+    }
+
+    #[test]
+    fn a_title_without_any_usable_character_falls_back_to_the_id() {
+        let slug = IssueSlug::new("🎉", id());
+        assert_eq!(slug.as_ref(), "1f0e3c5a");
+    }
+}
@@ -0,0 +1,76 @@
+/// Longest slug accepted for a list.
+const MAX_LENGTH: usize = 64;
+
+/// How a mailing list is referred to in forms and API calls, e.g.
+/// `product-updates`: lowercase ASCII letters, digits and inner dashes.
+#[derive(Debug, Clone)]
+pub struct ListSlug(String);
+
+impl AsRef<str> for ListSlug {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+/// The list every deployment starts with, used when a request names none.
+impl Default for ListSlug {
+    fn default() -> Self {
+        Self("newsletter".into())
+    }
+}
+
+impl ListSlug {
+    pub fn parse(s: String) -> Result<ListSlug, String> {
+        let has_valid_characters = s
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+        let has_valid_dashes = !s.starts_with('-') && !s.ends_with('-') && !s.contains("--");
+        if s.is_empty() || s.len() > MAX_LENGTH || !has_valid_characters || !has_valid_dashes {
+            Err(format!("{} is not a valid list slug.", s))
+        } else {
+            Ok(Self(s))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claim::{assert_err, assert_ok};
+
+    use super::ListSlug;
+
+    #[test]
+    fn lowercase_words_separated_by_dashes_are_valid() {
+        assert_ok!(ListSlug::parse("engineering-blog-2".into()));
+    }
+
+    #[test]
+    fn the_default_list_is_valid() {
+        assert_ok!(ListSlug::parse(ListSlug::default().as_ref().into()));
+    }
+
+    #[test]
+    fn an_empty_slug_is_rejected() {
+        assert_err!(ListSlug::parse("".into()));
+    }
+
+    #[test]
+    fn a_slug_longer_than_64_characters_is_rejected() {
+        assert_ok!(ListSlug::parse("a".repeat(64)));
+        assert_err!(ListSlug::parse("a".repeat(65)));
+    }
+
+    #[test]
+    fn uppercase_letters_spaces_and_symbols_are_rejected() {
+        for slug in ["Product", "product updates", "product_updates", "prödukt"] {
+            assert_err!(ListSlug::parse(slug.into()));
+        }
+    }
+
+    #[test]
+    fn leading_trailing_or_doubled_dashes_are_rejected() {
+        for slug in ["-product", "product-", "product--updates"] {
+            assert_err!(ListSlug::parse(slug.into()));
+        }
+    }
+}
@@ -0,0 +1,183 @@
+use chrono::{DateTime, Utc};
+
+/// The tags a newsletter body can reference, as `{{ name }}`.
+const MERGE_TAGS: [&str; 3] = ["name", "email", "subscribed_at"];
+
+/// The values merge tags are replaced with, for a single recipient.
+pub struct MergeFields<'a> {
+    pub name: &'a str,
+    pub email: &'a str,
+    pub subscribed_at: DateTime<Utc>,
+}
+
+#[derive(Clone, Copy)]
+pub enum ContentFormat {
+    /// Values are HTML-escaped before being inserted.
+    Html,
+    Text,
+}
+
+enum Segment<'a> {
+    Text(&'a str),
+    Tag(&'a str),
+}
+
+/// Check that `content` only references known merge tags.
+///
+/// Merge tags are plain substitutions: no other syntax is recognised, so
+/// newsletter content can never run template logic.
+pub fn validate_merge_tags(content: &str) -> Result<(), String> {
+    parse(content).map(|_| ())
+}
+
+/// Replace every merge tag in `content` with the recipient's value.
+pub fn expand_merge_tags(
+    content: &str,
+    fields: &MergeFields,
+    format: ContentFormat,
+) -> Result<String, String> {
+    let mut expanded = String::with_capacity(content.len());
+    for segment in parse(content)? {
+        match segment {
+            Segment::Text(text) => expanded.push_str(text),
+            Segment::Tag(tag) => {
+                let value = match tag {
+                    "name" => fields.name.to_owned(),
+                    "email" => fields.email.to_owned(),
+                    "subscribed_at" => fields.subscribed_at.format("%Y-%m-%d").to_string(),
+                    _ => unreachable!("`parse` only returns known merge tags"),
+                };
+                match format {
+                    ContentFormat::Html => expanded.push_str(&escape_html(&value)),
+                    ContentFormat::Text => expanded.push_str(&value),
+                }
+            }
+        }
+    }
+    Ok(expanded)
+}
+
+/// Remove every merge tag from `content`, for readers we know nothing about
+/// (e.g. in the public archive). Content with invalid merge tags is left as is.
+pub fn strip_merge_tags(content: &str) -> String {
+    match parse(content) {
+        Ok(segments) => segments
+            .into_iter()
+            .filter_map(|segment| match segment {
+                Segment::Text(text) => Some(text),
+                Segment::Tag(_) => None,
+            })
+            .collect(),
+        Err(_) => content.to_owned(),
+    }
+}
+
+fn parse(content: &str) -> Result<Vec<Segment<'_>>, String> {
+    let mut segments = Vec::new();
+    let mut rest = content;
+    while let Some(start) = rest.find("{{") {
+        segments.push(Segment::Text(&rest[..start]));
+        let after_opening = &rest[start + 2..];
+        let end = after_opening
+            .find("}}")
+            .ok_or_else(|| "A merge tag is missing its closing `}}`.".to_string())?;
+        let tag = after_opening[..end].trim();
+        if !MERGE_TAGS.contains(&tag) {
+            return Err(format!(
+                "`{{{{ {} }}}}` is not a known merge tag. Available tags are: {}.",
+                tag,
+                MERGE_TAGS.join(", ")
+            ));
+        }
+        segments.push(Segment::Tag(tag));
+        rest = &after_opening[end + 2..];
+    }
+    segments.push(Segment::Text(rest));
+    Ok(segments)
+}
+
+fn escape_html(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#x27;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+    use claim::{assert_err, assert_ok};
+
+    use super::{
+        expand_merge_tags, strip_merge_tags, validate_merge_tags, ContentFormat, MergeFields,
+    };
+
+    fn fields() -> MergeFields<'static> {
+        MergeFields {
+            name: "Ursula & co",
+            email: "ursula_le_guin@gmail.com",
+            subscribed_at: Utc.ymd(2022, 6, 12).and_hms(9, 47, 33),
+        }
+    }
+
+    #[test]
+    fn content_without_merge_tags_is_valid() {
+        assert_ok!(validate_merge_tags("Hello, world! {single braces} are fine"));
+    }
+
+    #[test]
+    fn known_merge_tags_are_valid_with_or_without_spaces() {
+        assert_ok!(validate_merge_tags("{{ name }} {{email}} {{  subscribed_at }}"));
+    }
+
+    #[test]
+    fn an_unknown_merge_tag_is_rejected() {
+        assert_err!(validate_merge_tags("Hello {{ nickname }}"));
+    }
+
+    #[test]
+    fn an_unclosed_merge_tag_is_rejected() {
+        assert_err!(validate_merge_tags("Hello {{ name"));
+    }
+
+    #[test]
+    fn template_syntax_is_not_interpreted() {
+        assert_err!(validate_merge_tags("{% for x in y %}{{ x }}{% endfor %}"));
+        assert_err!(validate_merge_tags("{{ name | upper }}"));
+    }
+
+    #[test]
+    fn merge_tags_are_expanded() {
+        let expanded = expand_merge_tags(
+            "Hi {{ name }} ({{ email }}), subscribed on {{ subscribed_at }}.",
+            &fields(),
+            ContentFormat::Text,
+        )
+        .unwrap();
+        assert_eq!(
+            expanded,
+            "Hi Ursula & co (ursula_le_guin@gmail.com), subscribed on 2022-06-12."
+        );
+    }
+
+    #[test]
+    fn values_are_escaped_in_html_content() {
+        let expanded =
+            expand_merge_tags("<p>Hi {{ name }}</p>", &fields(), ContentFormat::Html).unwrap();
+        assert_eq!(expanded, "<p>Hi Ursula &amp; co</p>");
+    }
+
+    #[test]
+    fn merge_tags_can_be_stripped() {
+        assert_eq!(strip_merge_tags("Hi{{ name }}, welcome!"), "Hi, welcome!");
+        assert_eq!(strip_merge_tags("Hi {{ nickname }}"), "Hi {{ nickname }}");
+    }
+}
@@ -1,8 +1,18 @@
 mod subscriber_name;
 mod subscriber_email;
 mod new_subscriber;
+mod merge_tags;
+mod issue_slug;
+mod list_slug;
+mod frequency;
 
 
 pub use subscriber_name::SubscriberName;
 pub use new_subscriber::NewSubscriber;
-pub use subscriber_email::SubscriberEmail;
\ No newline at end of file
+pub use subscriber_email::SubscriberEmail;
+pub use merge_tags::{
+    expand_merge_tags, strip_merge_tags, validate_merge_tags, ContentFormat, MergeFields,
+};
+pub use issue_slug::IssueSlug;
+pub use list_slug::ListSlug;
+pub use frequency::Frequency;
\ No newline at end of file
@@ -1,7 +1,9 @@
-use crate::domain::{SubscriberEmail, SubscriberName};
+use crate::domain::{ListSlug, SubscriberEmail, SubscriberName};
 
 #[derive(Debug)]
 pub struct NewSubscriber {
     pub email: SubscriberEmail,
     pub name: SubscriberName,
+    /// The list they want to join.
+    pub list: ListSlug,
 }
\ No newline at end of file
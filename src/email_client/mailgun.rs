@@ -0,0 +1,139 @@
+use std::time::Duration;
+
+use secrecy::{ExposeSecret, Secret};
+
+use super::{EmailSender, HttpTransport, RetryPolicy, SendEmailError};
+use crate::domain::SubscriberEmail;
+
+/// Sends emails through a Mailgun-style `/messages` API: a form-encoded body,
+/// authenticated with HTTP Basic (`api` as username, the API key as password).
+///
+/// `base_url` is expected to include the sending domain,
+/// e.g. `https://api.mailgun.net/v3/mg.example.com`.
+pub struct MailgunClient {
+    transport: HttpTransport,
+    sender: SubscriberEmail,
+    base_url: String,
+    authorization_token: Secret<String>,
+}
+
+impl MailgunClient {
+    pub fn new(
+        base_url: String,
+        sender: SubscriberEmail,
+        authorization_token: Secret<String>,
+        timeout: Duration,
+        retry_policy: RetryPolicy,
+    ) -> Self {
+        Self {
+            transport: HttpTransport::new(timeout, retry_policy),
+            base_url,
+            sender,
+            authorization_token,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailSender for MailgunClient {
+    #[tracing::instrument(
+        name = "Send an email through Mailgun",
+        skip_all,
+        fields(recipient = %recipient)
+    )]
+    async fn send_email_with_headers(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[(&str, &str)],
+    ) -> Result<(), SendEmailError> {
+        let url = format!("{}/messages", self.base_url);
+        // Custom headers are passed as `h:`-prefixed fields
+        let header_fields: Vec<(String, &str)> = headers
+            .iter()
+            .map(|&(name, value)| (format!("h:{}", name), value))
+            .collect();
+        let mut form = vec![
+            ("from", self.sender.as_ref()),
+            ("to", recipient.as_ref()),
+            ("subject", subject),
+            ("text", text_content),
+            ("html", html_content),
+        ];
+        form.extend(header_fields.iter().map(|(name, value)| (name.as_str(), *value)));
+        self.transport
+            .send(|client| {
+                client
+                    .post(&url)
+                    .basic_auth("api", Some(self.authorization_token.expose_secret()))
+                    .form(&form)
+            })
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claim::assert_ok;
+    use fake::faker::internet::en::SafeEmail;
+    use fake::faker::lorem::en::{Paragraph, Sentence};
+    use fake::{Fake, Faker};
+    use wiremock::matchers::{body_string_contains, header, header_exists, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn send_email_sends_the_expected_request() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let base_url = format!("{}/v3/mg.example.com", mock_server.uri());
+        let email_client = MailgunClient::new(
+            base_url,
+            email(),
+            Secret::new(Faker.fake()),
+            Duration::from_millis(200),
+            RetryPolicy {
+                max_attempts: 1,
+                base_delay: Duration::from_millis(10),
+                max_delay: Duration::from_millis(100),
+                jitter: false,
+            },
+        );
+
+        Mock::given(header_exists("Authorization"))
+            .and(header("Content-Type", "application/x-www-form-urlencoded"))
+            .and(path("/v3/mg.example.com/messages"))
+            .and(method("POST"))
+            .and(body_string_contains("subject="))
+            .and(body_string_contains("html="))
+            .and(body_string_contains("text="))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        assert_ok!(outcome);
+    }
+
+    fn email() -> SubscriberEmail {
+        SubscriberEmail::parse(SafeEmail().fake()).unwrap()
+    }
+
+    fn content() -> String {
+        Paragraph(1..10).fake()
+    }
+
+    fn subject() -> String {
+        Sentence(1..2).fake()
+    }
+}
@@ -0,0 +1,223 @@
+use std::time::Duration;
+
+use rand::Rng;
+use reqwest::header::RETRY_AFTER;
+use reqwest::{Client, RequestBuilder, Response, StatusCode};
+
+use crate::domain::SubscriberEmail;
+
+pub use mailgun::MailgunClient;
+pub use postmark::PostmarkClient;
+pub use sendgrid::SendGridClient;
+pub use smtp::{SmtpAuthMechanism, SmtpClient, SmtpConnectionOptions, SmtpTls};
+
+mod mailgun;
+mod postmark;
+mod sendgrid;
+mod smtp;
+
+/// Anything able to deliver an email on our behalf.
+///
+/// Route handlers and the delivery worker only ever talk to this trait:
+/// which vendor sits behind it is decided by `EmailClientSettings::provider`.
+#[async_trait::async_trait]
+pub trait EmailSender: Send + Sync {
+    /// Send an email carrying extra headers, given as `(name, value)` pairs
+    /// (e.g. `List-Unsubscribe`).
+    async fn send_email_with_headers(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[(&str, &str)],
+    ) -> Result<(), SendEmailError>;
+
+    async fn send_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+    ) -> Result<(), SendEmailError> {
+        self.send_email_with_headers(recipient, subject, html_content, text_content, &[])
+            .await
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum SendEmailError {
+    #[error(transparent)]
+    Http(#[from] reqwest::Error),
+    #[error(transparent)]
+    Smtp(#[from] lettre::transport::smtp::Error),
+    #[error("Failed to build the email: {0}")]
+    InvalidMessage(String),
+    /// The recipient is on the suppression list, nothing was sent.
+    #[error("The recipient is on the suppression list.")]
+    Suppressed,
+    #[error("Failed to look up the suppression list.")]
+    SuppressionLookup(#[source] sqlx::Error),
+}
+
+/// How transient failures (timeouts, 429 and 5xx responses) are retried.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first one.
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    /// Upper bound on the delay between two attempts.
+    pub max_delay: Duration,
+    /// Randomise each delay between zero and its computed value ("full jitter"),
+    /// to avoid synchronised retries from several workers.
+    pub jitter: bool,
+}
+
+impl RetryPolicy {
+    /// Delay to wait before the attempt following `attempt` (1-based).
+    fn backoff(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(31);
+        let delay = self
+            .base_delay
+            .checked_mul(1 << exponent)
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay);
+        if self.jitter {
+            let millis = delay.as_millis() as u64;
+            Duration::from_millis(rand::thread_rng().gen_range(0..=millis))
+        } else {
+            delay
+        }
+    }
+}
+
+/// The HTTP plumbing shared by all API-based providers: a `reqwest` client
+/// with a timeout and the retry loop.
+struct HttpTransport {
+    http_client: Client,
+    retry_policy: RetryPolicy,
+}
+
+impl HttpTransport {
+    fn new(timeout: Duration, retry_policy: RetryPolicy) -> Self {
+        Self {
+            http_client: Client::builder().timeout(timeout).build().unwrap(),
+            retry_policy,
+        }
+    }
+
+    /// Send the request produced by `build_request`, retrying it according
+    /// to the retry policy. Each attempt is recorded as an event on the
+    /// current span.
+    async fn send<F>(&self, build_request: F) -> Result<(), reqwest::Error>
+    where
+        F: Fn(&Client) -> RequestBuilder,
+    {
+        let mut attempt = 1;
+        loop {
+            let outcome = build_request(&self.http_client).send().await;
+            let (error, retry_after) = match outcome {
+                Ok(response) => {
+                    let retry_after = retry_after(&response);
+                    match response.error_for_status() {
+                        Ok(_) => {
+                            tracing::info!(attempt, "The email provider accepted the email.");
+                            return Ok(());
+                        }
+                        Err(e) => (e, retry_after),
+                    }
+                }
+                Err(e) => (e, None),
+            };
+
+            if attempt >= self.retry_policy.max_attempts || !is_transient(&error) {
+                tracing::error!(
+                    attempt,
+                    error.message = %error,
+                    "Failed to send the email, giving up."
+                );
+                return Err(error);
+            }
+            let delay = retry_after.unwrap_or_else(|| self.retry_policy.backoff(attempt));
+            if delay > self.retry_policy.max_delay {
+                // The provider asked us to wait for longer than we are willing to:
+                // retrying earlier than that would be pointless.
+                tracing::error!(
+                    attempt,
+                    error.message = %error,
+                    retry_after_seconds = delay.as_secs(),
+                    "The email provider asked to retry too far in the future, giving up."
+                );
+                return Err(error);
+            }
+            tracing::warn!(
+                attempt,
+                error.message = %error,
+                retry_in_milliseconds = delay.as_millis() as u64,
+                "Failed to send the email, retrying."
+            );
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+}
+
+/// Timeouts, connection failures, rate limiting and server-side errors are
+/// worth retrying. Any other 4xx means the request itself is wrong.
+fn is_transient(error: &reqwest::Error) -> bool {
+    if error.is_timeout() || error.is_connect() {
+        return true;
+    }
+    match error.status() {
+        Some(status) => status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error(),
+        None => false,
+    }
+}
+
+/// Parse the `Retry-After` header, either as a number of seconds
+/// or as an HTTP date.
+fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    let delay = date.signed_duration_since(chrono::Utc::now());
+    Some(delay.to_std().unwrap_or(Duration::ZERO))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::RetryPolicy;
+
+    #[test]
+    fn backoff_grows_exponentially_up_to_the_maximum_delay() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(1000),
+            jitter: false,
+        };
+        assert_eq!(policy.backoff(1), Duration::from_millis(100));
+        assert_eq!(policy.backoff(2), Duration::from_millis(200));
+        assert_eq!(policy.backoff(4), Duration::from_millis(800));
+        assert_eq!(policy.backoff(5), Duration::from_millis(1000));
+        assert_eq!(policy.backoff(u32::MAX), Duration::from_millis(1000));
+    }
+
+    #[test]
+    fn jittered_backoff_never_exceeds_the_computed_delay() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(1000),
+            jitter: true,
+        };
+        for attempt in 1..10 {
+            assert!(policy.backoff(attempt) <= Duration::from_millis(1000));
+        }
+        assert!(policy.backoff(1) <= Duration::from_millis(100));
+    }
+}
@@ -0,0 +1,187 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use secrecy::{ExposeSecret, Secret};
+
+use super::{EmailSender, HttpTransport, RetryPolicy, SendEmailError};
+use crate::domain::SubscriberEmail;
+
+/// Sends emails through a SendGrid-style `/v3/mail/send` API,
+/// authenticated with a bearer token.
+pub struct SendGridClient {
+    transport: HttpTransport,
+    sender: SubscriberEmail,
+    base_url: String,
+    authorization_token: Secret<String>,
+}
+
+impl SendGridClient {
+    pub fn new(
+        base_url: String,
+        sender: SubscriberEmail,
+        authorization_token: Secret<String>,
+        timeout: Duration,
+        retry_policy: RetryPolicy,
+    ) -> Self {
+        Self {
+            transport: HttpTransport::new(timeout, retry_policy),
+            base_url,
+            sender,
+            authorization_token,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailSender for SendGridClient {
+    #[tracing::instrument(
+        name = "Send an email through SendGrid",
+        skip_all,
+        fields(recipient = %recipient)
+    )]
+    async fn send_email_with_headers(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[(&str, &str)],
+    ) -> Result<(), SendEmailError> {
+        let url = format!("{}/v3/mail/send", self.base_url);
+        let request_body = SendEmailRequest {
+            personalizations: [Personalization {
+                to: [Address {
+                    email: recipient.as_ref(),
+                }],
+            }],
+            from: Address {
+                email: self.sender.as_ref(),
+            },
+            subject,
+            // `text/plain` must come before `text/html`
+            content: [
+                Content {
+                    r#type: "text/plain",
+                    value: text_content,
+                },
+                Content {
+                    r#type: "text/html",
+                    value: html_content,
+                },
+            ],
+            headers: headers.iter().copied().collect(),
+        };
+        self.transport
+            .send(|client| {
+                client
+                    .post(&url)
+                    .bearer_auth(self.authorization_token.expose_secret())
+                    .json(&request_body)
+            })
+            .await?;
+        Ok(())
+    }
+}
+
+#[derive(serde::Serialize)]
+struct SendEmailRequest<'a> {
+    personalizations: [Personalization<'a>; 1],
+    from: Address<'a>,
+    subject: &'a str,
+    content: [Content<'a>; 2],
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    headers: BTreeMap<&'a str, &'a str>,
+}
+
+#[derive(serde::Serialize)]
+struct Personalization<'a> {
+    to: [Address<'a>; 1],
+}
+
+#[derive(serde::Serialize)]
+struct Address<'a> {
+    email: &'a str,
+}
+
+#[derive(serde::Serialize)]
+struct Content<'a> {
+    r#type: &'a str,
+    value: &'a str,
+}
+
+#[cfg(test)]
+mod tests {
+    use claim::assert_ok;
+    use fake::faker::internet::en::SafeEmail;
+    use fake::faker::lorem::en::{Paragraph, Sentence};
+    use fake::{Fake, Faker};
+    use wiremock::matchers::{header, header_exists, method, path};
+    use wiremock::{Mock, MockServer, Request, ResponseTemplate};
+
+    use super::*;
+
+    struct SendEmailBodyMatcher;
+
+    impl wiremock::Match for SendEmailBodyMatcher {
+        fn matches(&self, request: &Request) -> bool {
+            let result: Result<serde_json::Value, _> = serde_json::from_slice(&request.body);
+            if let Ok(body) = result {
+                body["personalizations"][0]["to"][0]["email"].is_string()
+                    && body["from"]["email"].is_string()
+                    && body["subject"].is_string()
+                    && body["content"][0]["type"] == "text/plain"
+                    && body["content"][1]["type"] == "text/html"
+            } else {
+                false
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn send_email_sends_the_expected_request() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = SendGridClient::new(
+            mock_server.uri(),
+            email(),
+            Secret::new(Faker.fake()),
+            Duration::from_millis(200),
+            RetryPolicy {
+                max_attempts: 1,
+                base_delay: Duration::from_millis(10),
+                max_delay: Duration::from_millis(100),
+                jitter: false,
+            },
+        );
+
+        Mock::given(header_exists("Authorization"))
+            .and(header("Content-Type", "application/json"))
+            .and(path("/v3/mail/send"))
+            .and(method("POST"))
+            .and(SendEmailBodyMatcher)
+            .respond_with(ResponseTemplate::new(202))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        assert_ok!(outcome);
+    }
+
+    fn email() -> SubscriberEmail {
+        SubscriberEmail::parse(SafeEmail().fake()).unwrap()
+    }
+
+    fn content() -> String {
+        Paragraph(1..10).fake()
+    }
+
+    fn subject() -> String {
+        Sentence(1..2).fake()
+    }
+}
@@ -0,0 +1,336 @@
+use std::time::Duration;
+
+use lettre::message::header::{HeaderName, HeaderValue};
+use lettre::message::{Mailbox, MultiPart};
+use lettre::transport::smtp::authentication::{Credentials, Mechanism};
+use lettre::transport::smtp::client::{Tls, TlsParameters};
+use lettre::transport::smtp::PoolConfig;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use secrecy::{ExposeSecret, Secret};
+
+use super::{EmailSender, RetryPolicy, SendEmailError};
+use crate::domain::SubscriberEmail;
+
+/// How the connection to the SMTP relay is secured.
+#[derive(serde::Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpTls {
+    /// Plain text, only meant for local relays and tests.
+    None,
+    /// Upgrade a plain text connection with `STARTTLS` (usually port 587).
+    StartTls,
+    /// TLS from the first byte (usually port 465).
+    Implicit,
+}
+
+#[derive(serde::Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpAuthMechanism {
+    Plain,
+    Login,
+}
+
+impl From<SmtpAuthMechanism> for Mechanism {
+    fn from(mechanism: SmtpAuthMechanism) -> Self {
+        match mechanism {
+            SmtpAuthMechanism::Plain => Mechanism::Plain,
+            SmtpAuthMechanism::Login => Mechanism::Login,
+        }
+    }
+}
+
+pub struct SmtpConnectionOptions {
+    pub host: String,
+    pub port: u16,
+    pub tls: SmtpTls,
+    /// Authentication is skipped when `None`.
+    pub credentials: Option<(String, Secret<String>)>,
+    pub mechanisms: Vec<SmtpAuthMechanism>,
+    pub pool_max_size: u32,
+}
+
+/// Sends emails through an SMTP relay, keeping a pool of open connections.
+pub struct SmtpClient {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    sender: SubscriberEmail,
+    retry_policy: RetryPolicy,
+}
+
+impl SmtpClient {
+    pub fn new(
+        options: SmtpConnectionOptions,
+        sender: SubscriberEmail,
+        timeout: Duration,
+        retry_policy: RetryPolicy,
+    ) -> Result<Self, anyhow::Error> {
+        let tls = match options.tls {
+            SmtpTls::None => Tls::None,
+            SmtpTls::StartTls => Tls::Required(TlsParameters::new(options.host.clone())?),
+            SmtpTls::Implicit => Tls::Wrapper(TlsParameters::new(options.host.clone())?),
+        };
+        let mut builder = AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&options.host)
+            .port(options.port)
+            .tls(tls)
+            .timeout(Some(timeout))
+            .pool_config(PoolConfig::new().max_size(options.pool_max_size));
+        if let Some((username, password)) = options.credentials {
+            builder = builder
+                .credentials(Credentials::new(
+                    username,
+                    password.expose_secret().to_owned(),
+                ))
+                .authentication(options.mechanisms.into_iter().map(Into::into).collect());
+        }
+        Ok(Self {
+            transport: builder.build(),
+            sender,
+            retry_policy,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailSender for SmtpClient {
+    #[tracing::instrument(
+        name = "Send an email through SMTP",
+        skip_all,
+        fields(recipient = %recipient)
+    )]
+    async fn send_email_with_headers(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[(&str, &str)],
+    ) -> Result<(), SendEmailError> {
+        let mut message = Message::builder()
+            .from(mailbox(&self.sender)?)
+            .to(mailbox(recipient)?)
+            .subject(subject)
+            .multipart(MultiPart::alternative_plain_html(
+                text_content.to_owned(),
+                html_content.to_owned(),
+            ))
+            .map_err(|e| SendEmailError::InvalidMessage(e.to_string()))?;
+        for &(name, value) in headers {
+            let name = HeaderName::new_from_ascii(name.to_owned())
+                .map_err(|e| SendEmailError::InvalidMessage(e.to_string()))?;
+            message
+                .headers_mut()
+                .insert_raw(HeaderValue::new(name, value.to_owned()));
+        }
+
+        let mut attempt = 1;
+        loop {
+            let error = match self.transport.send(message.clone()).await {
+                Ok(_) => {
+                    tracing::info!(attempt, "The SMTP relay accepted the email.");
+                    return Ok(());
+                }
+                Err(e) => e,
+            };
+            let is_transient = error.is_transient() || error.is_timeout();
+            if attempt >= self.retry_policy.max_attempts || !is_transient {
+                tracing::error!(
+                    attempt,
+                    error.message = %error,
+                    "Failed to send the email, giving up."
+                );
+                return Err(error.into());
+            }
+            let delay = self.retry_policy.backoff(attempt);
+            tracing::warn!(
+                attempt,
+                error.message = %error,
+                retry_in_milliseconds = delay.as_millis() as u64,
+                "Failed to send the email, retrying."
+            );
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+}
+
+fn mailbox(email: &SubscriberEmail) -> Result<Mailbox, SendEmailError> {
+    email
+        .as_ref()
+        .parse()
+        .map_err(|e: lettre::address::AddressError| SendEmailError::InvalidMessage(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use claim::assert_ok;
+    use fake::faker::internet::en::SafeEmail;
+    use fake::faker::lorem::en::Sentence;
+    use fake::Fake;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    /// What the stand-in SMTP server observed.
+    #[derive(Default)]
+    struct Received {
+        connections: usize,
+        auth_commands: Vec<String>,
+        messages: Vec<String>,
+    }
+
+    /// A minimal in-process SMTP server: it accepts every command
+    /// and records what it was sent.
+    async fn spawn_smtp_server() -> (u16, Arc<Mutex<Received>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let received = Arc::new(Mutex::new(Received::default()));
+        let state = received.clone();
+        tokio::spawn(async move {
+            loop {
+                let (socket, _) = listener.accept().await.unwrap();
+                state.lock().unwrap().connections += 1;
+                let state = state.clone();
+                tokio::spawn(async move {
+                    let (reader, mut writer) = socket.into_split();
+                    let mut lines = BufReader::new(reader).lines();
+                    writer.write_all(b"220 localhost ESMTP\r\n").await.unwrap();
+                    while let Ok(Some(line)) = lines.next_line().await {
+                        let command = line.to_uppercase();
+                        let reply: &[u8] = if command.starts_with("EHLO") {
+                            b"250-localhost\r\n250 AUTH PLAIN LOGIN\r\n"
+                        } else if command.starts_with("AUTH") {
+                            state.lock().unwrap().auth_commands.push(line.clone());
+                            b"235 2.7.0 Authentication successful\r\n"
+                        } else if command.starts_with("DATA") {
+                            writer.write_all(b"354 End data with <CR><LF>.<CR><LF>\r\n").await.unwrap();
+                            let mut message = String::new();
+                            while let Ok(Some(line)) = lines.next_line().await {
+                                if line == "." {
+                                    break;
+                                }
+                                message.push_str(&line);
+                                message.push('\n');
+                            }
+                            state.lock().unwrap().messages.push(message);
+                            b"250 2.0.0 Ok: queued\r\n"
+                        } else if command.starts_with("QUIT") {
+                            writer.write_all(b"221 Bye\r\n").await.unwrap();
+                            break;
+                        } else {
+                            b"250 Ok\r\n"
+                        };
+                        writer.write_all(reply).await.unwrap();
+                    }
+                });
+            }
+        });
+        (port, received)
+    }
+
+    fn smtp_client(port: u16, credentials: Option<(String, Secret<String>)>) -> SmtpClient {
+        SmtpClient::new(
+            SmtpConnectionOptions {
+                host: "127.0.0.1".into(),
+                port,
+                tls: SmtpTls::None,
+                credentials,
+                mechanisms: vec![SmtpAuthMechanism::Plain, SmtpAuthMechanism::Login],
+                pool_max_size: 2,
+            },
+            email(),
+            Duration::from_secs(2),
+            RetryPolicy {
+                max_attempts: 1,
+                base_delay: Duration::from_millis(10),
+                max_delay: Duration::from_millis(100),
+                jitter: false,
+            },
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn send_email_delivers_a_multipart_alternative_message() {
+        // Arrange
+        let (port, received) = spawn_smtp_server().await;
+        let client = smtp_client(port, None);
+
+        // Act
+        let outcome = client
+            .send_email(
+                &email(),
+                &subject(),
+                "<p>Hello from the html body</p>",
+                "Hello from the text body",
+            )
+            .await;
+
+        // Assert
+        assert_ok!(outcome);
+        let received = received.lock().unwrap();
+        assert_eq!(received.messages.len(), 1);
+        let message = &received.messages[0];
+        assert!(message.contains("multipart/alternative"));
+        assert!(message.contains("text/plain"));
+        assert!(message.contains("Hello from the text body"));
+        assert!(message.contains("text/html"));
+        assert!(message.contains("<p>Hello from the html body</p>"));
+    }
+
+    #[tokio::test]
+    async fn send_email_authenticates_when_credentials_are_configured() {
+        // Arrange
+        let (port, received) = spawn_smtp_server().await;
+        let client = smtp_client(
+            port,
+            Some(("username".into(), Secret::new("password".into()))),
+        );
+
+        // Act
+        let outcome = client
+            .send_email(&email(), &subject(), "<p>Hi</p>", "Hi")
+            .await;
+
+        // Assert
+        assert_ok!(outcome);
+        let received = received.lock().unwrap();
+        assert!(!received.auth_commands.is_empty());
+        assert!(received
+            .auth_commands
+            .iter()
+            .all(|command| command.starts_with("AUTH PLAIN")));
+    }
+
+    #[tokio::test]
+    async fn connections_are_reused_across_emails() {
+        // Arrange
+        let (port, received) = spawn_smtp_server().await;
+        let client = smtp_client(port, None);
+
+        // Act
+        for _ in 0..5 {
+            assert_ok!(
+                client
+                    .send_email(&email(), &subject(), "<p>Hi</p>", "Hi")
+                    .await
+            );
+        }
+
+        // Assert
+        let received = received.lock().unwrap();
+        assert_eq!(received.messages.len(), 5);
+        // The pool may open an idle connection ahead of time,
+        // but it must not open one per email.
+        assert!(received.connections <= 2);
+    }
+
+    fn email() -> SubscriberEmail {
+        SubscriberEmail::parse(SafeEmail().fake()).unwrap()
+    }
+
+    fn subject() -> String {
+        Sentence(1..2).fake()
+    }
+}
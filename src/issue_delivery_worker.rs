@@ -0,0 +1,503 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{PgPool, Postgres, Transaction};
+use tracing::{field::display, Span};
+use uuid::Uuid;
+
+use crate::configuration::Settings;
+use crate::delivery_log::{log_attempt, DeliveryStatus};
+use crate::domain::{expand_merge_tags, ContentFormat, Frequency, MergeFields, SubscriberEmail};
+use crate::email_client::{EmailSender, SendEmailError};
+use crate::startup::get_connection_pool;
+use crate::templates::{RenderedEmail, Templates};
+use crate::tracking::{inject_open_pixel, rewrite_links, Delivery, Tracking};
+
+pub enum ExecutionOutcome {
+    TaskCompleted,
+    EmptyQueue,
+}
+
+/// Pick one pending delivery from the queue, send it and remove it.
+///
+/// The task row stays locked (`FOR UPDATE SKIP LOCKED`) until it is deleted,
+/// so several workers - possibly in different replicas - can drain the
+/// queue concurrently without ever picking the same task.
+///
+/// Subscribers who unsubscribed after the issue was enqueued are skipped.
+/// Deliveries to those who were emailed too recently for their `Frequency`
+/// stay in the queue until they can be emailed again, and only the newest of
+/// the issues queued for them in the meantime is sent: the others are skipped.
+/// Every email carries `List-Unsubscribe` headers pointing to `base_url`:
+/// for an issue sent to a single list, they only unsubscribe from that list.
+/// Unless disabled for the issue, its html body loads an open tracking pixel
+/// and its links go through click tracking redirects. What happened to the
+/// recipient is written to the delivery log.
+#[tracing::instrument(
+    skip_all,
+    fields(
+        newsletter_issue_id = tracing::field::Empty,
+        subscriber_email = tracing::field::Empty
+    ),
+    err
+)]
+pub async fn try_execute_task(
+    pool: &PgPool,
+    email_client: &dyn EmailSender,
+    templates: &Templates,
+    base_url: &str,
+    tracking: &Tracking,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let task = dequeue_task(pool).await?;
+    if task.is_none() {
+        return Ok(ExecutionOutcome::EmptyQueue);
+    }
+    let (mut transaction, issue_id, email) = task.unwrap();
+    Span::current()
+        .record("newsletter_issue_id", &display(issue_id))
+        .record("subscriber_email", &display(&email));
+    let recipient = get_recipient(&mut transaction, &email, issue_id).await?;
+    let superseded = match &recipient {
+        Some(recipient) if recipient.frequency != Frequency::EveryIssue.as_str() => {
+            if newer_task_queued(&mut transaction, issue_id, &email).await? {
+                true
+            } else {
+                skip_older_tasks(&mut transaction, issue_id, &email).await?;
+                false
+            }
+        }
+        _ => false,
+    };
+    let status = match recipient {
+        None => {
+            tracing::info!("Skipping a subscriber who is no longer confirmed.");
+            DeliveryStatus::Skipped
+        }
+        Some(_) if superseded => {
+            tracing::info!("Skipping an issue superseded by a newer one for this subscriber.");
+            DeliveryStatus::Skipped
+        }
+        Some(Recipient {
+            next_email_at: Some(next_email_at),
+            ..
+        }) if next_email_at > Utc::now() => {
+            tracing::info!(
+                %next_email_at,
+                "Postponing the delivery to a subscriber who was emailed too recently."
+            );
+            postpone_task(transaction, issue_id, &email, next_email_at).await?;
+            return Ok(ExecutionOutcome::TaskCompleted);
+        }
+        Some(recipient) => match SubscriberEmail::parse(email.clone()) {
+            Ok(email) => {
+                let mut issue = get_issue(pool, issue_id).await?;
+                let delivery = Delivery {
+                    newsletter_issue_id: issue_id,
+                    subscriber_id: recipient.id,
+                };
+                // Before expanding merge tags, so that only links found in the issue are tracked
+                if issue.track_clicks {
+                    let track = |url: &str| tracking.click_url(base_url, &delivery, url);
+                    issue.html_content =
+                        rewrite_links(&issue.html_content, ContentFormat::Html, track);
+                    issue.text_content =
+                        rewrite_links(&issue.text_content, ContentFormat::Text, track);
+                }
+                let mut unsubscribe_link = format!(
+                    "{}/subscriptions/unsubscribe?token={}",
+                    base_url, recipient.unsubscribe_token
+                );
+                if let [list] = &issue.lists[..] {
+                    unsubscribe_link.push_str(&format!("&list={}", list));
+                }
+                let merge_fields = MergeFields {
+                    name: &recipient.name,
+                    email: email.as_ref(),
+                    subscribed_at: recipient.subscribed_at,
+                };
+                let mut rendered = render_issue(
+                    templates,
+                    &issue,
+                    &merge_fields,
+                    base_url,
+                    &unsubscribe_link,
+                )?;
+                if issue.track_opens {
+                    rendered.html = inject_open_pixel(
+                        &rendered.html,
+                        &tracking.open_pixel_url(base_url, &delivery),
+                    );
+                }
+                let list_unsubscribe = format!("<{}>", unsubscribe_link);
+                let headers = [
+                    ("List-Unsubscribe", list_unsubscribe.as_str()),
+                    ("List-Unsubscribe-Post", "List-Unsubscribe=One-Click"),
+                ];
+                match email_client
+                    .send_email_with_headers(
+                        &email,
+                        &rendered.subject,
+                        &rendered.html,
+                        &rendered.text,
+                        &headers,
+                    )
+                    .await
+                {
+                    Ok(()) => {
+                        record_last_emailed_at(&mut transaction, &email).await?;
+                        DeliveryStatus::Delivered
+                    }
+                    Err(SendEmailError::Suppressed) => {
+                        tracing::info!("Skipping a confirmed subscriber on the suppression list.");
+                        DeliveryStatus::Skipped
+                    }
+                    Err(e) => {
+                        tracing::error!(
+                            error.cause_chain = ?e,
+                            error.message = %e,
+                            "Failed to deliver issue to a confirmed subscriber. \
+                            Skipping.",
+                        );
+                        DeliveryStatus::Failed
+                    }
+                }
+            }
+            Err(e) => {
+                tracing::error!(
+                    error.message = %e,
+                    "Skipping a confirmed subscriber. \
+                    Their stored contact details are invalid",
+                );
+                DeliveryStatus::Failed
+            }
+        },
+    };
+    log_attempt(&mut transaction, issue_id, &email, status).await?;
+    delete_task(transaction, issue_id, &email).await?;
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+type PgTransaction = Transaction<'static, Postgres>;
+
+#[tracing::instrument(skip_all)]
+async fn dequeue_task(
+    pool: &PgPool,
+) -> Result<Option<(PgTransaction, Uuid, String)>, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let r = sqlx::query!(
+        r#"
+        SELECT newsletter_issue_id, subscriber_email
+        FROM issue_delivery_queue
+        WHERE execute_after <= now()
+        ORDER BY execute_after, newsletter_issue_id
+        FOR UPDATE
+        SKIP LOCKED
+        LIMIT 1
+        "#,
+    )
+    .fetch_optional(&mut transaction)
+    .await?;
+    if let Some(r) = r {
+        Ok(Some((
+            transaction,
+            r.newsletter_issue_id,
+            r.subscriber_email,
+        )))
+    } else {
+        Ok(None)
+    }
+}
+
+#[tracing::instrument(skip_all)]
+async fn delete_task(
+    mut transaction: PgTransaction,
+    issue_id: Uuid,
+    email: &str,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        DELETE FROM issue_delivery_queue
+        WHERE
+            newsletter_issue_id = $1 AND
+            subscriber_email = $2
+        "#,
+        issue_id,
+        email
+    )
+    .execute(&mut transaction)
+    .await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+/// Leave the task in the queue, to be picked up again at `execute_after`.
+#[tracing::instrument(skip_all)]
+async fn postpone_task(
+    mut transaction: PgTransaction,
+    issue_id: Uuid,
+    email: &str,
+    execute_after: DateTime<Utc>,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE issue_delivery_queue
+        SET execute_after = $3
+        WHERE
+            newsletter_issue_id = $1 AND
+            subscriber_email = $2
+        "#,
+        issue_id,
+        email,
+        execute_after
+    )
+    .execute(&mut transaction)
+    .await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+/// Whether an issue published after this one is queued for the same subscriber.
+#[tracing::instrument(skip_all)]
+async fn newer_task_queued(
+    transaction: &mut PgTransaction,
+    issue_id: Uuid,
+    email: &str,
+) -> Result<bool, anyhow::Error> {
+    let r = sqlx::query!(
+        r#"
+        SELECT EXISTS (
+            SELECT 1
+            FROM issue_delivery_queue q
+            JOIN newsletter_issues newer ON newer.newsletter_issue_id = q.newsletter_issue_id
+            JOIN newsletter_issues this ON this.newsletter_issue_id = $1
+            WHERE
+                q.subscriber_email = $2 AND
+                (newer.published_at, newer.newsletter_issue_id) >
+                    (this.published_at, this.newsletter_issue_id)
+        ) AS "exists!"
+        "#,
+        issue_id,
+        email
+    )
+    .fetch_one(transaction)
+    .await?;
+    Ok(r.exists)
+}
+
+/// Remove the tasks of the issues published before this one for the same
+/// subscriber, logging them as skipped. Committed along with the task.
+#[tracing::instrument(skip_all)]
+async fn skip_older_tasks(
+    transaction: &mut PgTransaction,
+    issue_id: Uuid,
+    email: &str,
+) -> Result<(), anyhow::Error> {
+    let skipped = sqlx::query!(
+        r#"
+        DELETE FROM issue_delivery_queue q
+        USING newsletter_issues older, newsletter_issues this
+        WHERE
+            q.subscriber_email = $2 AND
+            older.newsletter_issue_id = q.newsletter_issue_id AND
+            this.newsletter_issue_id = $1 AND
+            (older.published_at, older.newsletter_issue_id) <
+                (this.published_at, this.newsletter_issue_id)
+        RETURNING q.newsletter_issue_id
+        "#,
+        issue_id,
+        email
+    )
+    .fetch_all(&mut *transaction)
+    .await?;
+    for task in skipped {
+        log_attempt(
+            transaction,
+            task.newsletter_issue_id,
+            email,
+            DeliveryStatus::Skipped,
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+/// Committed along with the removal of the task.
+#[tracing::instrument(skip_all)]
+async fn record_last_emailed_at(
+    transaction: &mut PgTransaction,
+    email: &SubscriberEmail,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"UPDATE subscriptions SET last_emailed_at = now() WHERE email = $1"#,
+        email.as_ref()
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+struct Recipient {
+    id: Uuid,
+    name: String,
+    subscribed_at: DateTime<Utc>,
+    unsubscribe_token: String,
+    frequency: String,
+    /// When their `Frequency` lets us email them again, `None` if right away.
+    next_email_at: Option<DateTime<Utc>>,
+}
+
+/// The details of `email`, if they are still a confirmed subscriber of one
+/// of the issue's lists.
+#[tracing::instrument(skip_all)]
+async fn get_recipient(
+    transaction: &mut PgTransaction,
+    email: &str,
+    issue_id: Uuid,
+) -> Result<Option<Recipient>, anyhow::Error> {
+    let recipient = sqlx::query_as!(
+        Recipient,
+        r#"
+        SELECT
+            s.id,
+            s.name,
+            s.subscribed_at,
+            s.unsubscribe_token,
+            s.frequency,
+            s.last_emailed_at + CASE s.frequency
+                WHEN 'daily' THEN interval '1 day'
+                WHEN 'weekly' THEN interval '7 days'
+                ELSE interval '0'
+            END AS next_email_at
+        FROM subscriptions s
+        WHERE s.email = $1 AND s.status = 'confirmed' AND EXISTS (
+            SELECT 1
+            FROM list_memberships m
+            JOIN newsletter_issue_lists l ON l.list_id = m.list_id
+            WHERE
+                m.subscriber_id = s.id AND
+                m.status = 'confirmed' AND
+                l.newsletter_issue_id = $2
+        )
+        "#,
+        email,
+        issue_id
+    )
+    .fetch_optional(transaction)
+    .await?;
+    Ok(recipient)
+}
+
+/// Expand the merge tags of the issue for this recipient, then wrap it
+/// in the `newsletter` email templates.
+fn render_issue(
+    templates: &Templates,
+    issue: &NewsletterIssue,
+    merge_fields: &MergeFields,
+    base_url: &str,
+    unsubscribe_link: &str,
+) -> Result<RenderedEmail, anyhow::Error> {
+    // Content is validated when the issue is published, this only fails for
+    // issues published before merge tags existed: send those as they are.
+    let expand = |content: &str, format| {
+        expand_merge_tags(content, merge_fields, format).unwrap_or_else(|e| {
+            tracing::warn!(error.message = %e, "Failed to expand merge tags.");
+            content.to_owned()
+        })
+    };
+    let html_content = expand(&issue.html_content, ContentFormat::Html);
+    let text_content = expand(&issue.text_content, ContentFormat::Text);
+    let mut context = tera::Context::new();
+    context.insert("base_url", base_url);
+    context.insert("title", &issue.title);
+    context.insert("html_content", &html_content);
+    context.insert("text_content", &text_content);
+    context.insert("unsubscribe_link", unsubscribe_link);
+    templates
+        .render_email("newsletter", &context)
+        .context("Failed to render the newsletter issue.")
+}
+
+struct NewsletterIssue {
+    title: String,
+    text_content: String,
+    html_content: String,
+    /// The slugs of the lists the issue is sent to.
+    lists: Vec<String>,
+    track_opens: bool,
+    track_clicks: bool,
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_issue(pool: &PgPool, issue_id: Uuid) -> Result<NewsletterIssue, anyhow::Error> {
+    let issue = sqlx::query_as!(
+        NewsletterIssue,
+        r#"
+        SELECT
+            i.title,
+            i.text_content,
+            i.html_content,
+            ARRAY(
+                SELECT l.slug
+                FROM newsletter_issue_lists il
+                JOIN lists l ON l.list_id = il.list_id
+                WHERE il.newsletter_issue_id = i.newsletter_issue_id
+            ) AS "lists!",
+            i.track_opens,
+            i.track_clicks
+        FROM newsletter_issues i
+        WHERE
+            i.newsletter_issue_id = $1
+        "#,
+        issue_id
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(issue)
+}
+
+async fn worker_loop(
+    pool: PgPool,
+    email_client: Arc<dyn EmailSender>,
+    templates: Templates,
+    base_url: String,
+    tracking: Tracking,
+) -> Result<(), anyhow::Error> {
+    loop {
+        match try_execute_task(
+            &pool,
+            email_client.as_ref(),
+            &templates,
+            &base_url,
+            &tracking,
+        )
+        .await
+        {
+            Ok(ExecutionOutcome::EmptyQueue) => {
+                tokio::time::sleep(Duration::from_secs(10)).await;
+            }
+            Err(_) => {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+            Ok(ExecutionOutcome::TaskCompleted) => {}
+        }
+    }
+}
+
+pub async fn run_worker_until_stopped(configuration: Settings) -> Result<(), anyhow::Error> {
+    let connection_pool = get_connection_pool(&configuration.database);
+    let email_client = configuration.email_client.client(&connection_pool);
+    let templates = configuration.templates.load()?;
+    let tracking = configuration
+        .tracking
+        .tracking(configuration.application.hmac_secret);
+    worker_loop(
+        connection_pool,
+        email_client,
+        templates,
+        configuration.application.base_url,
+        tracking,
+    )
+    .await
+}
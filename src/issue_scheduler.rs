@@ -0,0 +1,71 @@
+use std::time::Duration;
+
+use sqlx::PgPool;
+use tracing::{field::display, Span};
+use uuid::Uuid;
+
+use crate::configuration::Settings;
+use crate::issue_delivery_worker::ExecutionOutcome;
+use crate::routes::enqueue_issue_for_confirmed_subscribers;
+use crate::startup::get_connection_pool;
+
+/// Pick one scheduled issue whose time has come and queue its delivery.
+///
+/// The issue row stays locked (`FOR UPDATE SKIP LOCKED`) until it is marked
+/// as published, in the same transaction as its delivery tasks are enqueued:
+/// with several replicas running, each issue is still dispatched exactly once.
+#[tracing::instrument(skip_all, fields(newsletter_issue_id = tracing::field::Empty), err)]
+pub async fn try_dispatch_due_issue(pool: &PgPool) -> Result<ExecutionOutcome, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let r = sqlx::query!(
+        r#"
+        SELECT newsletter_issue_id
+        FROM newsletter_issues
+        WHERE status = 'scheduled' AND send_at <= now()
+        ORDER BY send_at
+        FOR UPDATE
+        SKIP LOCKED
+        LIMIT 1
+        "#,
+    )
+    .fetch_optional(&mut transaction)
+    .await?;
+    let issue_id: Uuid = match r {
+        Some(r) => r.newsletter_issue_id,
+        None => return Ok(ExecutionOutcome::EmptyQueue),
+    };
+    Span::current().record("newsletter_issue_id", &display(issue_id));
+    // Recipients are whoever is confirmed at send time, not at scheduling time.
+    enqueue_issue_for_confirmed_subscribers(&mut transaction, issue_id).await?;
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET status = 'published', published_at = now()
+        WHERE newsletter_issue_id = $1
+        "#,
+        issue_id
+    )
+    .execute(&mut transaction)
+    .await?;
+    transaction.commit().await?;
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+async fn scheduler_loop(pool: PgPool) -> Result<(), anyhow::Error> {
+    loop {
+        match try_dispatch_due_issue(&pool).await {
+            Ok(ExecutionOutcome::EmptyQueue) => {
+                tokio::time::sleep(Duration::from_secs(10)).await;
+            }
+            Err(_) => {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+            Ok(ExecutionOutcome::TaskCompleted) => {}
+        }
+    }
+}
+
+pub async fn run_scheduler_until_stopped(configuration: Settings) -> Result<(), anyhow::Error> {
+    let connection_pool = get_connection_pool(&configuration.database);
+    scheduler_loop(connection_pool).await
+}
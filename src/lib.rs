@@ -1,7 +1,19 @@
 #![allow(clippy::toplevel_ref_arg)]
+#![allow(clippy::async_yields_async)]
+pub mod authentication;
 pub mod configuration;
 pub mod routes;
 pub mod startup;
 pub mod telemetry;
 pub mod domain;
-pub mod email_client;
\ No newline at end of file
+pub mod idempotency;
+pub mod issue_delivery_worker;
+pub mod issue_scheduler;
+pub mod email_client;
+pub mod templates;
+pub mod signed_token;
+pub mod suppressions;
+pub mod rate_limit;
+pub mod bot_protection;
+pub mod tracking;
+pub mod delivery_log;
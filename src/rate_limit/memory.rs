@@ -0,0 +1,161 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use chrono::{DateTime, Utc};
+
+use super::{BucketLimit, BucketState, Decision, RateLimitStore};
+
+/// The store never holds more buckets than this.
+const MAX_BUCKETS: usize = 100_000;
+
+/// Buckets kept in this process: each replica enforces its own limits.
+pub struct InMemoryStore {
+    buckets: Mutex<HashMap<String, Bucket>>,
+    max_buckets: usize,
+}
+
+struct Bucket {
+    state: BucketState,
+    full_at: DateTime<Utc>,
+}
+
+impl Default for InMemoryStore {
+    fn default() -> Self {
+        Self::with_max_buckets(MAX_BUCKETS)
+    }
+}
+
+impl InMemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn with_max_buckets(max_buckets: usize) -> Self {
+        Self {
+            buckets: Mutex::new(HashMap::new()),
+            max_buckets,
+        }
+    }
+}
+
+/// Make room for a new bucket once `max_buckets` is reached.
+///
+/// A full bucket behaves exactly like a missing one, so those go first. If
+/// that is not enough, the buckets closest to being full are evicted: their
+/// keys get their tokens back early. Either way a tenth of the store is
+/// freed, so that the next insertions do not scan it again.
+fn make_room(buckets: &mut HashMap<String, Bucket>, max_buckets: usize, now: DateTime<Utc>) {
+    buckets.retain(|_, bucket| bucket.full_at > now);
+    let target = max_buckets - (max_buckets / 10).max(1);
+    if buckets.len() <= target {
+        return;
+    }
+    let mut by_full_at: Vec<_> = buckets
+        .iter()
+        .map(|(key, bucket)| (bucket.full_at, key.clone()))
+        .collect();
+    let n_evicted = buckets.len() - target;
+    by_full_at.select_nth_unstable(n_evicted - 1);
+    for (_, key) in &by_full_at[..n_evicted] {
+        buckets.remove(key);
+    }
+}
+
+#[async_trait::async_trait]
+impl RateLimitStore for InMemoryStore {
+    async fn take(
+        &self,
+        key: &str,
+        limit: &BucketLimit,
+        now: DateTime<Utc>,
+    ) -> Result<Decision, anyhow::Error> {
+        let mut buckets = self
+            .buckets
+            .lock()
+            .map_err(|_| anyhow::anyhow!("The rate limit buckets are poisoned."))?;
+        if buckets.len() >= self.max_buckets && !buckets.contains_key(key) {
+            make_room(&mut buckets, self.max_buckets, now);
+        }
+        let state = buckets
+            .get(key)
+            .map(|bucket| bucket.state)
+            .unwrap_or_else(|| limit.full(now));
+        let (state, decision) = limit.take(state, now);
+        let full_at = limit.full_at(&state);
+        buckets.insert(key.to_owned(), Bucket { state, full_at });
+        Ok(decision)
+    }
+
+    async fn give_back(&self, key: &str, limit: &BucketLimit) -> Result<(), anyhow::Error> {
+        let mut buckets = self
+            .buckets
+            .lock()
+            .map_err(|_| anyhow::anyhow!("The rate limit buckets are poisoned."))?;
+        // An evicted bucket is already as good as full
+        if let Some(bucket) = buckets.get_mut(key) {
+            bucket.state = limit.give_back(bucket.state);
+            bucket.full_at = limit.full_at(&bucket.state);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, TimeZone, Utc};
+
+    use super::InMemoryStore;
+    use crate::rate_limit::{BucketLimit, RateLimitStore};
+
+    fn limit() -> BucketLimit {
+        BucketLimit {
+            capacity: 2,
+            refill_interval: Duration::seconds(60),
+        }
+    }
+
+    #[tokio::test]
+    async fn full_buckets_are_dropped_first_to_make_room() {
+        let store = InMemoryStore::with_max_buckets(10);
+        let now = Utc.ymd(2022, 7, 8).and_hms(9, 0, 0);
+        store.take("early", &limit(), now).await.unwrap();
+        for i in 0..9 {
+            let key = format!("late-{}", i);
+            store
+                .take(&key, &limit(), now + Duration::seconds(30))
+                .await
+                .unwrap();
+        }
+
+        store
+            .take("new", &limit(), now + Duration::seconds(60))
+            .await
+            .unwrap();
+
+        let buckets = store.buckets.lock().unwrap();
+        assert_eq!(buckets.len(), 10);
+        assert!(!buckets.contains_key("early"));
+        assert!(buckets.contains_key("new"));
+    }
+
+    #[tokio::test]
+    async fn the_store_never_grows_past_its_maximum() {
+        let store = InMemoryStore::with_max_buckets(10);
+        let now = Utc.ymd(2022, 7, 8).and_hms(9, 0, 0);
+
+        // None of these buckets fill up again during the test
+        for i in 0..25 {
+            let key = format!("key-{}", i);
+            store
+                .take(&key, &limit(), now + Duration::seconds(i))
+                .await
+                .unwrap();
+            assert!(store.buckets.lock().unwrap().len() <= 10);
+        }
+
+        let buckets = store.buckets.lock().unwrap();
+        assert!(buckets.contains_key("key-24"));
+        // The buckets closest to being full are the ones evicted
+        assert!(!buckets.contains_key("key-0"));
+    }
+}
@@ -0,0 +1,215 @@
+use std::future::{ready, Future, Ready};
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::RETRY_AFTER;
+use actix_web::http::StatusCode;
+use actix_web::{web, FromRequest, HttpResponse, ResponseError};
+use chrono::{DateTime, Utc};
+
+use super::{BucketLimit, Decision, RateLimitStore};
+use crate::domain::SubscriberEmail;
+use crate::routes::error_chain_fmt;
+
+/// Middleware taking a token out of the client IP's bucket, then out of the
+/// bucket of the `email` field of the form, before letting a request through.
+///
+/// Each route it wraps gets its own buckets: asking for a privacy link does
+/// not use up the allowance for signing up.
+#[derive(Clone)]
+pub struct RateLimiter {
+    store: Arc<dyn RateLimitStore>,
+    per_ip: BucketLimit,
+    per_email: BucketLimit,
+    trust_forwarded_for: bool,
+}
+
+impl RateLimiter {
+    /// With `trust_forwarded_for`, the client IP is the last address in
+    /// `X-Forwarded-For` rather than the peer address. Only enable it behind
+    /// a reverse proxy appending to that header: clients can set it too.
+    pub fn new(
+        store: Arc<dyn RateLimitStore>,
+        per_ip: BucketLimit,
+        per_email: BucketLimit,
+        trust_forwarded_for: bool,
+    ) -> Self {
+        Self {
+            store,
+            per_ip,
+            per_email,
+            trust_forwarded_for,
+        }
+    }
+
+    async fn check(&self, request: &mut ServiceRequest) -> Result<(), actix_web::Error> {
+        let now = Utc::now();
+        let route = request
+            .match_pattern()
+            .unwrap_or_else(|| request.path().to_owned());
+        let ip_key = self
+            .client_ip(request)
+            .map(|ip| format!("{} ip:{}", route, ip));
+        if let Some(ip_key) = &ip_key {
+            self.take(ip_key, &self.per_ip, now).await?;
+        }
+        if let Some(email) = target_email(request).await? {
+            let email_key = format!("{} email:{}", route, email);
+            if let Err(e) = self.take(&email_key, &self.per_email, now).await {
+                // The request is turned away: it must not count against the IP either
+                if let Some(ip_key) = &ip_key {
+                    self.store
+                        .give_back(ip_key, &self.per_ip)
+                        .await
+                        .map_err(RateLimitError::from)?;
+                }
+                return Err(e.into());
+            }
+        }
+        Ok(())
+    }
+
+    async fn take(
+        &self,
+        key: &str,
+        limit: &BucketLimit,
+        now: DateTime<Utc>,
+    ) -> Result<(), RateLimitError> {
+        match self.store.take(key, limit, now).await? {
+            Decision::Allowed => Ok(()),
+            Decision::Denied { retry_after } => {
+                Err(RateLimitError::TooManyRequests { retry_after })
+            }
+        }
+    }
+
+    fn client_ip(&self, request: &ServiceRequest) -> Option<String> {
+        if self.trust_forwarded_for {
+            // Our proxy appends the address it got the request from,
+            // anything before it was sent by the client
+            let forwarded_for = request
+                .headers()
+                .get("X-Forwarded-For")
+                .and_then(|h| h.to_str().ok())
+                .and_then(|h| h.rsplit(',').next())
+                .map(|ip| ip.trim())
+                .filter(|ip| !ip.is_empty());
+            if let Some(ip) = forwarded_for {
+                return Some(ip.to_owned());
+            }
+        }
+        request.peer_addr().map(|address| address.ip().to_string())
+    }
+}
+
+/// The address a form is about to email, if any.
+///
+/// Invalid addresses are rejected by the handler without emailing anyone:
+/// they do not get a bucket, so that clients cannot fill the store with
+/// garbage keys. The body is buffered, then handed back to the request for the handler
+/// to read it again.
+async fn target_email(request: &mut ServiceRequest) -> Result<Option<String>, actix_web::Error> {
+    #[derive(serde::Deserialize)]
+    struct Target {
+        email: Option<String>,
+    }
+
+    let (http_request, payload) = request.parts_mut();
+    let body = web::Bytes::from_request(http_request, payload).await?;
+    let (_, mut buffered) = actix_http::h1::Payload::create(true);
+    buffered.unread_data(body.clone());
+    *payload = buffered.into();
+
+    let email = std::str::from_utf8(&body)
+        .ok()
+        .and_then(|body| web::Query::<Target>::from_query(body).ok())
+        .and_then(|target| target.into_inner().email)
+        .map(|email| email.trim().to_lowercase())
+        .and_then(|email| SubscriberEmail::parse(email).ok())
+        .map(|email| email.as_ref().to_owned());
+    Ok(email)
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RateLimiter
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Transform = RateLimiterMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RateLimiterMiddleware {
+            service: Rc::new(service),
+            limiter: self.clone(),
+        }))
+    }
+}
+
+pub struct RateLimiterMiddleware<S> {
+    service: Rc<S>,
+    limiter: RateLimiter,
+}
+
+impl<S, B> Service<ServiceRequest> for RateLimiterMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    #[allow(clippy::type_complexity)]
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, mut request: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+        let limiter = self.limiter.clone();
+        Box::pin(async move {
+            limiter.check(&mut request).await?;
+            service.call(request).await
+        })
+    }
+}
+
+#[derive(thiserror::Error)]
+pub enum RateLimitError {
+    #[error("Too many requests, please try again later.")]
+    TooManyRequests { retry_after: std::time::Duration },
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for RateLimitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for RateLimitError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            RateLimitError::TooManyRequests { .. } => StatusCode::TOO_MANY_REQUESTS,
+            RateLimitError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            RateLimitError::TooManyRequests { retry_after } => {
+                // `Retry-After` is in whole seconds: round up, never tell clients to retry right away
+                let seconds = (retry_after.as_millis() as f64 / 1000.).ceil().max(1.);
+                HttpResponse::TooManyRequests()
+                    .insert_header((RETRY_AFTER, seconds.to_string()))
+                    .body(self.to_string())
+            }
+            RateLimitError::UnexpectedError(_) => HttpResponse::new(self.status_code()),
+        }
+    }
+}
@@ -0,0 +1,184 @@
+//! Token buckets throttling the endpoints sending an email on every call,
+//! like `POST /subscriptions`.
+//!
+//! Each client IP and each target address gets its own bucket: a request
+//! takes a token out of both, and is turned away with a 429 once either is
+//! empty. Buckets are kept by a `RateLimitStore`, in memory or in Postgres.
+use chrono::{DateTime, Utc};
+
+pub use memory::InMemoryStore;
+pub use middleware::{RateLimitError, RateLimiter};
+pub use postgres::PostgresStore;
+
+mod memory;
+mod middleware;
+mod postgres;
+
+/// Where the buckets live.
+///
+/// Route handlers never see this trait: `RateLimiter` is the only caller,
+/// and which store it uses is decided by `RateLimitSettings::backend`.
+#[async_trait::async_trait]
+pub trait RateLimitStore: Send + Sync {
+    /// Take a token out of the bucket identified by `key`, if there is one left.
+    async fn take(
+        &self,
+        key: &str,
+        limit: &BucketLimit,
+        now: DateTime<Utc>,
+    ) -> Result<Decision, anyhow::Error>;
+
+    /// Put back a token taken out of the bucket identified by `key`, for a
+    /// request that was turned away by another bucket.
+    async fn give_back(&self, key: &str, limit: &BucketLimit) -> Result<(), anyhow::Error>;
+}
+
+/// Up to `capacity` requests in a burst, then one more every `refill_interval`.
+#[derive(Clone, Copy, Debug)]
+pub struct BucketLimit {
+    pub capacity: u32,
+    pub refill_interval: chrono::Duration,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BucketState {
+    pub tokens: f64,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Decision {
+    Allowed,
+    Denied { retry_after: std::time::Duration },
+}
+
+impl BucketLimit {
+    /// The state of a bucket nobody has taken a token out of yet.
+    pub fn full(&self, now: DateTime<Utc>) -> BucketState {
+        BucketState {
+            tokens: self.capacity as f64,
+            updated_at: now,
+        }
+    }
+
+    /// Refill `state` for the time elapsed since it was last updated, then
+    /// take a token out of it.
+    pub fn take(&self, state: BucketState, now: DateTime<Utc>) -> (BucketState, Decision) {
+        let interval = self.refill_interval.num_milliseconds().max(1) as f64;
+        // Clocks may disagree between replicas: never refill backwards
+        let elapsed = (now - state.updated_at).num_milliseconds().max(0) as f64;
+        let tokens = (state.tokens + elapsed / interval).min(self.capacity as f64);
+        if tokens >= 1. {
+            let state = BucketState {
+                tokens: tokens - 1.,
+                updated_at: now,
+            };
+            (state, Decision::Allowed)
+        } else {
+            let retry_after = ((1. - tokens) * interval).ceil() as u64;
+            let state = BucketState {
+                tokens,
+                updated_at: now,
+            };
+            let retry_after = std::time::Duration::from_millis(retry_after);
+            (state, Decision::Denied { retry_after })
+        }
+    }
+
+    /// Put back a token taken out of `state`, without refilling it beyond its
+    /// capacity.
+    pub fn give_back(&self, state: BucketState) -> BucketState {
+        BucketState {
+            tokens: (state.tokens + 1.).min(self.capacity as f64),
+            updated_at: state.updated_at,
+        }
+    }
+
+    /// When a bucket in `state` will be full again, and can be forgotten.
+    pub fn full_at(&self, state: &BucketState) -> DateTime<Utc> {
+        let missing = (self.capacity as f64 - state.tokens).max(0.);
+        let interval = self.refill_interval.num_milliseconds() as f64;
+        state.updated_at + chrono::Duration::milliseconds((missing * interval).ceil() as i64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, TimeZone, Utc};
+
+    use super::{BucketLimit, Decision};
+
+    fn limit() -> BucketLimit {
+        BucketLimit {
+            capacity: 2,
+            refill_interval: Duration::seconds(60),
+        }
+    }
+
+    #[test]
+    fn a_full_bucket_allows_a_burst_up_to_its_capacity() {
+        let now = Utc.ymd(2022, 7, 8).and_hms(9, 0, 0);
+        let state = limit().full(now);
+
+        let (state, first) = limit().take(state, now);
+        let (state, second) = limit().take(state, now);
+        let (_, third) = limit().take(state, now);
+
+        assert_eq!(first, Decision::Allowed);
+        assert_eq!(second, Decision::Allowed);
+        assert_eq!(
+            third,
+            Decision::Denied {
+                retry_after: std::time::Duration::from_secs(60)
+            }
+        );
+    }
+
+    #[test]
+    fn an_empty_bucket_is_refilled_over_time() {
+        let now = Utc.ymd(2022, 7, 8).and_hms(9, 0, 0);
+        let (state, _) = limit().take(limit().full(now), now);
+        let (state, _) = limit().take(state, now);
+
+        let (state, decision) = limit().take(state, now + Duration::seconds(45));
+        assert_eq!(
+            decision,
+            Decision::Denied {
+                retry_after: std::time::Duration::from_secs(15)
+            }
+        );
+        let (_, decision) = limit().take(state, now + Duration::seconds(60));
+        assert_eq!(decision, Decision::Allowed);
+    }
+
+    #[test]
+    fn a_bucket_is_never_refilled_beyond_its_capacity() {
+        let now = Utc.ymd(2022, 7, 8).and_hms(9, 0, 0);
+        let (state, _) = limit().take(limit().full(now), now);
+
+        let (state, _) = limit().take(state, now + Duration::days(1));
+
+        assert_eq!(state.tokens, 1.);
+    }
+
+    #[test]
+    fn a_bucket_is_full_again_after_one_interval_per_missing_token() {
+        let now = Utc.ymd(2022, 7, 8).and_hms(9, 0, 0);
+        let (state, _) = limit().take(limit().full(now), now);
+        let (state, _) = limit().take(state, now);
+
+        assert_eq!(limit().full_at(&state), now + Duration::seconds(120));
+    }
+
+    #[test]
+    fn a_token_given_back_can_be_taken_again() {
+        let now = Utc.ymd(2022, 7, 8).and_hms(9, 0, 0);
+        let (state, _) = limit().take(limit().full(now), now);
+        let (state, _) = limit().take(state, now);
+
+        let (state, decision) = limit().take(limit().give_back(state), now);
+
+        assert_eq!(decision, Decision::Allowed);
+        assert_eq!(limit().give_back(limit().give_back(state)).tokens, 2.);
+    }
+}
@@ -0,0 +1,134 @@
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+
+use super::{BucketLimit, BucketState, Decision, RateLimitStore};
+
+/// Expired buckets deleted by each call to `take`, to keep its cost bounded.
+const SWEEP_BATCH_SIZE: i64 = 100;
+
+/// Buckets kept in the `rate_limit_buckets` table, shared by every replica.
+///
+/// A full bucket behaves exactly like a missing one: each call to `take`
+/// deletes a batch of the buckets that have filled up since they were last
+/// used, so that the table only holds the keys that are being limited.
+pub struct PostgresStore {
+    pool: PgPool,
+}
+
+impl PostgresStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait::async_trait]
+impl RateLimitStore for PostgresStore {
+    async fn take(
+        &self,
+        key: &str,
+        limit: &BucketLimit,
+        now: DateTime<Utc>,
+    ) -> Result<Decision, anyhow::Error> {
+        let mut transaction = self
+            .pool
+            .begin()
+            .await
+            .context("Failed to acquire a Postgres connection from the pool")?;
+        // Buckets locked by a concurrent request are left for the next sweep
+        sqlx::query!(
+            r#"
+            DELETE FROM rate_limit_buckets
+            WHERE key IN (
+                SELECT key FROM rate_limit_buckets
+                WHERE full_at <= $1 AND key <> $2
+                LIMIT $3
+                FOR UPDATE SKIP LOCKED
+            )
+            "#,
+            now,
+            key,
+            SWEEP_BATCH_SIZE
+        )
+        .execute(&mut transaction)
+        .await
+        .context("Failed to delete the expired rate limit buckets.")?;
+        let full = limit.full(now);
+        sqlx::query!(
+            r#"
+            INSERT INTO rate_limit_buckets (key, tokens, updated_at, full_at)
+            VALUES ($1, $2, $3, $3)
+            ON CONFLICT (key) DO NOTHING
+            "#,
+            key,
+            full.tokens,
+            full.updated_at
+        )
+        .execute(&mut transaction)
+        .await
+        .context("Failed to create the rate limit bucket.")?;
+        // Lock the row, so that concurrent requests take their tokens one at a time
+        let state = sqlx::query_as!(
+            BucketState,
+            r#"SELECT tokens, updated_at FROM rate_limit_buckets WHERE key = $1 FOR UPDATE"#,
+            key
+        )
+        .fetch_one(&mut transaction)
+        .await
+        .context("Failed to retrieve the rate limit bucket.")?;
+        let (state, decision) = limit.take(state, now);
+        sqlx::query!(
+            r#"
+            UPDATE rate_limit_buckets
+            SET tokens = $2, updated_at = $3, full_at = $4
+            WHERE key = $1
+            "#,
+            key,
+            state.tokens,
+            state.updated_at,
+            limit.full_at(&state)
+        )
+        .execute(&mut transaction)
+        .await
+        .context("Failed to update the rate limit bucket.")?;
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit the rate limit bucket.")?;
+        Ok(decision)
+    }
+
+    async fn give_back(&self, key: &str, limit: &BucketLimit) -> Result<(), anyhow::Error> {
+        let mut transaction = self
+            .pool
+            .begin()
+            .await
+            .context("Failed to acquire a Postgres connection from the pool")?;
+        let state = sqlx::query_as!(
+            BucketState,
+            r#"SELECT tokens, updated_at FROM rate_limit_buckets WHERE key = $1 FOR UPDATE"#,
+            key
+        )
+        .fetch_optional(&mut transaction)
+        .await
+        .context("Failed to retrieve the rate limit bucket.")?;
+        // A swept bucket is already as good as full
+        if let Some(state) = state {
+            let state = limit.give_back(state);
+            sqlx::query!(
+                r#"UPDATE rate_limit_buckets SET tokens = $2, full_at = $3 WHERE key = $1"#,
+                key,
+                state.tokens,
+                limit.full_at(&state)
+            )
+            .execute(&mut transaction)
+            .await
+            .context("Failed to update the rate limit bucket.")?;
+        }
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit the rate limit bucket.")?;
+        Ok(())
+    }
+}
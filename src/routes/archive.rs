@@ -0,0 +1,310 @@
+use actix_web::http::header::{HeaderValue, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::domain::strip_merge_tags;
+use crate::routes::ArchiveError;
+use crate::startup::ApplicationBaseUrl;
+use crate::templates::Templates;
+
+/// Number of issues listed in the feeds.
+const FEED_LENGTH: i64 = 20;
+
+const HTML: &str = "text/html; charset=utf-8";
+const ATOM: &str = "application/atom+xml; charset=utf-8";
+const RSS: &str = "application/rss+xml; charset=utf-8";
+
+#[tracing::instrument(name = "Show the archive", skip_all)]
+pub async fn archive_index(
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    templates: web::Data<Templates>,
+) -> Result<HttpResponse, ArchiveError> {
+    let validators = Validators::for_archive(&pool).await?;
+    if validators.is_fresh(&request) {
+        return Ok(validators.not_modified());
+    }
+    let issues = get_issues(&pool, None)
+        .await
+        .context("Failed to retrieve newsletter issues.")?;
+    let mut context = tera::Context::new();
+    context.insert("issues", &issues);
+    render(&templates, "archive/index.html", &context, HTML, &validators)
+}
+
+#[tracing::instrument(name = "Show an archived issue", skip(request, pool, templates))]
+pub async fn archive_issue(
+    slug: web::Path<String>,
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    templates: web::Data<Templates>,
+) -> Result<HttpResponse, ArchiveError> {
+    let issue = get_issue_by_slug(&pool, &slug)
+        .await
+        .context("Failed to retrieve the newsletter issue.")?
+        .ok_or(ArchiveError::UnknownIssue)?;
+    // Issues never change once published, but their author may be renamed.
+    let validators = Validators::new(
+        format!(
+            "{}-{}-{}",
+            issue.id,
+            issue.published_at_rfc3339,
+            fingerprint(issue.author.as_deref().unwrap_or_default())
+        ),
+        Some(issue.published_at),
+    );
+    if validators.is_fresh(&request) {
+        return Ok(validators.not_modified());
+    }
+    let mut context = tera::Context::new();
+    context.insert("issue", &issue);
+    render(&templates, "archive/issue.html", &context, HTML, &validators)
+}
+
+#[tracing::instrument(name = "Serve the Atom feed", skip_all)]
+pub async fn atom_feed(
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    templates: web::Data<Templates>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, ArchiveError> {
+    feed(&request, &pool, &templates, &base_url.0, "feeds/atom.xml", ATOM).await
+}
+
+#[tracing::instrument(name = "Serve the RSS feed", skip_all)]
+pub async fn rss_feed(
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    templates: web::Data<Templates>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, ArchiveError> {
+    feed(&request, &pool, &templates, &base_url.0, "feeds/rss.xml", RSS).await
+}
+
+async fn feed(
+    request: &HttpRequest,
+    pool: &PgPool,
+    templates: &Templates,
+    base_url: &str,
+    template: &str,
+    content_type: &str,
+) -> Result<HttpResponse, ArchiveError> {
+    let validators = Validators::for_archive(pool).await?;
+    if validators.is_fresh(request) {
+        return Ok(validators.not_modified());
+    }
+    let issues = get_issues(pool, Some(FEED_LENGTH))
+        .await
+        .context("Failed to retrieve newsletter issues.")?;
+    let updated = validators.last_modified.unwrap_or_else(Utc::now);
+    let mut context = tera::Context::new();
+    context.insert("base_url", base_url);
+    context.insert("updated", &updated.to_rfc3339());
+    context.insert("issues", &issues);
+    render(templates, template, &context, content_type, &validators)
+}
+
+fn render(
+    templates: &Templates,
+    template: &str,
+    context: &tera::Context,
+    content_type: &str,
+    validators: &Validators,
+) -> Result<HttpResponse, ArchiveError> {
+    let body = templates
+        .render_page(template, context)
+        .with_context(|| format!("Failed to render `{}`.", template))?;
+    let mut response = HttpResponse::Ok().content_type(content_type).body(body);
+    validators.apply(&mut response);
+    Ok(response)
+}
+
+/// `ETag` and `Last-Modified` of a page, to answer conditional requests
+/// with a `304 Not Modified` instead of the whole page.
+struct Validators {
+    etag: String,
+    last_modified: Option<DateTime<Utc>>,
+}
+
+impl Validators {
+    fn new(tag: String, last_modified: Option<DateTime<Utc>>) -> Self {
+        // Pages also change when a new release ships different templates.
+        Self {
+            etag: format!("\"{}-{}\"", env!("CARGO_PKG_VERSION"), tag),
+            last_modified,
+        }
+    }
+
+    /// Pages listing issues only change when an issue is published or removed,
+    /// or when one of their authors is renamed.
+    async fn for_archive(pool: &PgPool) -> Result<Self, ArchiveError> {
+        let r = sqlx::query!(
+            r#"
+            SELECT
+                COUNT(*) AS "count!",
+                MAX(i.published_at) AS latest,
+                string_agg(
+                    COALESCE(u.display_name, ''), ',' ORDER BY i.newsletter_issue_id
+                ) AS authors
+            FROM newsletter_issues i
+            LEFT JOIN users u ON u.user_id = i.author_id
+            WHERE i.status = 'published'
+            "#
+        )
+        .fetch_one(pool)
+        .await
+        .context("Failed to retrieve the latest publication date.")?;
+        let tag = format!(
+            "{}-{}-{}",
+            r.count,
+            r.latest.map(|l| l.timestamp_nanos()).unwrap_or_default(),
+            fingerprint(&r.authors.unwrap_or_default())
+        );
+        Ok(Self::new(tag, r.latest))
+    }
+
+    /// Whether the copy the client already has is still current.
+    ///
+    /// As per RFC 7232, `If-Modified-Since` is ignored when `If-None-Match` is present.
+    fn is_fresh(&self, request: &HttpRequest) -> bool {
+        let headers = request.headers();
+        if let Some(if_none_match) = headers.get(IF_NONE_MATCH) {
+            return if_none_match.to_str().is_ok_and(|tags| {
+                tags.split(',')
+                    .map(|tag| tag.trim())
+                    .any(|tag| tag == "*" || tag.trim_start_matches("W/") == self.etag)
+            });
+        }
+        match (headers.get(IF_MODIFIED_SINCE), self.last_modified) {
+            (Some(if_modified_since), Some(last_modified)) => if_modified_since
+                .to_str()
+                .ok()
+                .and_then(|date| DateTime::parse_from_rfc2822(date).ok())
+                // HTTP dates have a one second precision
+                .is_some_and(|date| last_modified.timestamp() <= date.timestamp()),
+            _ => false,
+        }
+    }
+
+    fn not_modified(&self) -> HttpResponse {
+        let mut response = HttpResponse::NotModified().finish();
+        self.apply(&mut response);
+        response
+    }
+
+    fn apply(&self, response: &mut HttpResponse) {
+        let headers = response.headers_mut();
+        if let Ok(etag) = HeaderValue::from_str(&self.etag) {
+            headers.insert(ETAG, etag);
+        }
+        if let Some(last_modified) = self.last_modified {
+            let http_date = last_modified.format("%a, %d %b %Y %H:%M:%S GMT").to_string();
+            if let Ok(http_date) = HeaderValue::from_str(&http_date) {
+                headers.insert(LAST_MODIFIED, http_date);
+            }
+        }
+    }
+}
+
+/// A short digest of `value`, to put in an `ETag` whatever characters it holds.
+fn fingerprint(value: &str) -> String {
+    Sha256::digest(value.as_bytes())
+        .iter()
+        .take(8)
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// An issue as shown in the archive and feeds: merge tags are removed,
+/// since we know nothing about the reader.
+#[derive(serde::Serialize)]
+struct ArchivedIssue {
+    id: Uuid,
+    slug: String,
+    title: String,
+    author: Option<String>,
+    #[serde(skip)]
+    published_at: DateTime<Utc>,
+    published_on: String,
+    published_at_rfc3339: String,
+    published_at_rfc2822: String,
+    html_content: String,
+}
+
+struct IssueRow {
+    id: Uuid,
+    slug: String,
+    title: String,
+    author: Option<String>,
+    published_at: DateTime<Utc>,
+    html_content: String,
+}
+
+impl From<IssueRow> for ArchivedIssue {
+    fn from(row: IssueRow) -> Self {
+        Self {
+            id: row.id,
+            slug: row.slug,
+            title: row.title,
+            author: row.author,
+            published_at: row.published_at,
+            published_on: row.published_at.format("%Y-%m-%d").to_string(),
+            published_at_rfc3339: row.published_at.to_rfc3339(),
+            published_at_rfc2822: row.published_at.to_rfc2822(),
+            html_content: strip_merge_tags(&row.html_content),
+        }
+    }
+}
+
+/// The most recent issues first, all of them if `limit` is `None`.
+#[tracing::instrument(skip(pool))]
+async fn get_issues(pool: &PgPool, limit: Option<i64>) -> Result<Vec<ArchivedIssue>, sqlx::Error> {
+    let rows = sqlx::query_as!(
+        IssueRow,
+        r#"
+        SELECT
+            i.newsletter_issue_id AS id,
+            i.slug,
+            i.title,
+            u.display_name AS "author?",
+            i.published_at AS "published_at!",
+            i.html_content
+        FROM newsletter_issues i
+        LEFT JOIN users u ON u.user_id = i.author_id
+        WHERE i.status = 'published'
+        ORDER BY i.published_at DESC, i.newsletter_issue_id
+        LIMIT $1
+        "#,
+        limit
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows.into_iter().map(Into::into).collect())
+}
+
+#[tracing::instrument(skip(pool))]
+async fn get_issue_by_slug(pool: &PgPool, slug: &str) -> Result<Option<ArchivedIssue>, sqlx::Error> {
+    let row = sqlx::query_as!(
+        IssueRow,
+        r#"
+        SELECT
+            i.newsletter_issue_id AS id,
+            i.slug,
+            i.title,
+            u.display_name AS "author?",
+            i.published_at AS "published_at!",
+            i.html_content
+        FROM newsletter_issues i
+        LEFT JOIN users u ON u.user_id = i.author_id
+        WHERE i.slug = $1 AND i.status = 'published'
+        "#,
+        slug
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(row.map(Into::into))
+}
@@ -0,0 +1,147 @@
+use actix_web::http::header::{HeaderValue, WWW_AUTHENTICATE};
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::domain::ListSlug;
+use crate::routes::{authenticate, error_chain_fmt, PublishError};
+
+#[derive(thiserror::Error)]
+pub enum ListError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("Authentication failed.")]
+    AuthError(#[source] anyhow::Error),
+    #[error("There is already a list with the provided slug.")]
+    DuplicateList,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for ListError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl From<PublishError> for ListError {
+    fn from(e: PublishError) -> Self {
+        match e {
+            PublishError::AuthError(e) => ListError::AuthError(e),
+            e => ListError::UnexpectedError(e.into()),
+        }
+    }
+}
+
+impl ResponseError for ListError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ListError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            ListError::AuthError(_) => StatusCode::UNAUTHORIZED,
+            ListError::DuplicateList => StatusCode::CONFLICT,
+            ListError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            ListError::ValidationError(message) => HttpResponse::BadRequest().body(message.clone()),
+            ListError::AuthError(_) => {
+                let mut response = HttpResponse::new(StatusCode::UNAUTHORIZED);
+                let header_value = HeaderValue::from_str(r#"Basic realm="publish""#).unwrap();
+                response
+                    .headers_mut()
+                    .insert(WWW_AUTHENTICATE, header_value);
+                response
+            }
+            ListError::DuplicateList | ListError::UnexpectedError(_) => {
+                HttpResponse::new(self.status_code())
+            }
+        }
+    }
+}
+
+/// A mailing list subscribers can join and issues can be sent to.
+#[derive(serde::Serialize)]
+pub struct MailingList {
+    #[serde(skip)]
+    pub list_id: Uuid,
+    pub slug: String,
+    pub name: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct NewListBody {
+    slug: String,
+    name: String,
+}
+
+/// The lists that can be joined, for signup forms to offer.
+#[tracing::instrument(name = "List mailing lists", skip(pool))]
+pub async fn get_lists(pool: web::Data<PgPool>) -> Result<HttpResponse, ListError> {
+    let lists = sqlx::query_as!(
+        MailingList,
+        r#"SELECT list_id, slug, name FROM lists ORDER BY slug"#
+    )
+    .fetch_all(pool.get_ref())
+    .await
+    .context("Failed to retrieve the lists.")?;
+    Ok(HttpResponse::Ok().json(lists))
+}
+
+#[tracing::instrument(
+    name = "Create a mailing list",
+    skip(body, pool, request),
+    fields(username = tracing::field::Empty, user_id = tracing::field::Empty)
+)]
+pub async fn create_list(
+    body: web::Json<NewListBody>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, ListError> {
+    authenticate(&request, &pool).await?;
+    let body = body.into_inner();
+    let slug = ListSlug::parse(body.slug).map_err(ListError::ValidationError)?;
+    let name = body.name.trim();
+    if name.is_empty() {
+        return Err(ListError::ValidationError(
+            "A list name cannot be empty.".into(),
+        ));
+    }
+    let inserted = sqlx::query!(
+        r#"
+        INSERT INTO lists (list_id, slug, name, created_at)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (slug) DO NOTHING
+        "#,
+        Uuid::new_v4(),
+        slug.as_ref(),
+        name,
+        Utc::now()
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to store the new list.")?
+    .rows_affected();
+    if inserted == 0 {
+        return Err(ListError::DuplicateList);
+    }
+    Ok(HttpResponse::Created().finish())
+}
+
+#[tracing::instrument(skip(pool))]
+pub async fn get_list_by_slug(
+    pool: &PgPool,
+    slug: &ListSlug,
+) -> Result<Option<MailingList>, sqlx::Error> {
+    sqlx::query_as!(
+        MailingList,
+        r#"SELECT list_id, slug, name FROM lists WHERE slug = $1"#,
+        slug.as_ref()
+    )
+    .fetch_optional(pool)
+    .await
+}
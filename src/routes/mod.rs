@@ -1,9 +1,31 @@
+pub use archive::*;
 pub use health_check::*;
+pub use lists::*;
 pub use newsletters::*;
+pub use newsletters_archive::*;
+pub use newsletters_scheduled::*;
+pub use newsletters_stats::*;
+pub use preferences::*;
+pub use privacy::*;
 pub use subscriptions::*;
 pub use subscriptions_confirm::*;
+pub use subscriptions_unsubscribe::*;
+pub use suppressions::*;
+pub use tracking::*;
+pub use webhooks::*;
 
+mod archive;
 mod health_check;
+mod lists;
 mod newsletters;
+mod newsletters_archive;
+mod newsletters_scheduled;
+mod newsletters_stats;
+mod preferences;
+mod privacy;
 mod subscriptions;
 mod subscriptions_confirm;
+mod subscriptions_unsubscribe;
+mod suppressions;
+mod tracking;
+mod webhooks;
@@ -1,14 +1,29 @@
+use std::convert::TryInto;
+
+use actix_web::http::header::{HeaderValue, WWW_AUTHENTICATE};
 use actix_web::http::StatusCode;
-use actix_web::{web, HttpResponse, ResponseError};
-use anyhow::{Context, Error};
-use sqlx::PgPool;
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
 
-use crate::domain::SubscriberEmail;
-use crate::email_client::EmailClient;
-use crate::routes::error_chain_fmt;
+use crate::authentication::{basic_authentication, validate_credentials, AuthError};
+use crate::delivery_log::log_enqueued;
+use crate::domain::{validate_merge_tags, IssueSlug, ListSlug, SubscriberEmail};
+use crate::idempotency::{save_response, try_processing, IdempotencyKey, NextAction};
+use crate::routes::{error_chain_fmt, get_list_by_slug};
 
 #[derive(thiserror::Error)]
 pub enum PublishError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("Authentication failed.")]
+    AuthError(#[source] anyhow::Error),
+    #[error("There is no scheduled newsletter issue with the provided id.")]
+    UnknownIssue,
+    #[error("This newsletter issue has already been sent or cancelled.")]
+    NotScheduled,
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
@@ -16,6 +31,14 @@ pub enum PublishError {
 pub struct BodyData {
     title: String,
     content: Content,
+    /// Send the issue at this time rather than right away.
+    send_at: Option<DateTime<Utc>>,
+    /// The slugs of the lists to send the issue to, the default list if missing.
+    lists: Option<Vec<String>>,
+    /// Whether to record who opens the issue, `true` if missing.
+    track_opens: Option<bool>,
+    /// Whether to record who clicks the links of the issue, `true` if missing.
+    track_clicks: Option<bool>,
 }
 
 #[derive(serde::Deserialize)]
@@ -35,30 +58,168 @@ impl std::fmt::Debug for PublishError {
 impl ResponseError for PublishError {
     fn status_code(&self) -> StatusCode {
         match self {
+            PublishError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            PublishError::AuthError(_) => StatusCode::UNAUTHORIZED,
+            PublishError::UnknownIssue => StatusCode::NOT_FOUND,
+            PublishError::NotScheduled => StatusCode::CONFLICT,
             PublishError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            PublishError::ValidationError(message) => {
+                HttpResponse::BadRequest().body(message.clone())
+            }
+            PublishError::AuthError(_) => {
+                let mut response = HttpResponse::new(StatusCode::UNAUTHORIZED);
+                let header_value = HeaderValue::from_str(r#"Basic realm="publish""#).unwrap();
+                response
+                    .headers_mut()
+                    .insert(WWW_AUTHENTICATE, header_value);
+                response
+            }
+            PublishError::UnknownIssue
+            | PublishError::NotScheduled
+            | PublishError::UnexpectedError(_) => HttpResponse::new(self.status_code()),
+        }
+    }
 }
 
+#[tracing::instrument(
+    name = "Publish a newsletter issue",
+    skip(body, pool, request),
+    fields(username = tracing::field::Empty, user_id = tracing::field::Empty)
+)]
 pub async fn publish_newsletter(
     body: web::Json<BodyData>,
     pool: web::Data<PgPool>,
-    email_client: web::Data<EmailClient>,
+    request: HttpRequest,
 ) -> Result<HttpResponse, PublishError> {
-    let subs = get_confirmed_subscribers(&pool).await?;
-    for sub in subs {
-        match sub {
-            Ok(sub) => {
-                email_client
-                    .send_email(
-                        &sub.email,
-                        &body.title,
-                        &body.content.html,
-                        &body.content.text,
-                    )
-                    .await
-                    .with_context(|| format!("Failed to send email to {}", sub.email))?;
-            }
+    let user_id = authenticate(&request, &pool).await?;
+
+    validate_merge_tags(&body.content.html)
+        .and_then(|_| validate_merge_tags(&body.content.text))
+        .map_err(PublishError::ValidationError)?;
+    if let Some(send_at) = body.send_at {
+        validate_send_at(send_at)?;
+    }
+    let list_ids = get_list_ids(&pool, body.lists.clone()).await?;
+    let idempotency_key = get_idempotency_key(&request)?;
+    let mut transaction = match try_processing(&pool, &idempotency_key, user_id).await? {
+        NextAction::StartProcessing(t) => t,
+        NextAction::ReturnSavedResponse(saved_response) => {
+            return Ok(saved_response);
+        }
+    };
+
+    let issue_id = insert_newsletter_issue(&mut transaction, &body, user_id)
+        .await
+        .context("Failed to store newsletter issue details")?;
+    insert_issue_lists(&mut transaction, issue_id, &list_ids)
+        .await
+        .context("Failed to store the lists of the newsletter issue")?;
+    let response = if body.send_at.is_some() {
+        HttpResponse::Accepted().body("Newsletter issue scheduled for delivery")
+    } else {
+        enqueue_issue_for_confirmed_subscribers(&mut transaction, issue_id).await?;
+        HttpResponse::Accepted().body("Newsletter issue accepted for delivery")
+    };
+    let response = save_response(transaction, &idempotency_key, user_id, response).await?;
+    Ok(response)
+}
+
+/// Check the credentials of the request, returning the id of the user.
+///
+/// `username` and `user_id` are recorded on the current span.
+pub(crate) async fn authenticate(
+    request: &HttpRequest,
+    pool: &PgPool,
+) -> Result<Uuid, PublishError> {
+    let credentials = basic_authentication(request.headers()).map_err(PublishError::AuthError)?;
+    tracing::Span::current().record("username", &tracing::field::display(&credentials.username));
+    let user_id = validate_credentials(credentials, pool)
+        .await
+        .map_err(|e| match e {
+            AuthError::InvalidCredentials(_) => PublishError::AuthError(e.into()),
+            AuthError::UnexpectedError(_) => PublishError::UnexpectedError(e.into()),
+        })?;
+    tracing::Span::current().record("user_id", &tracing::field::display(&user_id));
+    Ok(user_id)
+}
+
+pub(crate) fn validate_send_at(send_at: DateTime<Utc>) -> Result<(), PublishError> {
+    if send_at <= Utc::now() {
+        return Err(PublishError::ValidationError(
+            "`send_at` must be in the future.".into(),
+        ));
+    }
+    Ok(())
+}
+
+/// Resolve the lists an issue is sent to.
+async fn get_list_ids(
+    pool: &PgPool,
+    slugs: Option<Vec<String>>,
+) -> Result<Vec<Uuid>, PublishError> {
+    let slugs = match slugs {
+        Some(slugs) if slugs.is_empty() => {
+            return Err(PublishError::ValidationError(
+                "`lists` must name at least one list.".into(),
+            ))
+        }
+        Some(slugs) => slugs
+            .into_iter()
+            .map(ListSlug::parse)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(PublishError::ValidationError)?,
+        None => vec![ListSlug::default()],
+    };
+    let mut list_ids = Vec::with_capacity(slugs.len());
+    for slug in slugs {
+        let list = get_list_by_slug(pool, &slug)
+            .await
+            .context("Failed to look up a list in the database.")?
+            .ok_or_else(|| {
+                PublishError::ValidationError(format!("There is no `{}` list.", slug.as_ref()))
+            })?;
+        list_ids.push(list.list_id);
+    }
+    Ok(list_ids)
+}
+
+#[tracing::instrument(skip_all)]
+async fn insert_issue_lists(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+    list_ids: &[Uuid],
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issue_lists (newsletter_issue_id, list_id)
+        SELECT $1, list_id
+        FROM UNNEST($2::uuid[]) AS list_id
+        ON CONFLICT DO NOTHING
+        "#,
+        newsletter_issue_id,
+        list_ids
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+/// Queue the delivery of an issue to every subscriber who is confirmed
+/// right now on at least one of its lists.
+pub(crate) async fn enqueue_issue_for_confirmed_subscribers(
+    transaction: &mut Transaction<'_, Postgres>,
+    issue_id: Uuid,
+) -> Result<(), anyhow::Error> {
+    let subscribers = get_confirmed_subscribers(transaction, issue_id).await?;
+    let mut recipients = Vec::with_capacity(subscribers.len());
+    for subscriber in subscribers {
+        match subscriber {
+            Ok(subscriber) => recipients.push(subscriber.email),
             Err(error) => {
                 tracing::warn!(
                 // We record the error chain as a structured field
@@ -72,22 +233,126 @@ pub async fn publish_newsletter(
             }
         }
     }
-    Ok(HttpResponse::Ok().body("Newsletter published"))
+    enqueue_delivery_tasks(transaction, issue_id, &recipients)
+        .await
+        .context("Failed to enqueue delivery tasks")?;
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn insert_newsletter_issue(
+    transaction: &mut Transaction<'_, Postgres>,
+    body: &BodyData,
+    author_id: Uuid,
+) -> Result<Uuid, sqlx::Error> {
+    let newsletter_issue_id = Uuid::new_v4();
+    let slug = IssueSlug::new(&body.title, newsletter_issue_id);
+    let (status, published_at) = match body.send_at {
+        Some(_) => ("scheduled", None),
+        None => ("published", Some(Utc::now())),
+    };
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issues (
+            newsletter_issue_id,
+            title,
+            text_content,
+            html_content,
+            published_at,
+            author_id,
+            slug,
+            status,
+            send_at,
+            track_opens,
+            track_clicks
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+        "#,
+        newsletter_issue_id,
+        body.title,
+        body.content.text,
+        body.content.html,
+        published_at,
+        author_id,
+        slug.as_ref(),
+        status,
+        body.send_at,
+        body.track_opens.unwrap_or(true),
+        body.track_clicks.unwrap_or(true)
+    )
+    .execute(transaction)
+    .await?;
+    Ok(newsletter_issue_id)
+}
+
+/// Queue one delivery task per recipient, to be picked up by the
+/// issue delivery worker, and log them as pending.
+#[tracing::instrument(skip_all, fields(n_recipients = recipients.len()))]
+async fn enqueue_delivery_tasks(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+    recipients: &[SubscriberEmail],
+) -> Result<(), sqlx::Error> {
+    let recipients: Vec<String> = recipients.iter().map(|r| r.as_ref().to_owned()).collect();
+    log_enqueued(transaction, newsletter_issue_id, &recipients).await?;
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_queue (
+            newsletter_issue_id,
+            subscriber_email
+        )
+        SELECT $1, subscriber_email
+        FROM UNNEST($2::text[]) AS subscriber_email
+        "#,
+        newsletter_issue_id,
+        &recipients[..]
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
 }
 
+/// Read the key that clients must attach to every publishing request so that
+/// retries of the same request are only processed once.
+fn get_idempotency_key(request: &HttpRequest) -> Result<IdempotencyKey, PublishError> {
+    let header_value = request
+        .headers()
+        .get("Idempotency-Key")
+        .ok_or_else(|| {
+            PublishError::ValidationError("The 'Idempotency-Key' header is missing.".into())
+        })?
+        .to_str()
+        .map_err(|_| {
+            PublishError::ValidationError(
+                "The 'Idempotency-Key' header is not a valid UTF8 string.".into(),
+            )
+        })?;
+    header_value
+        .to_owned()
+        .try_into()
+        .map_err(PublishError::ValidationError)
+}
+
+/// Subscribers of several of the issue's lists are only returned once.
 async fn get_confirmed_subscribers(
-    pool: &PgPool,
+    transaction: &mut Transaction<'_, Postgres>,
+    issue_id: Uuid,
 ) -> Result<Vec<Result<ConfirmedSubscriber, anyhow::Error>>, anyhow::Error> {
     struct Row {
         email: String,
     }
     let rows = sqlx::query_as!(
         Row,
-        r#"Select email
-        From subscriptions
-        WHERE status = 'confirmed'"#
+        r#"
+        SELECT DISTINCT s.email
+        FROM subscriptions s
+        JOIN list_memberships m ON m.subscriber_id = s.id
+        JOIN newsletter_issue_lists l ON l.list_id = m.list_id
+        WHERE l.newsletter_issue_id = $1 AND s.status = 'confirmed' AND m.status = 'confirmed'
+        "#,
+        issue_id
     )
-    .fetch_all(pool)
+    .fetch_all(transaction)
     .await?;
 
     let confirmed_subs = rows
@@ -0,0 +1,207 @@
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse, ResponseError};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::domain::strip_merge_tags;
+use crate::routes::error_chain_fmt;
+
+const DEFAULT_PER_PAGE: i64 = 20;
+const MAX_PER_PAGE: i64 = 100;
+
+#[derive(serde::Deserialize)]
+pub struct Pagination {
+    page: Option<i64>,
+    per_page: Option<i64>,
+}
+
+impl Pagination {
+    /// The requested page, page size and offset of the page's first item,
+    /// or why they are out of bounds.
+    pub(crate) fn validate(&self) -> Result<(i64, i64, i64), String> {
+        let page = self.page.unwrap_or(1);
+        let per_page = self.per_page.unwrap_or(DEFAULT_PER_PAGE);
+        if page < 1 {
+            return Err("`page` must be greater than zero.".into());
+        }
+        if !(1..=MAX_PER_PAGE).contains(&per_page) {
+            return Err(format!(
+                "`per_page` must be between 1 and {}.",
+                MAX_PER_PAGE
+            ));
+        }
+        let offset = (page - 1)
+            .checked_mul(per_page)
+            .ok_or_else(|| "`page` is too large.".to_string())?;
+        Ok((page, per_page, offset))
+    }
+}
+
+#[derive(thiserror::Error)]
+pub enum ArchiveError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("There is no newsletter issue with the provided id.")]
+    UnknownIssue,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for ArchiveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for ArchiveError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ArchiveError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            ArchiveError::UnknownIssue => StatusCode::NOT_FOUND,
+            ArchiveError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+pub struct IssueSummary {
+    id: Uuid,
+    slug: String,
+    title: String,
+    /// `None` for issues published before authors were recorded, or by users
+    /// without a display name: their username is a login, not a name.
+    author: Option<String>,
+    published_at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize)]
+pub struct IssuesPage {
+    issues: Vec<IssueSummary>,
+    page: i64,
+    per_page: i64,
+    total: i64,
+}
+
+#[derive(serde::Serialize)]
+pub struct Issue {
+    id: Uuid,
+    slug: String,
+    title: String,
+    author: Option<String>,
+    published_at: DateTime<Utc>,
+    content: IssueContent,
+}
+
+#[derive(serde::Serialize)]
+pub struct IssueContent {
+    html: String,
+    text: String,
+}
+
+/// List published issues, most recent first.
+#[tracing::instrument(name = "List newsletter issues", skip(pagination, pool))]
+pub async fn list_newsletter_issues(
+    pagination: web::Query<Pagination>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ArchiveError> {
+    let (page, per_page, offset) = pagination
+        .validate()
+        .map_err(ArchiveError::ValidationError)?;
+    let issues = get_issue_summaries(&pool, per_page, offset)
+        .await
+        .context("Failed to retrieve newsletter issues.")?;
+    let total = count_issues(&pool)
+        .await
+        .context("Failed to count newsletter issues.")?;
+    Ok(HttpResponse::Ok().json(IssuesPage {
+        issues,
+        page,
+        per_page,
+        total,
+    }))
+}
+
+#[tracing::instrument(name = "Get a newsletter issue", skip(pool))]
+pub async fn get_newsletter_issue(
+    newsletter_issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ArchiveError> {
+    let issue = get_issue(&pool, newsletter_issue_id.into_inner())
+        .await
+        .context("Failed to retrieve the newsletter issue.")?
+        .ok_or(ArchiveError::UnknownIssue)?;
+    Ok(HttpResponse::Ok().json(issue))
+}
+
+#[tracing::instrument(skip(pool))]
+async fn get_issue_summaries(
+    pool: &PgPool,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<IssueSummary>, sqlx::Error> {
+    sqlx::query_as!(
+        IssueSummary,
+        r#"
+        SELECT
+            i.newsletter_issue_id AS id,
+            i.slug,
+            i.title,
+            u.display_name AS "author?",
+            i.published_at AS "published_at!"
+        FROM newsletter_issues i
+        LEFT JOIN users u ON u.user_id = i.author_id
+        WHERE i.status = 'published'
+        ORDER BY i.published_at DESC, i.newsletter_issue_id
+        LIMIT $1 OFFSET $2
+        "#,
+        limit,
+        offset
+    )
+    .fetch_all(pool)
+    .await
+}
+
+#[tracing::instrument(skip_all)]
+async fn count_issues(pool: &PgPool) -> Result<i64, sqlx::Error> {
+    let r = sqlx::query!(
+        r#"SELECT COUNT(*) AS "count!" FROM newsletter_issues WHERE status = 'published'"#
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(r.count)
+}
+
+#[tracing::instrument(skip(pool))]
+async fn get_issue(pool: &PgPool, newsletter_issue_id: Uuid) -> Result<Option<Issue>, sqlx::Error> {
+    let r = sqlx::query!(
+        r#"
+        SELECT
+            i.slug,
+            i.title,
+            u.display_name AS "author?",
+            i.published_at AS "published_at!",
+            i.html_content,
+            i.text_content
+        FROM newsletter_issues i
+        LEFT JOIN users u ON u.user_id = i.author_id
+        WHERE i.newsletter_issue_id = $1 AND i.status = 'published'
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(r.map(|r| Issue {
+        id: newsletter_issue_id,
+        slug: r.slug,
+        title: r.title,
+        author: r.author,
+        published_at: r.published_at,
+        // Anonymous readers have no merge fields to fill in
+        content: IssueContent {
+            html: strip_merge_tags(&r.html_content),
+            text: strip_merge_tags(&r.text_content),
+        },
+    }))
+}
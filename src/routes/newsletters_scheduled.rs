@@ -0,0 +1,126 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::routes::{authenticate, validate_send_at, PublishError};
+
+#[derive(serde::Serialize)]
+pub struct ScheduledIssue {
+    id: Uuid,
+    title: String,
+    send_at: DateTime<Utc>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct RescheduleBody {
+    send_at: DateTime<Utc>,
+}
+
+/// List the issues waiting to be sent, the next one first.
+#[tracing::instrument(
+    name = "List scheduled newsletter issues",
+    skip(pool, request),
+    fields(username = tracing::field::Empty, user_id = tracing::field::Empty)
+)]
+pub async fn list_scheduled_issues(
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, PublishError> {
+    authenticate(&request, &pool).await?;
+    let issues = sqlx::query_as!(
+        ScheduledIssue,
+        r#"
+        SELECT newsletter_issue_id AS id, title, send_at AS "send_at!"
+        FROM newsletter_issues
+        WHERE status = 'scheduled'
+        ORDER BY send_at, newsletter_issue_id
+        "#
+    )
+    .fetch_all(pool.get_ref())
+    .await
+    .context("Failed to retrieve scheduled newsletter issues.")?;
+    Ok(HttpResponse::Ok().json(issues))
+}
+
+#[tracing::instrument(
+    name = "Reschedule a newsletter issue",
+    skip(body, pool, request),
+    fields(username = tracing::field::Empty, user_id = tracing::field::Empty)
+)]
+pub async fn reschedule_issue(
+    newsletter_issue_id: web::Path<Uuid>,
+    body: web::Json<RescheduleBody>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, PublishError> {
+    authenticate(&request, &pool).await?;
+    validate_send_at(body.send_at)?;
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let updated = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET send_at = $2
+        WHERE newsletter_issue_id = $1 AND status = 'scheduled'
+        "#,
+        newsletter_issue_id,
+        body.send_at
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to reschedule the newsletter issue.")?
+    .rows_affected();
+    if updated == 0 {
+        return Err(not_scheduled(&pool, newsletter_issue_id).await);
+    }
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Cancel a scheduled issue. It is kept, but will never be sent.
+#[tracing::instrument(
+    name = "Cancel a scheduled newsletter issue",
+    skip(pool, request),
+    fields(username = tracing::field::Empty, user_id = tracing::field::Empty)
+)]
+pub async fn cancel_scheduled_issue(
+    newsletter_issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, PublishError> {
+    authenticate(&request, &pool).await?;
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let updated = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET status = 'cancelled'
+        WHERE newsletter_issue_id = $1 AND status = 'scheduled'
+        "#,
+        newsletter_issue_id
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to cancel the newsletter issue.")?
+    .rows_affected();
+    if updated == 0 {
+        return Err(not_scheduled(&pool, newsletter_issue_id).await);
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Why an issue could not be updated: it either does not exist, or it has
+/// already left the schedule (sent, or cancelled).
+async fn not_scheduled(pool: &PgPool, newsletter_issue_id: Uuid) -> PublishError {
+    let exists = sqlx::query!(
+        r#"SELECT EXISTS(SELECT 1 FROM newsletter_issues WHERE newsletter_issue_id = $1) AS "exists!""#,
+        newsletter_issue_id
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to retrieve the newsletter issue.");
+    match exists {
+        Ok(r) if r.exists => PublishError::NotScheduled,
+        Ok(_) => PublishError::UnknownIssue,
+        Err(e) => e.into(),
+    }
+}
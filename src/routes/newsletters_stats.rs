@@ -0,0 +1,220 @@
+use actix_web::http::header::{HeaderValue, WWW_AUTHENTICATE};
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::routes::{authenticate, error_chain_fmt, Pagination, PublishError};
+
+#[derive(thiserror::Error)]
+pub enum StatsError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("Authentication failed.")]
+    AuthError(#[source] anyhow::Error),
+    #[error("There is no newsletter issue with the provided id.")]
+    UnknownIssue,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for StatsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl From<PublishError> for StatsError {
+    fn from(e: PublishError) -> Self {
+        match e {
+            PublishError::AuthError(e) => StatsError::AuthError(e),
+            e => StatsError::UnexpectedError(e.into()),
+        }
+    }
+}
+
+impl ResponseError for StatsError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            StatsError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            StatsError::AuthError(_) => StatusCode::UNAUTHORIZED,
+            StatsError::UnknownIssue => StatusCode::NOT_FOUND,
+            StatsError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            StatsError::AuthError(_) => {
+                let mut response = HttpResponse::new(StatusCode::UNAUTHORIZED);
+                let header_value = HeaderValue::from_str(r#"Basic realm="publish""#).unwrap();
+                response
+                    .headers_mut()
+                    .insert(WWW_AUTHENTICATE, header_value);
+                response
+            }
+            _ => HttpResponse::build(self.status_code()).body(self.to_string()),
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+pub struct IssueStats {
+    id: Uuid,
+    title: String,
+    totals: DeliveryTotals,
+    recipients: Vec<RecipientStats>,
+    page: i64,
+    per_page: i64,
+}
+
+/// Recipients are counted once per event, however many times it happened.
+#[derive(serde::Serialize)]
+pub struct DeliveryTotals {
+    targeted: i64,
+    /// Not attempted by the delivery worker yet.
+    pending: i64,
+    delivered: i64,
+    failed: i64,
+    skipped: i64,
+    bounced: i64,
+    opened: i64,
+    clicked: i64,
+    unsubscribed: i64,
+}
+
+#[derive(serde::Serialize)]
+pub struct RecipientStats {
+    email: String,
+    status: String,
+    attempted_at: Option<DateTime<Utc>>,
+    bounced: bool,
+    opened: bool,
+    clicked: bool,
+    unsubscribed: bool,
+}
+
+/// Delivery and engagement statistics of an issue, with a page of the
+/// recipients it was enqueued for, sorted by address.
+///
+/// Erased subscribers are left out, along with everything they did.
+#[tracing::instrument(
+    name = "Get newsletter issue statistics",
+    skip(pagination, pool, request),
+    fields(username = tracing::field::Empty, user_id = tracing::field::Empty)
+)]
+pub async fn get_newsletter_issue_stats(
+    newsletter_issue_id: web::Path<Uuid>,
+    pagination: web::Query<Pagination>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, StatsError> {
+    authenticate(&request, &pool).await?;
+    let (page, per_page, offset) = pagination.validate().map_err(StatsError::ValidationError)?;
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let title = get_issue_title(&pool, newsletter_issue_id)
+        .await
+        .context("Failed to retrieve the newsletter issue.")?
+        .ok_or(StatsError::UnknownIssue)?;
+    let totals = get_totals(&pool, newsletter_issue_id)
+        .await
+        .context("Failed to compute the delivery totals.")?;
+    let recipients = get_recipients(&pool, newsletter_issue_id, per_page, offset)
+        .await
+        .context("Failed to retrieve the recipients.")?;
+    Ok(HttpResponse::Ok().json(IssueStats {
+        id: newsletter_issue_id,
+        title,
+        totals,
+        recipients,
+        page,
+        per_page,
+    }))
+}
+
+#[tracing::instrument(skip(pool))]
+async fn get_issue_title(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+) -> Result<Option<String>, sqlx::Error> {
+    let r = sqlx::query!(
+        r#"SELECT title FROM newsletter_issues WHERE newsletter_issue_id = $1"#,
+        newsletter_issue_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(r.map(|r| r.title))
+}
+
+#[tracing::instrument(skip(pool))]
+async fn get_totals(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+) -> Result<DeliveryTotals, sqlx::Error> {
+    sqlx::query_as!(
+        DeliveryTotals,
+        r#"
+        SELECT
+            COUNT(*) AS "targeted!",
+            COUNT(*) FILTER (WHERE d.status = 'pending') AS "pending!",
+            COUNT(*) FILTER (WHERE d.status = 'delivered') AS "delivered!",
+            COUNT(*) FILTER (WHERE d.status = 'failed') AS "failed!",
+            COUNT(*) FILTER (WHERE d.status = 'skipped') AS "skipped!",
+            COUNT(d.bounced_at) AS "bounced!",
+            COUNT(*) FILTER (WHERE EXISTS (
+                SELECT 1 FROM email_opens o
+                WHERE o.newsletter_issue_id = d.newsletter_issue_id AND o.subscriber_id = d.subscriber_id
+            )) AS "opened!",
+            COUNT(*) FILTER (WHERE EXISTS (
+                SELECT 1 FROM email_clicks c
+                WHERE c.newsletter_issue_id = d.newsletter_issue_id AND c.subscriber_id = d.subscriber_id
+            )) AS "clicked!",
+            COUNT(d.unsubscribed_at) AS "unsubscribed!"
+        FROM issue_deliveries d
+        WHERE d.newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_one(pool)
+    .await
+}
+
+#[tracing::instrument(skip(pool))]
+async fn get_recipients(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<RecipientStats>, sqlx::Error> {
+    sqlx::query_as!(
+        RecipientStats,
+        r#"
+        SELECT
+            s.email,
+            d.status,
+            d.attempted_at,
+            d.bounced_at IS NOT NULL AS "bounced!",
+            EXISTS (
+                SELECT 1 FROM email_opens o
+                WHERE o.newsletter_issue_id = d.newsletter_issue_id AND o.subscriber_id = d.subscriber_id
+            ) AS "opened!",
+            EXISTS (
+                SELECT 1 FROM email_clicks c
+                WHERE c.newsletter_issue_id = d.newsletter_issue_id AND c.subscriber_id = d.subscriber_id
+            ) AS "clicked!",
+            d.unsubscribed_at IS NOT NULL AS "unsubscribed!"
+        FROM issue_deliveries d
+        JOIN subscriptions s ON s.id = d.subscriber_id
+        WHERE d.newsletter_issue_id = $1
+        ORDER BY s.email
+        LIMIT $2 OFFSET $3
+        "#,
+        newsletter_issue_id,
+        limit,
+        offset
+    )
+    .fetch_all(pool)
+    .await
+}
@@ -0,0 +1,448 @@
+use std::convert::{TryFrom, TryInto};
+use std::future::Future;
+
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse, ResponseError};
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::{PgPool, Postgres, Transaction};
+use tracing::Instrument;
+use uuid::Uuid;
+
+use crate::delivery_log::log_unsubscribe;
+use crate::domain::{Frequency, ListSlug, SubscriberEmail, SubscriberName};
+use crate::email_client::{EmailSender, SendEmailError};
+use crate::routes::{error_chain_fmt, get_list_by_slug};
+use crate::signed_token::{self, SignedTokenError};
+use crate::startup::{ApplicationBaseUrl, HmacSecret, PreferencesLinkTtl};
+use crate::templates::Templates;
+
+/// What preference center links are signed for, see `signed_token`.
+const PURPOSE: &str = "preferences";
+
+#[derive(serde::Deserialize)]
+pub struct PreferencesLinkFormData {
+    email: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct PreferencesParameters {
+    token: String,
+}
+
+/// The preferences of a subscriber, validated.
+struct Preferences {
+    name: SubscriberName,
+    frequency: Frequency,
+    lists: Vec<ListSlug>,
+}
+
+/// Built from the raw form fields: `list` is repeated once per selected list,
+/// which a struct cannot capture.
+impl TryFrom<Vec<(String, String)>> for Preferences {
+    type Error = String;
+
+    fn try_from(value: Vec<(String, String)>) -> Result<Self, Self::Error> {
+        let mut name = None;
+        let mut frequency = None;
+        let mut lists = Vec::new();
+        for (key, value) in value {
+            match key.as_str() {
+                "name" => name = Some(SubscriberName::parse(value)?),
+                "frequency" => frequency = Some(Frequency::parse(value)?),
+                "list" => lists.push(ListSlug::parse(value)?),
+                _ => {}
+            }
+        }
+        Ok(Self {
+            name: name.ok_or("The name is missing.")?,
+            frequency: frequency.ok_or("The frequency is missing.")?,
+            lists,
+        })
+    }
+}
+
+#[derive(thiserror::Error)]
+pub enum PreferencesError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("This link is not valid.")]
+    InvalidLink,
+    #[error("This link has expired, please ask for a new one.")]
+    ExpiredLink,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for PreferencesError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for PreferencesError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            PreferencesError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            PreferencesError::InvalidLink => StatusCode::NOT_FOUND,
+            PreferencesError::ExpiredLink => StatusCode::GONE,
+            PreferencesError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl From<SignedTokenError> for PreferencesError {
+    fn from(e: SignedTokenError) -> Self {
+        match e {
+            SignedTokenError::Invalid => PreferencesError::InvalidLink,
+            SignedTokenError::Expired => PreferencesError::ExpiredLink,
+        }
+    }
+}
+
+/// Email a link to the preference center to a confirmed subscriber.
+#[tracing::instrument(
+    name = "Send a link to the preference center",
+    skip(form, pool, email_client, templates, base_url, hmac_secret, link_ttl),
+    fields(subscriber_email = %form.email)
+)]
+pub async fn request_preferences_link(
+    form: web::Form<PreferencesLinkFormData>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<dyn EmailSender>,
+    templates: web::Data<Templates>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    hmac_secret: web::Data<HmacSecret>,
+    link_ttl: web::Data<PreferencesLinkTtl>,
+) -> Result<HttpResponse, PreferencesError> {
+    let email =
+        SubscriberEmail::parse(form.0.email).map_err(PreferencesError::ValidationError)?;
+    send_in_background(async move {
+        send_preferences_link(
+            &email,
+            &pool,
+            email_client.get_ref(),
+            &templates,
+            &base_url.0,
+            &hmac_secret,
+            link_ttl.0,
+        )
+        .await
+    });
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Send an email to an address given by an anonymous visitor, once the
+/// response is on its way. Failures are logged.
+///
+/// The response is the same, and comes back as fast, whether or not the
+/// address is subscribed: neither the lookup, nor the email provider, nor
+/// their errors reveal who is.
+pub(crate) fn send_in_background<F>(send: F)
+where
+    F: Future<Output = Result<(), anyhow::Error>> + Send + 'static,
+{
+    tokio::spawn(
+        async move {
+            if let Err(e) = send.await {
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    "Failed to send an email in the background."
+                );
+            }
+        }
+        .in_current_span(),
+    );
+}
+
+async fn send_preferences_link(
+    email: &SubscriberEmail,
+    pool: &PgPool,
+    email_client: &dyn EmailSender,
+    templates: &Templates,
+    base_url: &str,
+    hmac_secret: &HmacSecret,
+    link_ttl: chrono::Duration,
+) -> Result<(), anyhow::Error> {
+    let subscriber = get_confirmed_subscriber_by_email(pool, email)
+        .await
+        .context("Failed to look up the subscriber in the database.")?;
+    let subscriber = match subscriber {
+        Some(subscriber) => subscriber,
+        None => return Ok(()),
+    };
+    let token = signed_token::sign(
+        &hmac_secret.0,
+        PURPOSE,
+        &subscriber.id.to_string(),
+        Utc::now() + link_ttl,
+    );
+    let preferences_link = format!("{}/preferences?token={}", base_url, token);
+    let mut context = tera::Context::new();
+    context.insert("base_url", base_url);
+    context.insert("name", &subscriber.name);
+    context.insert("preferences_link", &preferences_link);
+    context.insert("link_ttl_minutes", &link_ttl.num_minutes());
+    let rendered = templates
+        .render_email("preferences_link", &context)
+        .context("Failed to render the preference center email.")?;
+    match email_client
+        .send_email(email, &rendered.subject, &rendered.html, &rendered.text)
+        .await
+    {
+        // Nobody is waiting for the outcome: a suppressed address is not an error.
+        Ok(()) | Err(SendEmailError::Suppressed) => Ok(()),
+        Err(e) => Err(e).context("Failed to send a link to the preference center."),
+    }
+}
+
+#[tracing::instrument(
+    name = "Show the preference center",
+    skip(parameters, pool, templates, hmac_secret)
+)]
+pub async fn preferences_form(
+    parameters: web::Query<PreferencesParameters>,
+    pool: web::Data<PgPool>,
+    templates: web::Data<Templates>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, PreferencesError> {
+    let subscriber_id = get_subscriber_id(&pool, &hmac_secret, &parameters.token).await?;
+    render_preferences(&pool, &templates, subscriber_id, &parameters.token, false).await
+}
+
+/// Apply the preferences submitted from the preference center.
+///
+/// Lists picked here are joined right away: following the link already
+/// proved that the subscriber owns the address.
+#[tracing::instrument(
+    name = "Update subscriber preferences",
+    skip(parameters, form, pool, templates, hmac_secret)
+)]
+pub async fn update_preferences(
+    parameters: web::Query<PreferencesParameters>,
+    form: web::Form<Vec<(String, String)>>,
+    pool: web::Data<PgPool>,
+    templates: web::Data<Templates>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, PreferencesError> {
+    let subscriber_id = get_subscriber_id(&pool, &hmac_secret, &parameters.token).await?;
+    let preferences: Preferences = form.0.try_into().map_err(PreferencesError::ValidationError)?;
+    let mut list_ids = Vec::with_capacity(preferences.lists.len());
+    for slug in &preferences.lists {
+        let list = get_list_by_slug(&pool, slug)
+            .await
+            .context("Failed to look up a list in the database.")?
+            .ok_or_else(|| {
+                PreferencesError::ValidationError(format!("There is no `{}` list.", slug.as_ref()))
+            })?;
+        list_ids.push(list.list_id);
+    }
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    update_subscriber(&mut transaction, subscriber_id, &preferences)
+        .await
+        .context("Failed to update the subscriber details.")?;
+    update_memberships(&mut transaction, subscriber_id, &list_ids)
+        .await
+        .context("Failed to update the list memberships.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to update subscriber preferences.")?;
+    render_preferences(&pool, &templates, subscriber_id, &parameters.token, true).await
+}
+
+/// The subscriber a preference center link was sent to, if they are still confirmed.
+async fn get_subscriber_id(
+    pool: &PgPool,
+    hmac_secret: &HmacSecret,
+    token: &str,
+) -> Result<Uuid, PreferencesError> {
+    let subscriber_id = signed_token::verify(&hmac_secret.0, PURPOSE, token)?;
+    let subscriber_id =
+        Uuid::parse_str(&subscriber_id).map_err(|_| PreferencesError::InvalidLink)?;
+    let subscriber = get_confirmed_subscriber(pool, subscriber_id)
+        .await
+        .context("Failed to retrieve the subscriber associated with the link.")?
+        .ok_or(PreferencesError::InvalidLink)?;
+    Ok(subscriber.id)
+}
+
+#[derive(serde::Serialize)]
+struct ListOption {
+    slug: String,
+    name: String,
+    selected: bool,
+}
+
+#[derive(serde::Serialize)]
+struct FrequencyOption {
+    value: &'static str,
+    label: &'static str,
+    selected: bool,
+}
+
+async fn render_preferences(
+    pool: &PgPool,
+    templates: &Templates,
+    subscriber_id: Uuid,
+    token: &str,
+    saved: bool,
+) -> Result<HttpResponse, PreferencesError> {
+    let subscriber = get_confirmed_subscriber(pool, subscriber_id)
+        .await
+        .context("Failed to retrieve the subscriber.")?
+        .ok_or(PreferencesError::InvalidLink)?;
+    let lists = get_list_options(pool, subscriber_id)
+        .await
+        .context("Failed to retrieve the lists.")?;
+    let frequencies: Vec<_> = Frequency::ALL
+        .into_iter()
+        .map(|f| FrequencyOption {
+            value: f.as_str(),
+            label: f.label(),
+            selected: f.as_str() == subscriber.frequency,
+        })
+        .collect();
+    let mut context = tera::Context::new();
+    context.insert("token", token);
+    context.insert("name", &subscriber.name);
+    context.insert("saved", &saved);
+    context.insert("lists", &lists);
+    context.insert("frequencies", &frequencies);
+    // The unsubscribe token is alphanumeric, it is safe to embed as is.
+    context.insert(
+        "unsubscribe_link",
+        &format!(
+            "/subscriptions/unsubscribe?token={}",
+            subscriber.unsubscribe_token
+        ),
+    );
+    let body = templates
+        .render_page("preferences/index.html", &context)
+        .context("Failed to render the preference center.")?;
+    Ok(HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(body))
+}
+
+struct Subscriber {
+    id: Uuid,
+    name: String,
+    frequency: String,
+    unsubscribe_token: String,
+}
+
+#[tracing::instrument(name = "Get confirmed subscriber", skip(pool))]
+async fn get_confirmed_subscriber(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Option<Subscriber>, sqlx::Error> {
+    sqlx::query_as!(
+        Subscriber,
+        r#"
+        SELECT id, name, frequency, unsubscribe_token
+        FROM subscriptions
+        WHERE id = $1 AND status = 'confirmed'
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+#[tracing::instrument(name = "Get confirmed subscriber by email", skip(pool, email))]
+async fn get_confirmed_subscriber_by_email(
+    pool: &PgPool,
+    email: &SubscriberEmail,
+) -> Result<Option<Subscriber>, sqlx::Error> {
+    sqlx::query_as!(
+        Subscriber,
+        r#"
+        SELECT id, name, frequency, unsubscribe_token
+        FROM subscriptions
+        WHERE email = $1 AND status = 'confirmed'
+        "#,
+        email.as_ref()
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+/// Every list, selected if the subscriber is a confirmed member.
+#[tracing::instrument(skip(pool))]
+async fn get_list_options(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Vec<ListOption>, sqlx::Error> {
+    sqlx::query_as!(
+        ListOption,
+        r#"
+        SELECT l.slug, l.name, (m.status IS NOT NULL AND m.status = 'confirmed') AS "selected!"
+        FROM lists l
+        LEFT JOIN list_memberships m ON m.list_id = l.list_id AND m.subscriber_id = $1
+        ORDER BY l.slug
+        "#,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+#[tracing::instrument(skip(transaction, preferences))]
+async fn update_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    preferences: &Preferences,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"UPDATE subscriptions SET name = $2, frequency = $3 WHERE id = $1"#,
+        subscriber_id,
+        preferences.name.as_ref(),
+        preferences.frequency.as_str()
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+/// Join the selected lists and leave every other one. Leaving a list counts
+/// as unsubscribing in the delivery log.
+#[tracing::instrument(skip(transaction))]
+async fn update_memberships(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    list_ids: &[Uuid],
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO list_memberships (subscriber_id, list_id, status, subscribed_at)
+        SELECT $1, list_id, 'confirmed', $3
+        FROM UNNEST($2::uuid[]) AS list_id
+        ON CONFLICT (subscriber_id, list_id) DO UPDATE SET status = 'confirmed'
+        "#,
+        subscriber_id,
+        list_ids,
+        Utc::now()
+    )
+    .execute(&mut *transaction)
+    .await?;
+    let left = sqlx::query!(
+        r#"
+        UPDATE list_memberships SET status = 'unsubscribed'
+        WHERE subscriber_id = $1 AND list_id <> ALL($2::uuid[]) AND status <> 'unsubscribed'
+        "#,
+        subscriber_id,
+        list_ids
+    )
+    .execute(&mut *transaction)
+    .await?
+    .rows_affected();
+    if left > 0 {
+        log_unsubscribe(transaction, subscriber_id).await?;
+    }
+    Ok(())
+}
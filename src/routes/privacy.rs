@@ -0,0 +1,643 @@
+use actix_web::http::header::{HeaderValue, WWW_AUTHENTICATE};
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::domain::SubscriberEmail;
+use crate::email_client::{EmailSender, SendEmailError};
+use crate::routes::{authenticate, error_chain_fmt, send_in_background, PublishError};
+use crate::signed_token::{self, SignedTokenError};
+use crate::startup::{ApplicationBaseUrl, HmacSecret, PrivacyLinkTtl};
+use crate::suppressions::{suppress, SuppressionReason};
+use crate::templates::Templates;
+
+/// What data export links are signed for, see `signed_token`.
+const EXPORT_PURPOSE: &str = "export";
+/// What erasure links are signed for, see `signed_token`.
+const ERASURE_PURPOSE: &str = "erasure";
+
+#[derive(serde::Deserialize)]
+pub struct PrivacyLinkFormData {
+    email: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct PrivacyLinkParameters {
+    token: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct SubscriberParameters {
+    email: String,
+}
+
+#[derive(thiserror::Error)]
+pub enum PrivacyError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("Authentication failed.")]
+    AuthError(#[source] anyhow::Error),
+    #[error("This link is not valid.")]
+    InvalidLink,
+    #[error("This link has expired, please ask for a new one.")]
+    ExpiredLink,
+    #[error("We hold no data about this address.")]
+    UnknownSubscriber,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for PrivacyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl From<PublishError> for PrivacyError {
+    fn from(e: PublishError) -> Self {
+        match e {
+            PublishError::AuthError(e) => PrivacyError::AuthError(e),
+            e => PrivacyError::UnexpectedError(e.into()),
+        }
+    }
+}
+
+impl From<SignedTokenError> for PrivacyError {
+    fn from(e: SignedTokenError) -> Self {
+        match e {
+            SignedTokenError::Invalid => PrivacyError::InvalidLink,
+            SignedTokenError::Expired => PrivacyError::ExpiredLink,
+        }
+    }
+}
+
+impl ResponseError for PrivacyError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            PrivacyError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            PrivacyError::AuthError(_) => StatusCode::UNAUTHORIZED,
+            PrivacyError::InvalidLink | PrivacyError::UnknownSubscriber => StatusCode::NOT_FOUND,
+            PrivacyError::ExpiredLink => StatusCode::GONE,
+            PrivacyError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            PrivacyError::AuthError(_) => {
+                let mut response = HttpResponse::new(StatusCode::UNAUTHORIZED);
+                let header_value = HeaderValue::from_str(r#"Basic realm="publish""#).unwrap();
+                response
+                    .headers_mut()
+                    .insert(WWW_AUTHENTICATE, header_value);
+                response
+            }
+            _ => HttpResponse::build(self.status_code()).body(self.to_string()),
+        }
+    }
+}
+
+/// Email a link to download everything we hold about the address.
+#[tracing::instrument(
+    name = "Send a data export link",
+    skip(form, pool, email_client, templates, base_url, hmac_secret, link_ttl),
+    fields(subscriber_email = %form.email)
+)]
+pub async fn request_data_export_link(
+    form: web::Form<PrivacyLinkFormData>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<dyn EmailSender>,
+    templates: web::Data<Templates>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    hmac_secret: web::Data<HmacSecret>,
+    link_ttl: web::Data<PrivacyLinkTtl>,
+) -> Result<HttpResponse, PrivacyError> {
+    let link = PrivacyLink {
+        email: "data_export_link",
+        purpose: EXPORT_PURPOSE,
+        path: "/privacy/export",
+    };
+    let email = SubscriberEmail::parse(form.0.email).map_err(PrivacyError::ValidationError)?;
+    send_in_background(async move {
+        send_privacy_link(
+            &link,
+            &email,
+            &pool,
+            email_client.get_ref(),
+            &templates,
+            &base_url.0,
+            &hmac_secret,
+            link_ttl.0,
+        )
+        .await
+    });
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Email a link to erase everything we hold about the address.
+#[tracing::instrument(
+    name = "Send an erasure link",
+    skip(form, pool, email_client, templates, base_url, hmac_secret, link_ttl),
+    fields(subscriber_email = %form.email)
+)]
+pub async fn request_erasure_link(
+    form: web::Form<PrivacyLinkFormData>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<dyn EmailSender>,
+    templates: web::Data<Templates>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    hmac_secret: web::Data<HmacSecret>,
+    link_ttl: web::Data<PrivacyLinkTtl>,
+) -> Result<HttpResponse, PrivacyError> {
+    let link = PrivacyLink {
+        email: "erasure_link",
+        purpose: ERASURE_PURPOSE,
+        path: "/privacy/erasure",
+    };
+    let email = SubscriberEmail::parse(form.0.email).map_err(PrivacyError::ValidationError)?;
+    send_in_background(async move {
+        send_privacy_link(
+            &link,
+            &email,
+            &pool,
+            email_client.get_ref(),
+            &templates,
+            &base_url.0,
+            &hmac_secret,
+            link_ttl.0,
+        )
+        .await
+    });
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Everything we hold about the subscriber a data export link was sent to.
+#[tracing::instrument(name = "Export subscriber data", skip(parameters, pool, hmac_secret))]
+pub async fn export_data(
+    parameters: web::Query<PrivacyLinkParameters>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, PrivacyError> {
+    let subscriber_id = verify_link(&hmac_secret, EXPORT_PURPOSE, &parameters.token)?;
+    let data = get_subscriber_data(&pool, subscriber_id)
+        .await?
+        .ok_or(PrivacyError::UnknownSubscriber)?;
+    Ok(HttpResponse::Ok().json(data))
+}
+
+/// Ask for confirmation: following an erasure link must not erase anything
+/// by itself, link scanners would do it on the subscriber's behalf.
+#[tracing::instrument(name = "Show the erasure form", skip(parameters, pool, hmac_secret))]
+pub async fn erasure_form(
+    parameters: web::Query<PrivacyLinkParameters>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, PrivacyError> {
+    let subscriber_id = verify_link(&hmac_secret, ERASURE_PURPOSE, &parameters.token)?;
+    get_subscriber_email(&pool, subscriber_id)
+        .await
+        .context("Failed to retrieve the subscriber associated with the link.")?
+        .ok_or(PrivacyError::UnknownSubscriber)?;
+    // The token is URL-safe base64, it is safe to embed as is.
+    Ok(HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Erase your data</title>
+</head>
+<body>
+    <p>Do you want us to erase everything we hold about you? This cannot be undone, and you will not be able to subscribe again with this address.</p>
+    <form action="/privacy/erasure?token={}" method="post">
+        <button type="submit">Erase my data</button>
+    </form>
+</body>
+</html>"#,
+            parameters.token
+        )))
+}
+
+#[tracing::instrument(name = "Erase subscriber data", skip(parameters, pool, hmac_secret))]
+pub async fn erase_data(
+    parameters: web::Query<PrivacyLinkParameters>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, PrivacyError> {
+    let subscriber_id = verify_link(&hmac_secret, ERASURE_PURPOSE, &parameters.token)?;
+    erase_subscriber(&pool, subscriber_id, "subscriber").await?;
+    Ok(HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Data erased</title>
+</head>
+<body>
+    <p>Everything we held about you has been erased.</p>
+</body>
+</html>"#,
+        ))
+}
+
+/// Everything we hold about an address, on behalf of its owner.
+#[tracing::instrument(
+    name = "Export subscriber data on behalf of an admin",
+    skip(parameters, pool, request),
+    fields(username = tracing::field::Empty, user_id = tracing::field::Empty)
+)]
+pub async fn admin_export_data(
+    parameters: web::Query<SubscriberParameters>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, PrivacyError> {
+    authenticate(&request, &pool).await?;
+    let subscriber_id = get_subscriber_id_by_email(&pool, &parameters.email).await?;
+    let data = get_subscriber_data(&pool, subscriber_id)
+        .await?
+        .ok_or(PrivacyError::UnknownSubscriber)?;
+    Ok(HttpResponse::Ok().json(data))
+}
+
+/// Erase everything we hold about an address, on behalf of its owner.
+#[tracing::instrument(
+    name = "Erase subscriber data on behalf of an admin",
+    skip(parameters, pool, request),
+    fields(username = tracing::field::Empty, user_id = tracing::field::Empty)
+)]
+pub async fn admin_erase_data(
+    parameters: web::Query<SubscriberParameters>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, PrivacyError> {
+    authenticate(&request, &pool).await?;
+    let subscriber_id = get_subscriber_id_by_email(&pool, &parameters.email).await?;
+    erase_subscriber(&pool, subscriber_id, "admin").await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// One of the links subscribers can ask for to exercise their rights.
+struct PrivacyLink {
+    /// The email template the link is sent with.
+    email: &'static str,
+    purpose: &'static str,
+    path: &'static str,
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn send_privacy_link(
+    link: &PrivacyLink,
+    email: &SubscriberEmail,
+    pool: &PgPool,
+    email_client: &dyn EmailSender,
+    templates: &Templates,
+    base_url: &str,
+    hmac_secret: &HmacSecret,
+    link_ttl: chrono::Duration,
+) -> Result<(), anyhow::Error> {
+    let subscriber = sqlx::query!(
+        r#"SELECT id, name FROM subscriptions WHERE email = $1"#,
+        email.as_ref()
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to look up the subscriber in the database.")?;
+    let subscriber = match subscriber {
+        Some(subscriber) => subscriber,
+        None => return Ok(()),
+    };
+    let token = signed_token::sign(
+        &hmac_secret.0,
+        link.purpose,
+        &subscriber.id.to_string(),
+        Utc::now() + link_ttl,
+    );
+    let mut context = tera::Context::new();
+    context.insert("base_url", base_url);
+    context.insert("name", &subscriber.name);
+    context.insert(
+        "link",
+        &format!("{}{}?token={}", base_url, link.path, token),
+    );
+    context.insert("link_ttl_minutes", &link_ttl.num_minutes());
+    let rendered = templates
+        .render_email(link.email, &context)
+        .with_context(|| format!("Failed to render the `{}` email.", link.email))?;
+    match email_client
+        .send_email(email, &rendered.subject, &rendered.html, &rendered.text)
+        .await
+    {
+        // Nobody is waiting for the outcome: a suppressed address is not an error.
+        Ok(()) | Err(SendEmailError::Suppressed) => Ok(()),
+        Err(e) => Err(e).with_context(|| format!("Failed to send the `{}` email.", link.email)),
+    }
+}
+
+fn verify_link(hmac_secret: &HmacSecret, purpose: &str, token: &str) -> Result<Uuid, PrivacyError> {
+    let subscriber_id = signed_token::verify(&hmac_secret.0, purpose, token)?;
+    Uuid::parse_str(&subscriber_id).map_err(|_| PrivacyError::InvalidLink)
+}
+
+async fn get_subscriber_id_by_email(pool: &PgPool, email: &str) -> Result<Uuid, PrivacyError> {
+    let email = SubscriberEmail::parse(email.to_owned()).map_err(PrivacyError::ValidationError)?;
+    let subscriber = sqlx::query!(
+        r#"SELECT id FROM subscriptions WHERE email = $1"#,
+        email.as_ref()
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to look up the subscriber in the database.")?
+    .ok_or(PrivacyError::UnknownSubscriber)?;
+    Ok(subscriber.id)
+}
+
+#[tracing::instrument(skip(pool))]
+async fn get_subscriber_email(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Option<String>, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"SELECT email FROM subscriptions WHERE id = $1"#,
+        subscriber_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(row.map(|r| r.email))
+}
+
+/// Everything we hold about a subscriber.
+#[derive(serde::Serialize)]
+pub struct SubscriberData {
+    exported_at: DateTime<Utc>,
+    subscription: Subscription,
+    list_memberships: Vec<ListMembership>,
+    subscription_tokens: Vec<SubscriptionToken>,
+    /// Issues waiting in the delivery queue for them.
+    pending_deliveries: Vec<PendingDelivery>,
+    /// As reported by the email provider.
+    bounces: Vec<Bounce>,
+    /// Issues they opened, as recorded by the tracking pixel.
+    opens: Vec<Open>,
+    /// Links of issues they clicked, as recorded by click tracking.
+    clicks: Vec<Click>,
+    /// What happened to every issue enqueued for them.
+    deliveries: Vec<Delivery>,
+}
+
+#[derive(serde::Serialize)]
+struct Subscription {
+    id: Uuid,
+    email: String,
+    name: String,
+    status: String,
+    subscribed_at: DateTime<Utc>,
+    frequency: String,
+    last_emailed_at: Option<DateTime<Utc>>,
+    unsubscribe_token: String,
+    soft_bounce_count: i32,
+}
+
+#[derive(serde::Serialize)]
+struct ListMembership {
+    list: String,
+    status: String,
+    subscribed_at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize)]
+struct SubscriptionToken {
+    subscription_token: String,
+    list: String,
+    created_at: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+    consumed_at: Option<DateTime<Utc>>,
+}
+
+#[derive(serde::Serialize)]
+struct PendingDelivery {
+    newsletter_issue_id: Uuid,
+    title: String,
+}
+
+#[derive(serde::Serialize)]
+struct Bounce {
+    kind: String,
+    bounce_type: String,
+    details: String,
+    bounced_at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize)]
+struct Open {
+    newsletter_issue_id: Uuid,
+    title: String,
+    open_count: i32,
+    first_opened_at: DateTime<Utc>,
+    last_opened_at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize)]
+struct Click {
+    newsletter_issue_id: Uuid,
+    title: String,
+    url: String,
+    click_count: i32,
+    first_clicked_at: DateTime<Utc>,
+    last_clicked_at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize)]
+struct Delivery {
+    newsletter_issue_id: Uuid,
+    title: String,
+    status: String,
+    enqueued_at: DateTime<Utc>,
+    attempted_at: Option<DateTime<Utc>>,
+    bounced_at: Option<DateTime<Utc>>,
+    unsubscribed_at: Option<DateTime<Utc>>,
+}
+
+#[tracing::instrument(skip(pool))]
+async fn get_subscriber_data(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Option<SubscriberData>, PrivacyError> {
+    let subscription = sqlx::query_as!(
+        Subscription,
+        r#"
+        SELECT
+            id, email, name, status, subscribed_at, frequency, last_emailed_at, unsubscribe_token,
+            soft_bounce_count
+        FROM subscriptions
+        WHERE id = $1
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve the subscriber.")?;
+    let subscription = match subscription {
+        Some(subscription) => subscription,
+        None => return Ok(None),
+    };
+    let list_memberships = sqlx::query_as!(
+        ListMembership,
+        r#"
+        SELECT l.slug AS list, m.status, m.subscribed_at
+        FROM list_memberships m
+        JOIN lists l ON l.list_id = m.list_id
+        WHERE m.subscriber_id = $1
+        ORDER BY l.slug
+        "#,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the list memberships.")?;
+    let subscription_tokens = sqlx::query_as!(
+        SubscriptionToken,
+        r#"
+        SELECT t.subscription_token, l.slug AS list, t.created_at, t.expires_at, t.consumed_at
+        FROM subscription_tokens t
+        JOIN lists l ON l.list_id = t.list_id
+        WHERE t.subscriber_id = $1
+        ORDER BY t.created_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the subscription tokens.")?;
+    let pending_deliveries = sqlx::query_as!(
+        PendingDelivery,
+        r#"
+        SELECT q.newsletter_issue_id, i.title
+        FROM issue_delivery_queue q
+        JOIN newsletter_issues i ON i.newsletter_issue_id = q.newsletter_issue_id
+        WHERE q.subscriber_email = $1
+        ORDER BY i.published_at
+        "#,
+        subscription.email
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the pending deliveries.")?;
+    let bounces = sqlx::query_as!(
+        Bounce,
+        r#"
+        SELECT kind, bounce_type, details, bounced_at
+        FROM email_bounces
+        WHERE subscriber_id = $1
+        ORDER BY bounced_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the bounces.")?;
+    let opens = sqlx::query_as!(
+        Open,
+        r#"
+        SELECT o.newsletter_issue_id, i.title, o.open_count, o.first_opened_at, o.last_opened_at
+        FROM email_opens o
+        JOIN newsletter_issues i ON i.newsletter_issue_id = o.newsletter_issue_id
+        WHERE o.subscriber_id = $1
+        ORDER BY o.first_opened_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the opens.")?;
+    let clicks = sqlx::query_as!(
+        Click,
+        r#"
+        SELECT
+            c.newsletter_issue_id, i.title, c.url, c.click_count, c.first_clicked_at,
+            c.last_clicked_at
+        FROM email_clicks c
+        JOIN newsletter_issues i ON i.newsletter_issue_id = c.newsletter_issue_id
+        WHERE c.subscriber_id = $1
+        ORDER BY c.first_clicked_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the clicks.")?;
+    let deliveries = sqlx::query_as!(
+        Delivery,
+        r#"
+        SELECT
+            d.newsletter_issue_id, i.title, d.status, d.enqueued_at, d.attempted_at,
+            d.bounced_at, d.unsubscribed_at
+        FROM issue_deliveries d
+        JOIN newsletter_issues i ON i.newsletter_issue_id = d.newsletter_issue_id
+        WHERE d.subscriber_id = $1
+        ORDER BY d.enqueued_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the deliveries.")?;
+    Ok(Some(SubscriberData {
+        exported_at: Utc::now(),
+        subscription,
+        list_memberships,
+        subscription_tokens,
+        pending_deliveries,
+        bounces,
+        opens,
+        clicks,
+        deliveries,
+    }))
+}
+
+/// Delete everything we hold about a subscriber, leaving only a hash of
+/// their address behind so that they are not subscribed again.
+///
+/// List memberships, confirmation tokens, bounces, opens, clicks and the
+/// delivery log go along with the subscription.
+#[tracing::instrument(skip(pool))]
+async fn erase_subscriber(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+    source: &str,
+) -> Result<(), PrivacyError> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let erased = sqlx::query!(
+        r#"DELETE FROM subscriptions WHERE id = $1 RETURNING email"#,
+        subscriber_id
+    )
+    .fetch_optional(&mut transaction)
+    .await
+    .context("Failed to delete the subscriber.")?
+    .ok_or(PrivacyError::UnknownSubscriber)?;
+    sqlx::query!(
+        r#"DELETE FROM issue_delivery_queue WHERE subscriber_email = $1"#,
+        erased.email
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to delete the pending deliveries.")?;
+    // Addresses are validated on the way in, only a corrupted row can fail here.
+    let email = SubscriberEmail::parse(erased.email).map_err(anyhow::Error::msg)?;
+    suppress(&mut transaction, &email, SuppressionReason::Erasure, source)
+        .await
+        .context("Failed to record the erased address.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to erase a subscriber.")?;
+    Ok(())
+}
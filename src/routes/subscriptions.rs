@@ -9,14 +9,41 @@ use rand::{thread_rng, Rng};
 use sqlx::{PgPool, Postgres, Transaction};
 use uuid::Uuid;
 
-use crate::domain::{NewSubscriber, SubscriberEmail, SubscriberName};
-use crate::email_client::EmailClient;
-use crate::startup::ApplicationBaseUrl;
+use crate::bot_protection::{BotCheckError, BotProtection, Submission};
+use crate::domain::{Frequency, ListSlug, NewSubscriber, SubscriberEmail, SubscriberName};
+use crate::email_client::EmailSender;
+use crate::routes::{get_list_by_slug, MailingList};
+use crate::startup::{ApplicationBaseUrl, SubscriptionTokenTtl};
+use crate::suppressions::is_suppressed;
+use crate::templates::Templates;
 
 #[derive(serde::Deserialize)]
 pub struct FormData {
     email: String,
     name: String,
+    /// The slug of the list to join, the default list if missing.
+    list: Option<String>,
+    /// Hidden from people, only bots fill it in.
+    website: Option<String>,
+    /// Handed out by `GET /subscriptions/form_token` along with the form.
+    form_token: Option<String>,
+    /// The answer of the challenge widget, under the name the widget gives it.
+    #[serde(
+        alias = "h-captcha-response",
+        alias = "cf-turnstile-response",
+        alias = "g-recaptcha-response"
+    )]
+    challenge_response: Option<String>,
+}
+
+impl FormData {
+    fn submission(&self) -> Submission<'_> {
+        Submission {
+            honeypot: self.website.as_deref(),
+            form_token: self.form_token.as_deref(),
+            challenge_response: self.challenge_response.as_deref(),
+        }
+    }
 }
 
 impl TryFrom<FormData> for NewSubscriber {
@@ -25,7 +52,8 @@ impl TryFrom<FormData> for NewSubscriber {
     fn try_from(value: FormData) -> Result<Self, Self::Error> {
         let name = SubscriberName::parse(value.name)?;
         let email = SubscriberEmail::parse(value.email)?;
-        Ok(Self { email, name })
+        let list = value.list.map(ListSlug::parse).transpose()?.unwrap_or_default();
+        Ok(Self { email, name, list })
     }
 }
 
@@ -33,10 +61,22 @@ impl TryFrom<FormData> for NewSubscriber {
 pub enum SubscribeError {
     #[error("{0}")]
     ValidationError(String),
+    // Says nothing of which check failed, that is for our logs only.
+    #[error("We could not process your subscription.")]
+    SuspectedBot(#[source] BotCheckError),
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
 
+impl From<BotCheckError> for SubscribeError {
+    fn from(e: BotCheckError) -> Self {
+        match e {
+            BotCheckError::Unexpected(e) => SubscribeError::UnexpectedError(e),
+            e @ BotCheckError::Suspicious(_) => SubscribeError::SuspectedBot(e),
+        }
+    }
+}
+
 impl std::fmt::Debug for SubscribeError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         error_chain_fmt(self, f)
@@ -46,7 +86,9 @@ impl std::fmt::Debug for SubscribeError {
 impl ResponseError for SubscribeError {
     fn status_code(&self) -> StatusCode {
         match self {
-            SubscribeError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            SubscribeError::ValidationError(_) | SubscribeError::SuspectedBot(_) => {
+                StatusCode::BAD_REQUEST
+            }
             SubscribeError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
@@ -54,7 +96,7 @@ impl ResponseError for SubscribeError {
 
 #[tracing::instrument(
 name = "Adding a new subscriber",
-skip(form, pool, email_client, base_url),
+skip(form, pool, email_client, templates, base_url, token_ttl, bot_protection),
 fields(
 subscriber_email = %form.email,
 subscriber_name = %form.name
@@ -63,28 +105,101 @@ subscriber_name = %form.name
 pub async fn subscribe(
     form: web::Form<FormData>,
     pool: web::Data<PgPool>,
-    email_client: web::Data<EmailClient>,
+    email_client: web::Data<dyn EmailSender>,
+    templates: web::Data<Templates>,
     base_url: web::Data<ApplicationBaseUrl>,
+    token_ttl: web::Data<SubscriptionTokenTtl>,
+    bot_protection: web::Data<BotProtection>,
 ) -> Result<HttpResponse, SubscribeError> {
-    let new_subscriber = form.0.try_into().map_err(SubscribeError::ValidationError)?;
+    // Before validating anything, so that bots do not learn from our answers
+    bot_protection.check(&form.submission()).await?;
+    let new_subscriber: NewSubscriber = form.0.try_into().map_err(SubscribeError::ValidationError)?;
+    // Answer as usual, so the response does not reveal which addresses are suppressed.
+    if is_suppressed(&pool, &new_subscriber.email)
+        .await
+        .context("Failed to look up the suppressed addresses.")?
+    {
+        return Ok(HttpResponse::Ok().finish());
+    }
+    let list = get_list_by_slug(&pool, &new_subscriber.list)
+        .await
+        .context("Failed to look up the list in the database.")?
+        .ok_or_else(|| {
+            SubscribeError::ValidationError(format!(
+                "There is no `{}` list.",
+                new_subscriber.list.as_ref()
+            ))
+        })?;
     let mut transaction = pool
         .begin()
         .await
         .context("Failed to acquire a Postgres connection from the pool")?;
-    let subscriber_id = insert_subscriber(&mut transaction, &new_subscriber)
+    // Inserting first, so that concurrent signups for a new address do not
+    // both try to create it: the second one waits, then finds it.
+    let inserted = insert_subscriber(&mut transaction, &new_subscriber)
         .await
         .context("Failed to insert new subscriber in the database.")?;
-    let subscription_token = generate_subscription_token();
-    store_token(&mut transaction, subscriber_id, &subscription_token)
+    let subscriber_id = match inserted {
+        Some(subscriber_id) => subscriber_id,
+        None => {
+            let (subscriber_id, status) =
+                get_subscriber_by_email(&mut transaction, &new_subscriber.email)
+                    .await
+                    .context("Failed to look up the subscriber in the database.")?
+                    .context("The subscriber was deleted while subscribing.")?;
+            let membership_status =
+                get_membership_status(&mut transaction, subscriber_id, list.list_id)
+                    .await
+                    .context("Failed to look up the list membership in the database.")?;
+            // Answer as for a new address, so the response does not reveal who is subscribed.
+            if status == "confirmed" && membership_status.as_deref() == Some("confirmed") {
+                send_already_subscribed_email(
+                    email_client.get_ref(),
+                    &templates,
+                    new_subscriber,
+                    &list,
+                    &base_url.0,
+                )
+                .await
+                .context("Failed to send an 'already subscribed' email.")?;
+                return Ok(HttpResponse::Ok().finish());
+            }
+            // They lost their confirmation email, had unsubscribed, or are joining
+            // another list: start over for this list. An address confirmed through
+            // another list stays confirmed.
+            if status != "confirmed" {
+                reset_subscriber_to_pending(&mut transaction, subscriber_id)
+                    .await
+                    .context("Failed to reset the subscriber to pending confirmation.")?;
+            }
+            invalidate_tokens(&mut transaction, subscriber_id, list.list_id)
+                .await
+                .context("Failed to invalidate previous confirmation tokens.")?;
+            subscriber_id
+        }
+    };
+    upsert_pending_membership(&mut transaction, subscriber_id, list.list_id)
         .await
-        .context("Failed to store the confirmation token for a new subscriber.")?;
+        .context("Failed to add the subscriber to the list.")?;
+    let subscription_token = generate_subscription_token();
+    store_token(
+        &mut transaction,
+        subscriber_id,
+        list.list_id,
+        &subscription_token,
+        token_ttl.0,
+    )
+    .await
+    .context("Failed to store the confirmation token for a new subscriber.")?;
     transaction
         .commit()
         .await
         .context("Failed to commit SQL transaction to store a new subscriber.")?;
     send_confirmation_email(
-        &email_client,
+        email_client.get_ref(),
+        &templates,
         new_subscriber,
+        &list,
         &base_url.0,
         &subscription_token,
     )
@@ -93,6 +208,18 @@ pub async fn subscribe(
     Ok(HttpResponse::Ok().finish())
 }
 
+#[derive(serde::Serialize)]
+struct FormToken {
+    form_token: String,
+}
+
+/// A token to embed in the signup form, see `BotProtection`.
+pub async fn signup_form_token(bot_protection: web::Data<BotProtection>) -> HttpResponse {
+    HttpResponse::Ok().json(FormToken {
+        form_token: bot_protection.form_token(),
+    })
+}
+
 fn generate_subscription_token() -> String {
     let mut rng = thread_rng();
     std::iter::repeat_with(|| rng.sample(Alphanumeric))
@@ -103,31 +230,159 @@ fn generate_subscription_token() -> String {
 
 #[tracing::instrument(
     name = "Send a confirmation email to a new subscriber",
-    skip(email_client, new_subscriber, base_url, subscription_token)
+    skip(email_client, templates, new_subscriber, list, base_url, subscription_token)
 )]
 pub async fn send_confirmation_email(
-    email_client: &EmailClient,
+    email_client: &dyn EmailSender,
+    templates: &Templates,
     new_subscriber: NewSubscriber,
+    list: &MailingList,
     base_url: &str,
     subscription_token: &str,
-) -> Result<(), reqwest::Error> {
+) -> Result<(), anyhow::Error> {
     let confirmation_link = format!(
         "{}/subscriptions/confirm?subscription_token={}",
         base_url, subscription_token
     );
-    let plain_body = format!(
-        "Welcome to our newsletter!\nVisit {} to confirm your subscription.",
-        confirmation_link
-    );
-    let html_body = format!(
-        "Welcome to our newsletter!<br />Click <a href=\"{}\">here</a> to confirm your subscription.",
-        confirmation_link
-    );
+    let mut context = tera::Context::new();
+    context.insert("base_url", base_url);
+    context.insert("name", new_subscriber.name.as_ref());
+    context.insert("list_name", &list.name);
+    context.insert("confirmation_link", &confirmation_link);
+    let email = templates
+        .render_email("confirmation", &context)
+        .context("Failed to render the confirmation email.")?;
     email_client
-        .send_email(&new_subscriber.email, "Welcome!", &html_body, &plain_body)
-        .await
+        .send_email(&new_subscriber.email, &email.subject, &email.html, &email.text)
+        .await?;
+    Ok(())
+}
+
+#[tracing::instrument(
+    name = "Send an 'already subscribed' email",
+    skip(email_client, templates, new_subscriber, list, base_url)
+)]
+pub async fn send_already_subscribed_email(
+    email_client: &dyn EmailSender,
+    templates: &Templates,
+    new_subscriber: NewSubscriber,
+    list: &MailingList,
+    base_url: &str,
+) -> Result<(), anyhow::Error> {
+    let mut context = tera::Context::new();
+    context.insert("base_url", base_url);
+    context.insert("name", new_subscriber.name.as_ref());
+    context.insert("list_name", &list.name);
+    let email = templates
+        .render_email("already_subscribed", &context)
+        .context("Failed to render the 'already subscribed' email.")?;
+    email_client
+        .send_email(&new_subscriber.email, &email.subject, &email.html, &email.text)
+        .await?;
+    Ok(())
+}
+
+#[tracing::instrument(
+    name = "Look up a subscriber by email",
+    skip(email, transaction)
+)]
+async fn get_subscriber_by_email(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &SubscriberEmail,
+) -> Result<Option<(Uuid, String)>, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"SELECT id, status FROM subscriptions WHERE email = $1 FOR UPDATE"#,
+        email.as_ref(),
+    )
+    .fetch_optional(transaction)
+    .await?;
+    Ok(result.map(|r| (r.id, r.status)))
 }
 
+#[tracing::instrument(
+    name = "Reset subscriber to pending confirmation",
+    skip(transaction)
+)]
+async fn reset_subscriber_to_pending(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions SET status = 'pending_confirmation', soft_bounce_count = 0
+        WHERE id = $1
+        "#,
+        subscriber_id
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(name = "Look up a list membership", skip(transaction))]
+async fn get_membership_status(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    list_id: Uuid,
+) -> Result<Option<String>, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        SELECT status FROM list_memberships
+        WHERE subscriber_id = $1 AND list_id = $2
+        FOR UPDATE
+        "#,
+        subscriber_id,
+        list_id
+    )
+    .fetch_optional(transaction)
+    .await?;
+    Ok(result.map(|r| r.status))
+}
+
+/// Add the subscriber to the list, pending confirmation.
+#[tracing::instrument(name = "Add a pending list membership", skip(transaction))]
+async fn upsert_pending_membership(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    list_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO list_memberships (subscriber_id, list_id, status, subscribed_at)
+        VALUES ($1, $2, 'pending_confirmation', $3)
+        ON CONFLICT (subscriber_id, list_id) DO UPDATE SET status = 'pending_confirmation'
+        "#,
+        subscriber_id,
+        list_id,
+        Utc::now()
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+/// Expire the confirmation links sent so far for this list, only the newest
+/// one stays valid.
+#[tracing::instrument(name = "Invalidate previous subscription tokens", skip(transaction))]
+async fn invalidate_tokens(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    list_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+    UPDATE subscription_tokens SET expires_at = now()
+    WHERE subscriber_id = $1 AND list_id = $2 AND consumed_at IS NULL AND expires_at > now()
+        "#,
+        subscriber_id,
+        list_id
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+/// `None` if there already is a subscriber with this address.
 #[tracing::instrument(
     name = "Saving new subscriber details in the database",
     skip(new_subscriber, transaction)
@@ -135,21 +390,26 @@ pub async fn send_confirmation_email(
 pub async fn insert_subscriber(
     transaction: &mut Transaction<'_, Postgres>,
     new_subscriber: &NewSubscriber,
-) -> Result<Uuid, sqlx::Error> {
-    let subscriber_id = Uuid::new_v4();
-    sqlx::query!(
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let result = sqlx::query!(
         r#"
-    INSERT INTO subscriptions (id, email, name, subscribed_at, status)
-    VALUES ($1, $2, $3, $4, 'pending_confirmation')
+    INSERT INTO subscriptions (
+        id, email, name, subscribed_at, status, unsubscribe_token, frequency
+    )
+    VALUES ($1, $2, $3, $4, 'pending_confirmation', $5, $6)
+    ON CONFLICT (email) DO NOTHING
+    RETURNING id
             "#,
-        subscriber_id,
+        Uuid::new_v4(),
         new_subscriber.email.as_ref(),
         new_subscriber.name.as_ref(),
-        Utc::now()
+        Utc::now(),
+        generate_subscription_token(),
+        Frequency::EveryIssue.as_str()
     )
-    .execute(transaction)
+    .fetch_optional(transaction)
     .await?;
-    Ok(subscriber_id)
+    Ok(result.map(|r| r.id))
 }
 
 #[tracing::instrument(
@@ -159,15 +419,23 @@ pub async fn insert_subscriber(
 pub async fn store_token(
     transaction: &mut Transaction<'_, Postgres>,
     subscriber_id: Uuid,
+    list_id: Uuid,
     subscription_token: &str,
+    time_to_live: chrono::Duration,
 ) -> Result<(), StoreTokenError> {
+    let created_at = Utc::now();
     sqlx::query!(
         r#"
-    INSERT INTO subscription_tokens (subscription_token, subscriber_id)
-    VALUES ($1, $2)
+    INSERT INTO subscription_tokens (
+        subscription_token, subscriber_id, list_id, created_at, expires_at
+    )
+    VALUES ($1, $2, $3, $4, $5)
         "#,
         subscription_token,
-        subscriber_id
+        subscriber_id,
+        list_id,
+        created_at,
+        created_at + time_to_live
     )
     .execute(transaction)
     .await
@@ -1,62 +1,177 @@
-use actix_web::{web, HttpResponse};
-use sqlx::PgPool;
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse, ResponseError};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{PgPool, Postgres, Transaction};
 use uuid::Uuid;
 
+use crate::routes::error_chain_fmt;
+
 #[derive(serde::Deserialize)]
 pub struct Parameters {
     subscription_token: String,
 }
 
-#[tracing::instrument(name = "Confirm a pending subscriber", skip(parameters, pool))]
-pub async fn confirm(parameters: web::Query<Parameters>, pool: web::Data<PgPool>) -> HttpResponse {
-    dbg!("subscribing");
-
-    let subscriber_id =
-        match get_subscriber_id_from_token(&pool, &parameters.subscription_token).await {
-            Ok(id) => id,
-            Err(_) => return HttpResponse::BadRequest().body("Invalid subscription token"),
-        };
-    match subscriber_id {
-        None => HttpResponse::Unauthorized().finish(),
-        Some(subscriber_id) => {
-            if confirm_subscriber(&pool, subscriber_id).await.is_err() {
-                HttpResponse::InternalServerError().finish();
-            }
-            HttpResponse::Ok().finish()
+#[derive(thiserror::Error)]
+pub enum ConfirmError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("There is no subscriber associated with the provided token.")]
+    UnknownToken,
+    #[error("This confirmation link has expired.")]
+    ExpiredToken,
+    #[error("This confirmation link has already been used.")]
+    ConsumedToken,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for ConfirmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for ConfirmError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ConfirmError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            ConfirmError::UnknownToken => StatusCode::NOT_FOUND,
+            ConfirmError::ExpiredToken | ConfirmError::ConsumedToken => StatusCode::GONE,
+            ConfirmError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
 }
 
-#[tracing::instrument(name = "Mark subscriber as confirmed", skip(subscriber_id, pool))]
-async fn confirm_subscriber(pool: &PgPool, subscriber_id: Uuid) -> Result<(), sqlx::Error> {
+#[tracing::instrument(name = "Confirm a pending subscriber", skip(parameters, pool))]
+pub async fn confirm(
+    parameters: web::Query<Parameters>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ConfirmError> {
+    let subscription_token = &parameters.subscription_token;
+    if !is_well_formed(subscription_token) {
+        return Err(ConfirmError::ValidationError(
+            "The subscription token is malformed.".into(),
+        ));
+    }
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let token = get_token(&mut transaction, subscription_token)
+        .await
+        .context("Failed to retrieve the subscriber associated with the provided token.")?
+        .ok_or(ConfirmError::UnknownToken)?;
+    // Clicking the link twice is fine, as long as the subscriber is still confirmed.
+    if token.subscriber_status == "confirmed" && token.membership_status == "confirmed" {
+        return Ok(HttpResponse::Ok().finish());
+    }
+    if token.consumed_at.is_some() {
+        return Err(ConfirmError::ConsumedToken);
+    }
+    if token.expires_at <= Utc::now() {
+        return Err(ConfirmError::ExpiredToken);
+    }
+    consume_token(&mut transaction, subscription_token)
+        .await
+        .context("Failed to mark the subscription token as consumed.")?;
+    confirm_subscriber(&mut transaction, token.subscriber_id, token.list_id)
+        .await
+        .context("Failed to update the subscriber status to `confirmed`.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to confirm a subscriber.")?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Tokens are generated as 25 alphanumeric characters.
+fn is_well_formed(subscription_token: &str) -> bool {
+    subscription_token.len() == 25
+        && subscription_token
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric())
+}
+
+/// Confirm both the address and its membership of the list the token was sent for.
+#[tracing::instrument(name = "Mark subscriber as confirmed", skip(subscriber_id, transaction))]
+async fn confirm_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    list_id: Uuid,
+) -> Result<(), sqlx::Error> {
     sqlx::query!(
-        r#"UPDATE subscriptions SET status= 'confirmed' WHERE id = $1"#,
+        r#"UPDATE subscriptions SET status = 'confirmed' WHERE id = $1"#,
         subscriber_id
     )
-        .execute(pool)
-        .await
-        .map_err(|e| {
-            tracing::error!("Failed to execute query {:?}", e);
-            e
-        })?;
+    .execute(&mut *transaction)
+    .await?;
+    sqlx::query!(
+        r#"
+        UPDATE list_memberships SET status = 'confirmed'
+        WHERE subscriber_id = $1 AND list_id = $2
+        "#,
+        subscriber_id,
+        list_id
+    )
+    .execute(transaction)
+    .await?;
     Ok(())
 }
 
-#[tracing::instrument(name = "Get subscriber_id from token", skip(subscriber_token, pool))]
-async fn get_subscriber_id_from_token(
-    pool: &PgPool,
-    subscriber_token: &str,
-) -> Result<Option<Uuid>, sqlx::Error> {
-    let result = sqlx::query!(
-        r#"SELECT subscriber_id FROM subscription_tokens WHERE subscription_token = $1"#,
-        subscriber_token,
+/// Mark the token as used, so that the confirmation link cannot be replayed.
+#[tracing::instrument(
+    name = "Mark subscription token as consumed",
+    skip(subscription_token, transaction)
+)]
+async fn consume_token(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscription_token: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"UPDATE subscription_tokens SET consumed_at = now() WHERE subscription_token = $1"#,
+        subscription_token,
     )
-        .fetch_optional(pool)
-        .await
-        .map_err(|e| {
-            tracing::error!("Failed to execute query {:?}", e);
-            e
-        })?;
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
 
-    Ok(result.map(|r| r.subscriber_id))
+struct SubscriptionToken {
+    subscriber_id: Uuid,
+    list_id: Uuid,
+    subscriber_status: String,
+    membership_status: String,
+    expires_at: DateTime<Utc>,
+    consumed_at: Option<DateTime<Utc>>,
+}
+
+/// The token, its subscriber and their membership are locked until the transaction ends,
+/// so concurrent clicks on the same link are processed one after the other.
+#[tracing::instrument(name = "Get subscription token", skip(subscription_token, transaction))]
+async fn get_token(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscription_token: &str,
+) -> Result<Option<SubscriptionToken>, sqlx::Error> {
+    let result = sqlx::query_as!(
+        SubscriptionToken,
+        r#"
+        SELECT
+            t.subscriber_id,
+            t.list_id,
+            s.status AS subscriber_status,
+            m.status AS membership_status,
+            t.expires_at,
+            t.consumed_at
+        FROM subscription_tokens t
+        JOIN subscriptions s ON s.id = t.subscriber_id
+        JOIN list_memberships m ON m.subscriber_id = t.subscriber_id AND m.list_id = t.list_id
+        WHERE t.subscription_token = $1
+        FOR UPDATE
+        "#,
+        subscription_token,
+    )
+    .fetch_optional(transaction)
+    .await?;
+    Ok(result)
 }
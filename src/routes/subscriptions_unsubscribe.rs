@@ -0,0 +1,211 @@
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse, ResponseError};
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::delivery_log::log_unsubscribe;
+use crate::domain::ListSlug;
+use crate::routes::{error_chain_fmt, get_list_by_slug, MailingList};
+
+#[derive(serde::Deserialize)]
+pub struct UnsubscribeParameters {
+    token: String,
+    /// Only leave this list, rather than every list.
+    list: Option<String>,
+}
+
+#[derive(thiserror::Error)]
+pub enum UnsubscribeError {
+    #[error("There is no subscriber associated with the provided token.")]
+    UnknownToken,
+    #[error("There is no list with the provided slug.")]
+    UnknownList,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for UnsubscribeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for UnsubscribeError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            UnsubscribeError::UnknownToken | UnsubscribeError::UnknownList => {
+                StatusCode::NOT_FOUND
+            }
+            UnsubscribeError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+/// Landing page for the link in the `List-Unsubscribe` header.
+///
+/// Mail scanners and link previewers follow links on their own, so a `GET`
+/// never changes anything: the page asks the reader to confirm through
+/// the same one-click `POST` used by mail clients.
+#[tracing::instrument(name = "Show the unsubscribe page", skip(parameters, pool))]
+pub async fn unsubscribe_form(
+    parameters: web::Query<UnsubscribeParameters>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, UnsubscribeError> {
+    get_subscriber_id_from_unsubscribe_token(&pool, &parameters.token)
+        .await
+        .context("Failed to retrieve the subscriber associated with the provided token.")?
+        .ok_or(UnsubscribeError::UnknownToken)?;
+    // The token and the list slug matched stored ones, so they only contain
+    // alphanumeric characters and dashes: they are safe to embed as is.
+    let action = match get_list(&pool, &parameters.list).await? {
+        Some(list) => format!(
+            "/subscriptions/unsubscribe?token={}&list={}",
+            parameters.token, list.slug
+        ),
+        None => format!("/subscriptions/unsubscribe?token={}", parameters.token),
+    };
+    Ok(HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Unsubscribe</title>
+</head>
+<body>
+    <p>Do you want to stop receiving our newsletter?</p>
+    <form action="{}" method="post">
+        <input type="hidden" name="List-Unsubscribe" value="One-Click">
+        <button type="submit">Unsubscribe</button>
+    </form>
+</body>
+</html>"#,
+            action
+        )))
+}
+
+/// One-click unsubscribe, as specified by RFC 8058.
+///
+/// The body (`List-Unsubscribe=One-Click`) carries no information, the token
+/// alone identifies the subscriber. Unsubscribing twice is not an error.
+///
+/// With a `list`, the subscriber only leaves that list and keeps receiving
+/// the others.
+#[tracing::instrument(name = "Unsubscribe a subscriber", skip(parameters, pool))]
+pub async fn unsubscribe(
+    parameters: web::Query<UnsubscribeParameters>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, UnsubscribeError> {
+    match get_list(&pool, &parameters.list).await? {
+        Some(list) => {
+            let subscriber_id = get_subscriber_id_from_unsubscribe_token(&pool, &parameters.token)
+                .await
+                .context("Failed to retrieve the subscriber associated with the provided token.")?
+                .ok_or(UnsubscribeError::UnknownToken)?;
+            leave_list(&pool, subscriber_id, list.list_id)
+                .await
+                .context("Failed to remove the subscriber from the list.")?;
+        }
+        None => {
+            mark_subscriber_as_unsubscribed(&pool, &parameters.token)
+                .await
+                .context("Failed to mark the subscriber as unsubscribed.")?
+                .ok_or(UnsubscribeError::UnknownToken)?;
+        }
+    }
+    Ok(HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Unsubscribed</title>
+</head>
+<body>
+    <p>You have been unsubscribed, you will not receive our newsletter anymore.</p>
+</body>
+</html>"#,
+        ))
+}
+
+/// The list named in the parameters, if any.
+async fn get_list(
+    pool: &PgPool,
+    slug: &Option<String>,
+) -> Result<Option<MailingList>, UnsubscribeError> {
+    let slug = match slug {
+        Some(slug) => ListSlug::parse(slug.clone()).map_err(|_| UnsubscribeError::UnknownList)?,
+        None => return Ok(None),
+    };
+    let list = get_list_by_slug(pool, &slug)
+        .await
+        .context("Failed to look up the list in the database.")?
+        .ok_or(UnsubscribeError::UnknownList)?;
+    Ok(Some(list))
+}
+
+#[tracing::instrument(name = "Get subscriber_id from unsubscribe token", skip(token, pool))]
+async fn get_subscriber_id_from_unsubscribe_token(
+    pool: &PgPool,
+    token: &str,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"SELECT id FROM subscriptions WHERE unsubscribe_token = $1"#,
+        token,
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(result.map(|r| r.id))
+}
+
+#[tracing::instrument(name = "Remove subscriber from a list", skip(pool))]
+async fn leave_list(pool: &PgPool, subscriber_id: Uuid, list_id: Uuid) -> Result<(), sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    sqlx::query!(
+        r#"
+        UPDATE list_memberships SET status = 'unsubscribed'
+        WHERE subscriber_id = $1 AND list_id = $2
+        "#,
+        subscriber_id,
+        list_id
+    )
+    .execute(&mut transaction)
+    .await?;
+    log_unsubscribe(&mut transaction, subscriber_id).await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+/// Leave every list, so that joining one of them again later does not bring
+/// the others back.
+#[tracing::instrument(name = "Mark subscriber as unsubscribed", skip(token, pool))]
+async fn mark_subscriber_as_unsubscribed(
+    pool: &PgPool,
+    token: &str,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    let result = sqlx::query!(
+        r#"
+        UPDATE subscriptions SET status = 'unsubscribed'
+        WHERE unsubscribe_token = $1
+        RETURNING id
+        "#,
+        token,
+    )
+    .fetch_optional(&mut transaction)
+    .await?;
+    if let Some(r) = &result {
+        sqlx::query!(
+            r#"UPDATE list_memberships SET status = 'unsubscribed' WHERE subscriber_id = $1"#,
+            r.id
+        )
+        .execute(&mut transaction)
+        .await?;
+        log_unsubscribe(&mut transaction, r.id).await?;
+    }
+    transaction.commit().await?;
+    Ok(result.map(|r| r.id))
+}
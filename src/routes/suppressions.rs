@@ -0,0 +1,203 @@
+use actix_web::http::header::{HeaderValue, WWW_AUTHENTICATE};
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+
+use crate::domain::SubscriberEmail;
+use crate::routes::{authenticate, error_chain_fmt, PublishError};
+use crate::suppressions::{hash_email, lift, suppress, SuppressionReason};
+
+#[derive(serde::Deserialize)]
+pub struct SuppressionParameters {
+    email: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct LiftParameters {
+    email: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct ImportBody {
+    emails: Vec<String>,
+    /// `manual` if missing.
+    reason: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+pub struct Suppression {
+    email_hash: String,
+    reason: String,
+    source: String,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize)]
+pub struct ImportOutcome {
+    suppressed: usize,
+    already_suppressed: usize,
+}
+
+#[derive(thiserror::Error)]
+pub enum SuppressionError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("Authentication failed.")]
+    AuthError(#[source] anyhow::Error),
+    #[error("This address is not suppressed.")]
+    NotSuppressed,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for SuppressionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl From<PublishError> for SuppressionError {
+    fn from(e: PublishError) -> Self {
+        match e {
+            PublishError::AuthError(e) => SuppressionError::AuthError(e),
+            e => SuppressionError::UnexpectedError(e.into()),
+        }
+    }
+}
+
+impl ResponseError for SuppressionError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            SuppressionError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            SuppressionError::AuthError(_) => StatusCode::UNAUTHORIZED,
+            SuppressionError::NotSuppressed => StatusCode::NOT_FOUND,
+            SuppressionError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            SuppressionError::AuthError(_) => {
+                let mut response = HttpResponse::new(StatusCode::UNAUTHORIZED);
+                let header_value = HeaderValue::from_str(r#"Basic realm="publish""#).unwrap();
+                response
+                    .headers_mut()
+                    .insert(WWW_AUTHENTICATE, header_value);
+                response
+            }
+            _ => HttpResponse::build(self.status_code()).body(self.to_string()),
+        }
+    }
+}
+
+/// List suppressed addresses, newest first, or look up a single one with `?email=`.
+///
+/// Only hashes are stored: the addresses themselves cannot be listed.
+#[tracing::instrument(
+    name = "List suppressions",
+    skip(parameters, pool, request),
+    fields(username = tracing::field::Empty, user_id = tracing::field::Empty)
+)]
+pub async fn list_suppressions(
+    parameters: web::Query<SuppressionParameters>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, SuppressionError> {
+    authenticate(&request, &pool).await?;
+    let email_hash = match parameters.0.email {
+        Some(email) => Some(hash_email(&parse_email(email)?)),
+        None => None,
+    };
+    let suppressions = sqlx::query_as!(
+        Suppression,
+        r#"
+        SELECT email_hash, reason, source, created_at
+        FROM suppressions
+        WHERE $1::TEXT IS NULL OR email_hash = $1
+        ORDER BY created_at DESC
+        "#,
+        email_hash
+    )
+    .fetch_all(pool.get_ref())
+    .await
+    .context("Failed to list the suppressions.")?;
+    Ok(HttpResponse::Ok().json(suppressions))
+}
+
+/// Suppress a batch of addresses, e.g. exported from another tool.
+///
+/// Nothing is imported if any of the addresses is invalid.
+#[tracing::instrument(
+    name = "Import suppressions",
+    skip(body, pool, request),
+    fields(username = tracing::field::Empty, user_id = tracing::field::Empty)
+)]
+pub async fn import_suppressions(
+    body: web::Json<ImportBody>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, SuppressionError> {
+    authenticate(&request, &pool).await?;
+    let ImportBody { emails, reason } = body.0;
+    let reason = match reason {
+        Some(reason) => {
+            SuppressionReason::parse(&reason).map_err(SuppressionError::ValidationError)?
+        }
+        None => SuppressionReason::Manual,
+    };
+    let emails = emails
+        .into_iter()
+        .map(parse_email)
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let mut suppressed = 0;
+    for email in &emails {
+        if suppress(&mut transaction, email, reason, "admin")
+            .await
+            .context("Failed to suppress an address.")?
+        {
+            suppressed += 1;
+        }
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to import suppressions.")?;
+    Ok(HttpResponse::Ok().json(ImportOutcome {
+        suppressed,
+        already_suppressed: emails.len() - suppressed,
+    }))
+}
+
+/// Allow emailing an address again.
+///
+/// It is not subscribed again: its owner has to go through the form.
+#[tracing::instrument(
+    name = "Lift a suppression",
+    skip(parameters, pool, request),
+    fields(username = tracing::field::Empty, user_id = tracing::field::Empty)
+)]
+pub async fn lift_suppression(
+    parameters: web::Query<LiftParameters>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, SuppressionError> {
+    authenticate(&request, &pool).await?;
+    let email = parse_email(parameters.0.email)?;
+    if !lift(&pool, &email)
+        .await
+        .context("Failed to lift the suppression.")?
+    {
+        return Err(SuppressionError::NotSuppressed);
+    }
+    Ok(HttpResponse::Ok().finish())
+}
+
+fn parse_email(email: String) -> Result<SubscriberEmail, SuppressionError> {
+    SubscriberEmail::parse(email).map_err(SuppressionError::ValidationError)
+}
@@ -0,0 +1,160 @@
+use actix_web::http::header::{CACHE_CONTROL, LOCATION};
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse, ResponseError};
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::routes::error_chain_fmt;
+use crate::signed_token::SignedTokenError;
+use crate::tracking::{issue_links, Delivery, Tracking, PIXEL_GIF};
+
+#[derive(thiserror::Error)]
+pub enum TrackingError {
+    #[error("This link is not valid.")]
+    InvalidLink,
+    #[error("This link has expired.")]
+    ExpiredLink,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for TrackingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for TrackingError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            TrackingError::InvalidLink => StatusCode::NOT_FOUND,
+            TrackingError::ExpiredLink => StatusCode::GONE,
+            TrackingError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl From<SignedTokenError> for TrackingError {
+    fn from(e: SignedTokenError) -> Self {
+        match e {
+            SignedTokenError::Invalid => TrackingError::InvalidLink,
+            SignedTokenError::Expired => TrackingError::ExpiredLink,
+        }
+    }
+}
+
+/// Record that a recipient opened an issue, and serve the tracking pixel.
+///
+/// The pixel is served whatever the token, so that old or forwarded emails
+/// do not show a broken image. It must not be cached, or we would only see
+/// the first open.
+#[tracing::instrument(name = "Record an open", skip(token, pool, tracking))]
+pub async fn track_open(
+    token: web::Path<String>,
+    pool: web::Data<PgPool>,
+    tracking: web::Data<Tracking>,
+) -> Result<HttpResponse, TrackingError> {
+    match tracking.verify_open(&token) {
+        Ok(delivery) => record_open(&pool, &delivery)
+            .await
+            .context("Failed to record an open.")?,
+        Err(e) => tracing::info!(error.message = %e, "Ignoring an invalid open tracking token."),
+    }
+    Ok(HttpResponse::Ok()
+        .content_type("image/gif")
+        .insert_header((CACHE_CONTROL, "no-store"))
+        .body(PIXEL_GIF))
+}
+
+/// Record that a recipient clicked a link of an issue, and redirect them to it.
+///
+/// Only links found in the issue are redirected to, even with a valid token:
+/// the endpoint cannot be used to send people anywhere else.
+#[tracing::instrument(name = "Record a click", skip(token, pool, tracking))]
+pub async fn track_click(
+    token: web::Path<String>,
+    pool: web::Data<PgPool>,
+    tracking: web::Data<Tracking>,
+) -> Result<HttpResponse, TrackingError> {
+    let (delivery, target) = tracking.verify_click(&token)?;
+    let links = get_issue_links(&pool, delivery.newsletter_issue_id)
+        .await
+        .context("Failed to retrieve the links of the issue.")?;
+    if !links.contains(&target) {
+        tracing::warn!(url = %target, "Refusing to redirect to a link missing from the issue.");
+        return Err(TrackingError::InvalidLink);
+    }
+    record_click(&pool, &delivery, &target)
+        .await
+        .context("Failed to record a click.")?;
+    Ok(HttpResponse::Found()
+        .insert_header((LOCATION, target))
+        .finish())
+}
+
+/// Empty if there is no such issue.
+#[tracing::instrument(skip(pool))]
+async fn get_issue_links(pool: &PgPool, issue_id: Uuid) -> Result<Vec<String>, sqlx::Error> {
+    let issue = sqlx::query!(
+        r#"SELECT html_content, text_content FROM newsletter_issues WHERE newsletter_issue_id = $1"#,
+        issue_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(issue
+        .map(|i| issue_links(&i.html_content, &i.text_content))
+        .unwrap_or_default())
+}
+
+/// Clicks of an erased subscriber are not recorded.
+#[tracing::instrument(skip(pool, target))]
+async fn record_click(pool: &PgPool, delivery: &Delivery, target: &str) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO email_clicks (
+            newsletter_issue_id, subscriber_id, url, click_count, first_clicked_at, last_clicked_at
+        )
+        SELECT $1, $2, $3, 1, $4, $4
+        WHERE EXISTS (SELECT 1 FROM subscriptions WHERE id = $2)
+        ON CONFLICT (newsletter_issue_id, subscriber_id, url) DO UPDATE
+        SET
+            click_count = email_clicks.click_count + 1,
+            last_clicked_at = EXCLUDED.last_clicked_at
+        "#,
+        delivery.newsletter_issue_id,
+        delivery.subscriber_id,
+        target,
+        Utc::now()
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Opens of an erased subscriber are not recorded.
+#[tracing::instrument(skip(pool))]
+async fn record_open(pool: &PgPool, delivery: &Delivery) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO email_opens (
+            newsletter_issue_id, subscriber_id, open_count, first_opened_at, last_opened_at
+        )
+        SELECT $1, $2, 1, $3, $3
+        WHERE
+            EXISTS (SELECT 1 FROM newsletter_issues WHERE newsletter_issue_id = $1) AND
+            EXISTS (SELECT 1 FROM subscriptions WHERE id = $2)
+        ON CONFLICT (newsletter_issue_id, subscriber_id) DO UPDATE
+        SET
+            open_count = email_opens.open_count + 1,
+            last_opened_at = EXCLUDED.last_opened_at
+        "#,
+        delivery.newsletter_issue_id,
+        delivery.subscriber_id,
+        Utc::now()
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
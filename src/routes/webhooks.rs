@@ -0,0 +1,337 @@
+use actix_web::http::header::{HeaderValue, WWW_AUTHENTICATE};
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use secrecy::ExposeSecret;
+use sha2::{Digest, Sha256};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::authentication::basic_authentication;
+use crate::configuration::EmailWebhookSettings;
+use crate::delivery_log::{log_bounce, log_unsubscribe};
+use crate::domain::SubscriberEmail;
+use crate::routes::error_chain_fmt;
+use crate::suppressions::{suppress, SuppressionReason};
+
+#[derive(thiserror::Error)]
+pub enum WebhookError {
+    #[error("Authentication failed.")]
+    AuthError(#[source] anyhow::Error),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for WebhookError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for WebhookError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            WebhookError::AuthError(_) => StatusCode::UNAUTHORIZED,
+            WebhookError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::new(self.status_code());
+        if let WebhookError::AuthError(_) = self {
+            let header_value = HeaderValue::from_str(r#"Basic realm="webhooks""#).unwrap();
+            response
+                .headers_mut()
+                .insert(WWW_AUTHENTICATE, header_value);
+        }
+        response
+    }
+}
+
+/// The fields we use out of Postmark's bounce webhook.
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct PostmarkBounce {
+    record_type: String,
+    #[serde(rename = "ID")]
+    id: i64,
+    #[serde(rename = "Type")]
+    bounce_type: String,
+    email: String,
+    details: Option<String>,
+    bounced_at: DateTime<Utc>,
+}
+
+/// The fields we use out of Postmark's spam complaint webhook.
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct PostmarkComplaint {
+    record_type: String,
+    #[serde(rename = "ID")]
+    id: i64,
+    email: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum BounceKind {
+    /// The address does not work and never will.
+    Hard,
+    /// The address did not work this time (full mailbox, DNS failure...).
+    Soft,
+}
+
+impl BounceKind {
+    /// How we treat each of Postmark's bounce types. `None` for those saying
+    /// nothing about the address, e.g. auto-responders.
+    fn of(bounce_type: &str) -> Option<Self> {
+        match bounce_type {
+            "HardBounce" | "BadEmailAddress" | "ManuallyDeactivated" => Some(BounceKind::Hard),
+            "SoftBounce" | "Transient" | "DnsError" | "Blocked" => Some(BounceKind::Soft),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            BounceKind::Hard => "hard",
+            BounceKind::Soft => "soft",
+        }
+    }
+}
+
+/// Check the webhook carries the credentials we gave the provider.
+pub(crate) fn authenticate_webhook(
+    request: &HttpRequest,
+    settings: &EmailWebhookSettings,
+) -> Result<(), WebhookError> {
+    let credentials = basic_authentication(request.headers()).map_err(WebhookError::AuthError)?;
+    // Comparing digests takes the same time however much of the password matches
+    let password_matches = Sha256::digest(credentials.password.expose_secret().as_bytes())
+        == Sha256::digest(settings.password.expose_secret().as_bytes());
+    if credentials.username != settings.username || !password_matches {
+        return Err(WebhookError::AuthError(anyhow::anyhow!(
+            "Invalid webhook credentials."
+        )));
+    }
+    Ok(())
+}
+
+/// Record a bounce reported by Postmark.
+///
+/// Hard-bounced subscribers are marked as `bounced` right away, soft-bounced
+/// ones once they reach `soft_bounce_threshold`: we stop emailing both.
+/// Anything we do not act on is acknowledged all the same, or Postmark would
+/// keep retrying.
+#[tracing::instrument(
+    name = "Processing a bounce",
+    skip(request, bounce, pool, settings),
+    fields(bounce_id = %bounce.id, bounce_type = %bounce.bounce_type)
+)]
+pub async fn bounce_webhook(
+    request: HttpRequest,
+    bounce: web::Json<PostmarkBounce>,
+    pool: web::Data<PgPool>,
+    settings: web::Data<EmailWebhookSettings>,
+) -> Result<HttpResponse, WebhookError> {
+    authenticate_webhook(&request, &settings)?;
+    let kind = match BounceKind::of(&bounce.bounce_type) {
+        Some(kind) if bounce.record_type == "Bounce" => kind,
+        _ => return Ok(HttpResponse::Ok().finish()),
+    };
+    let email = match parse_webhook_email(&bounce.email) {
+        Ok(email) => email,
+        Err(e) => {
+            tracing::warn!(error.message = %e, "Ignoring a bounce of an invalid address.");
+            return Ok(HttpResponse::Ok().finish());
+        }
+    };
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let subscriber_id = match get_subscriber_id(&mut transaction, &email)
+        .await
+        .context("Failed to look up the bounced subscriber.")?
+    {
+        Some(subscriber_id) => subscriber_id,
+        None => return Ok(HttpResponse::Ok().finish()),
+    };
+    let recorded = record_bounce(&mut transaction, subscriber_id, kind, &bounce)
+        .await
+        .context("Failed to record the bounce.")?;
+    // Postmark delivered this webhook before: it has been counted already
+    if !recorded {
+        return Ok(HttpResponse::Ok().finish());
+    }
+    log_bounce(&mut transaction, subscriber_id)
+        .await
+        .context("Failed to log the bounce.")?;
+    let bounced = match kind {
+        BounceKind::Hard => true,
+        BounceKind::Soft => {
+            let soft_bounce_count = increment_soft_bounce_count(&mut transaction, subscriber_id)
+                .await
+                .context("Failed to count the soft bounce.")?;
+            soft_bounce_count >= settings.soft_bounce_threshold
+        }
+    };
+    if bounced {
+        mark_subscriber_as_bounced(&mut transaction, subscriber_id)
+            .await
+            .context("Failed to mark the subscriber as bounced.")?;
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to record a bounce.")?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Suppress an address whose owner marked one of our emails as spam.
+///
+/// The address is suppressed even if it is not subscribed (anymore), and
+/// every complaint is acknowledged, or Postmark would keep retrying.
+#[tracing::instrument(
+    name = "Processing a spam complaint",
+    skip(request, complaint, pool, settings),
+    fields(complaint_id = %complaint.id)
+)]
+pub async fn complaint_webhook(
+    request: HttpRequest,
+    complaint: web::Json<PostmarkComplaint>,
+    pool: web::Data<PgPool>,
+    settings: web::Data<EmailWebhookSettings>,
+) -> Result<HttpResponse, WebhookError> {
+    authenticate_webhook(&request, &settings)?;
+    if complaint.record_type != "SpamComplaint" {
+        return Ok(HttpResponse::Ok().finish());
+    }
+    let email = match parse_webhook_email(&complaint.email) {
+        Ok(email) => email,
+        Err(e) => {
+            tracing::warn!(error.message = %e, "Ignoring a complaint about an invalid address.");
+            return Ok(HttpResponse::Ok().finish());
+        }
+    };
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    suppress(&mut transaction, &email, SuppressionReason::Complaint, "webhook")
+        .await
+        .context("Failed to suppress the address.")?;
+    let subscriber_id = mark_subscriber_as_complained(&mut transaction, &email)
+        .await
+        .context("Failed to mark the subscriber as complained.")?;
+    if let Some(subscriber_id) = subscriber_id {
+        log_unsubscribe(&mut transaction, subscriber_id)
+            .await
+            .context("Failed to log the complaint as an unsubscribe.")?;
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to record a spam complaint.")?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Postmark reports addresses as the receiving server spelled them: they
+/// are matched against ours ignoring case and surrounding whitespace.
+fn parse_webhook_email(email: &str) -> Result<SubscriberEmail, String> {
+    SubscriberEmail::parse(email.trim().to_owned())
+}
+
+#[tracing::instrument(name = "Look up a subscriber by email", skip(transaction, email))]
+async fn get_subscriber_id(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &SubscriberEmail,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"SELECT id FROM subscriptions WHERE lower(email) = lower($1)"#,
+        email.as_ref()
+    )
+    .fetch_optional(transaction)
+    .await?;
+    Ok(row.map(|r| r.id))
+}
+
+/// Whether the bounce was new to us.
+#[tracing::instrument(name = "Record a bounce", skip(transaction, bounce))]
+async fn record_bounce(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    kind: BounceKind,
+    bounce: &PostmarkBounce,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO email_bounces (
+            bounce_id, subscriber_id, kind, bounce_type, details, bounced_at, received_at
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        ON CONFLICT (bounce_id) DO NOTHING
+        "#,
+        bounce.id.to_string(),
+        subscriber_id,
+        kind.as_str(),
+        bounce.bounce_type,
+        bounce.details.as_deref().unwrap_or_default(),
+        bounce.bounced_at,
+        Utc::now()
+    )
+    .execute(transaction)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+#[tracing::instrument(name = "Count a soft bounce", skip(transaction))]
+async fn increment_soft_bounce_count(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<i32, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        UPDATE subscriptions SET soft_bounce_count = soft_bounce_count + 1
+        WHERE id = $1
+        RETURNING soft_bounce_count
+        "#,
+        subscriber_id
+    )
+    .fetch_one(transaction)
+    .await?;
+    Ok(row.soft_bounce_count)
+}
+
+#[tracing::instrument(name = "Mark subscriber as bounced", skip(transaction))]
+async fn mark_subscriber_as_bounced(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"UPDATE subscriptions SET status = 'bounced' WHERE id = $1"#,
+        subscriber_id
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+/// The id of the subscriber, if the address is subscribed.
+#[tracing::instrument(name = "Mark subscriber as complained", skip(transaction, email))]
+async fn mark_subscriber_as_complained(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &SubscriberEmail,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        UPDATE subscriptions SET status = 'complained'
+        WHERE lower(email) = lower($1)
+        RETURNING id
+        "#,
+        email.as_ref()
+    )
+    .fetch_optional(transaction)
+    .await?;
+    Ok(row.map(|r| r.id))
+}
@@ -0,0 +1,160 @@
+//! Tamper-proof, expiring tokens for the links we email out.
+//!
+//! A token is `{payload}.{signature}`, both URL-safe base64: the payload
+//! carries a subject (e.g. a subscriber id) and an expiry, the signature is
+//! an HMAC-SHA256 of the payload keyed with the application secret.
+//! Nothing is stored server-side, so a token stays valid until it expires.
+use chrono::{DateTime, TimeZone, Utc};
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+
+#[derive(thiserror::Error, Debug)]
+pub enum SignedTokenError {
+    #[error("The token is malformed or its signature does not match.")]
+    Invalid,
+    #[error("The token has expired.")]
+    Expired,
+}
+
+/// Sign `subject` until `expires_at`.
+///
+/// `purpose` is part of the signature, so a token handed out for one
+/// purpose is rejected when presented for another.
+pub fn sign(
+    secret: &Secret<String>,
+    purpose: &str,
+    subject: &str,
+    expires_at: DateTime<Utc>,
+) -> String {
+    let payload = base64::encode_config(
+        format!("{}:{}", expires_at.timestamp(), subject),
+        base64::URL_SAFE_NO_PAD,
+    );
+    let signature = mac(secret, purpose, &payload).finalize().into_bytes();
+    format!(
+        "{}.{}",
+        payload,
+        base64::encode_config(signature, base64::URL_SAFE_NO_PAD)
+    )
+}
+
+/// The subject of a token signed for `purpose`, if it is authentic and has
+/// not expired yet.
+pub fn verify(
+    secret: &Secret<String>,
+    purpose: &str,
+    token: &str,
+) -> Result<String, SignedTokenError> {
+    let (payload, signature) = token.split_once('.').ok_or(SignedTokenError::Invalid)?;
+    let signature = base64::decode_config(signature, base64::URL_SAFE_NO_PAD)
+        .map_err(|_| SignedTokenError::Invalid)?;
+    mac(secret, purpose, payload)
+        .verify_slice(&signature)
+        .map_err(|_| SignedTokenError::Invalid)?;
+    // The payload is ours from here on, it can only fail to parse if the
+    // secret has leaked.
+    let payload = base64::decode_config(payload, base64::URL_SAFE_NO_PAD)
+        .ok()
+        .and_then(|p| String::from_utf8(p).ok())
+        .ok_or(SignedTokenError::Invalid)?;
+    let (expires_at, subject) = payload.split_once(':').ok_or(SignedTokenError::Invalid)?;
+    let expires_at = expires_at
+        .parse::<i64>()
+        .map_err(|_| SignedTokenError::Invalid)?;
+    if Utc.timestamp(expires_at, 0) <= Utc::now() {
+        return Err(SignedTokenError::Expired);
+    }
+    Ok(subject.to_owned())
+}
+
+fn mac(secret: &Secret<String>, purpose: &str, payload: &str) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.expose_secret().as_bytes())
+        .expect("HMAC accepts keys of any length.");
+    mac.update(purpose.as_bytes());
+    mac.update(b"\n");
+    mac.update(payload.as_bytes());
+    mac
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, Utc};
+    use claim::{assert_err, assert_ok_eq};
+    use secrecy::Secret;
+
+    use super::{sign, verify, SignedTokenError};
+
+    fn secret() -> Secret<String> {
+        Secret::new("a-very-secret-key".into())
+    }
+
+    fn in_an_hour() -> chrono::DateTime<Utc> {
+        Utc::now() + Duration::hours(1)
+    }
+
+    #[test]
+    fn a_signed_token_yields_its_subject() {
+        let token = sign(&secret(), "preferences", "subscriber-id", in_an_hour());
+        assert_ok_eq!(verify(&secret(), "preferences", &token), "subscriber-id");
+    }
+
+    #[test]
+    fn tokens_only_contain_url_safe_characters() {
+        let token = sign(&secret(), "preferences", "a:b/c?d", in_an_hour());
+        assert!(token
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || ['-', '_', '.'].contains(&c)));
+    }
+
+    #[test]
+    fn an_expired_token_is_rejected() {
+        let token = sign(
+            &secret(),
+            "preferences",
+            "subscriber-id",
+            Utc::now() - Duration::seconds(1),
+        );
+        assert!(matches!(
+            verify(&secret(), "preferences", &token),
+            Err(SignedTokenError::Expired)
+        ));
+    }
+
+    #[test]
+    fn a_token_signed_with_another_secret_is_rejected() {
+        let token = sign(
+            &Secret::new("another-key".into()),
+            "preferences",
+            "subscriber-id",
+            in_an_hour(),
+        );
+        assert!(matches!(
+            verify(&secret(), "preferences", &token),
+            Err(SignedTokenError::Invalid)
+        ));
+    }
+
+    #[test]
+    fn a_token_signed_for_another_purpose_is_rejected() {
+        let token = sign(&secret(), "export", "subscriber-id", in_an_hour());
+        assert_err!(verify(&secret(), "preferences", &token));
+    }
+
+    #[test]
+    fn a_tampered_payload_is_rejected() {
+        let token = sign(&secret(), "preferences", "subscriber-id", in_an_hour());
+        let forged = sign(&secret(), "preferences", "someone-else", in_an_hour());
+        let (_, signature) = token.split_once('.').unwrap();
+        let (forged_payload, _) = forged.split_once('.').unwrap();
+        let tampered = format!("{}.{}", forged_payload, signature);
+        assert_err!(verify(&secret(), "preferences", &tampered));
+    }
+
+    #[test]
+    fn malformed_tokens_are_rejected() {
+        for token in ["", "no-dot", "a.b", "!!!.???"] {
+            assert_err!(verify(&secret(), "preferences", token));
+        }
+    }
+}
@@ -1,15 +1,27 @@
 use std::net::TcpListener;
+use std::sync::Arc;
 
 use actix_web::dev::Server;
 use actix_web::web::Data;
-use actix_web::{web, App, HttpServer, HttpResponse};
+use actix_web::{web, App, HttpServer};
+use secrecy::Secret;
 use sqlx::postgres::PgPoolOptions;
 use sqlx::{PgPool, Pool, Postgres};
 use tracing_actix_web::TracingLogger;
 
 use crate::configuration::{DatabaseSettings, Settings};
-use crate::email_client::EmailClient;
-use crate::routes::{health_check, subscribe, confirm, publish_newsletter};
+use crate::email_client::EmailSender;
+use crate::routes::{
+    admin_erase_data, admin_export_data, archive_index, archive_issue, atom_feed, bounce_webhook,
+    cancel_scheduled_issue, complaint_webhook, confirm, create_list, erase_data, erasure_form,
+    export_data, get_lists, get_newsletter_issue, get_newsletter_issue_stats, health_check,
+    import_suppressions, lift_suppression, list_newsletter_issues, list_scheduled_issues,
+    list_suppressions, preferences_form, publish_newsletter, request_data_export_link,
+    request_erasure_link, request_preferences_link, reschedule_issue, rss_feed,
+    signup_form_token, subscribe, track_click, track_open, unsubscribe, unsubscribe_form,
+    update_preferences,
+};
+use crate::templates::Templates;
 
 pub struct Application {
     port: u16,
@@ -18,20 +30,23 @@ pub struct Application {
 
 pub struct ApplicationBaseUrl(pub String);
 
+/// How long a confirmation link stays valid after it has been sent.
+pub struct SubscriptionTokenTtl(pub chrono::Duration);
+
+/// The key signing the links we email out, see `signed_token`.
+pub struct HmacSecret(pub Secret<String>);
+
+/// How long a link to the preference center stays valid after it has been sent.
+pub struct PreferencesLinkTtl(pub chrono::Duration);
+
+/// How long a link to export or erase a subscriber's data stays valid after it has been sent.
+pub struct PrivacyLinkTtl(pub chrono::Duration);
+
 impl Application {
-    pub async fn build(configuration: Settings) -> Result<Application, std::io::Error> {
+    pub async fn build(configuration: Settings) -> Result<Application, anyhow::Error> {
         let connection_pool = get_connection_pool(&configuration.database);
-        let sender_email = configuration
-            .email_client
-            .sender()
-            .expect("Invalid sender email address.");
-        let timeout = configuration.email_client.timeout();
-        let email_client = EmailClient::new(
-            configuration.email_client.base_url,
-            sender_email,
-            configuration.email_client.authorization_token,
-            timeout,
-        );
+        let email_client = configuration.email_client.clone().client(&connection_pool);
+        let templates = configuration.templates.load()?;
 
         let address = format!(
             "{}:{}",
@@ -43,7 +58,8 @@ impl Application {
             listener,
             connection_pool,
             email_client,
-            configuration.application.base_url,
+            templates,
+            configuration,
         )?;
 
         Ok(Self { port, server })
@@ -65,22 +81,116 @@ pub fn get_connection_pool(configuration: &DatabaseSettings) -> Pool<Postgres> {
 fn run(
     listener: TcpListener,
     db_pool: PgPool,
-    email_client: EmailClient,
-    base_url: String,
+    email_client: Arc<dyn EmailSender>,
+    templates: Templates,
+    configuration: Settings,
 ) -> Result<Server, std::io::Error> {
+    let application = configuration.application;
+    let rate_limiter = configuration.rate_limit.limiter(&db_pool);
+    let bot_protection = Data::new(
+        configuration
+            .bot_protection
+            .protection(application.hmac_secret.clone()),
+    );
+    let email_webhooks = Data::new(configuration.email_webhooks);
+    let tracking = Data::new(
+        configuration
+            .tracking
+            .tracking(application.hmac_secret.clone()),
+    );
     let db_pool = Data::new(db_pool);
-    let email_client = Data::new(email_client);
-    let base_url = Data::new(ApplicationBaseUrl(base_url));
+    let email_client: Data<dyn EmailSender> = Data::from(email_client);
+    let templates = Data::new(templates);
+    let base_url = Data::new(ApplicationBaseUrl(application.base_url));
+    let subscription_token_ttl = Data::new(SubscriptionTokenTtl(chrono::Duration::hours(
+        application.subscription_token_ttl_hours,
+    )));
+    let hmac_secret = Data::new(HmacSecret(application.hmac_secret));
+    let preferences_link_ttl = Data::new(PreferencesLinkTtl(chrono::Duration::minutes(
+        application.preferences_link_ttl_minutes,
+    )));
+    let privacy_link_ttl = Data::new(PrivacyLinkTtl(chrono::Duration::minutes(
+        application.privacy_link_ttl_minutes,
+    )));
     let server = HttpServer::new(move || {
         App::new()
             .wrap(TracingLogger::default())
             .route("/health_check", web::get().to(health_check))
-            .route("/subscriptions", web::post().to(subscribe))
+            .route("/lists", web::get().to(get_lists))
+            .route("/lists", web::post().to(create_list))
+            .service(
+                web::resource("/subscriptions")
+                    .wrap(rate_limiter.clone())
+                    .route(web::post().to(subscribe)),
+            )
+            .route("/subscriptions/form_token", web::get().to(signup_form_token))
             .route("/subscriptions/confirm", web::get().to(confirm))
+            .route("/subscriptions/unsubscribe", web::get().to(unsubscribe_form))
+            .route("/subscriptions/unsubscribe", web::post().to(unsubscribe))
+            .service(
+                web::resource("/preferences/link")
+                    .wrap(rate_limiter.clone())
+                    .route(web::post().to(request_preferences_link)),
+            )
+            .route("/preferences", web::get().to(preferences_form))
+            .route("/preferences", web::post().to(update_preferences))
+            .service(
+                web::resource("/privacy/export/link")
+                    .wrap(rate_limiter.clone())
+                    .route(web::post().to(request_data_export_link)),
+            )
+            .route("/privacy/export", web::get().to(export_data))
+            .service(
+                web::resource("/privacy/erasure/link")
+                    .wrap(rate_limiter.clone())
+                    .route(web::post().to(request_erasure_link)),
+            )
+            .route("/privacy/erasure", web::get().to(erasure_form))
+            .route("/privacy/erasure", web::post().to(erase_data))
+            .route("/subscribers/data", web::get().to(admin_export_data))
+            .route("/subscribers/data", web::delete().to(admin_erase_data))
+            .route("/webhooks/email/bounce", web::post().to(bounce_webhook))
+            .route("/webhooks/email/complaint", web::post().to(complaint_webhook))
+            .route("/suppressions", web::get().to(list_suppressions))
+            .route("/suppressions", web::post().to(import_suppressions))
+            .route("/suppressions", web::delete().to(lift_suppression))
             .route("/newsletters",web::post().to(publish_newsletter))
+            .route("/newsletters", web::get().to(list_newsletter_issues))
+            // Registered before `/newsletters/{newsletter_issue_id}`, which would match it too
+            .route("/newsletters/scheduled", web::get().to(list_scheduled_issues))
+            .route(
+                "/newsletters/scheduled/{newsletter_issue_id}",
+                web::put().to(reschedule_issue),
+            )
+            .route(
+                "/newsletters/scheduled/{newsletter_issue_id}",
+                web::delete().to(cancel_scheduled_issue),
+            )
+            .route(
+                "/newsletters/{newsletter_issue_id}",
+                web::get().to(get_newsletter_issue),
+            )
+            .route(
+                "/newsletters/{newsletter_issue_id}/stats",
+                web::get().to(get_newsletter_issue_stats),
+            )
+            .route("/archive", web::get().to(archive_index))
+            .route("/archive/{slug}", web::get().to(archive_issue))
+            .route("/feed.atom", web::get().to(atom_feed))
+            .route("/feed.rss", web::get().to(rss_feed))
+            .route("/t/o/{token}.gif", web::get().to(track_open))
+            .route("/t/c/{token}", web::get().to(track_click))
             .app_data(db_pool.clone())
             .app_data(email_client.clone())
+            .app_data(templates.clone())
             .app_data(base_url.clone())
+            .app_data(subscription_token_ttl.clone())
+            .app_data(hmac_secret.clone())
+            .app_data(preferences_link_ttl.clone())
+            .app_data(privacy_link_ttl.clone())
+            .app_data(bot_protection.clone())
+            .app_data(email_webhooks.clone())
+            .app_data(tracking.clone())
     })
         .listen(listener)?
         .run();
@@ -0,0 +1,173 @@
+//! Addresses we must never email nor subscribe again.
+//!
+//! Only a SHA-256 hash of the (lowercased) address is stored, so that an
+//! erased subscriber leaves nothing readable behind.
+//!
+//! `SuppressionFilter` wraps the `EmailSender` handed out by
+//! `EmailClientSettings::client`, so that no code path can email a
+//! suppressed address.
+use std::sync::Arc;
+
+use chrono::Utc;
+use sha2::{Digest, Sha256};
+use sqlx::{PgPool, Postgres, Transaction};
+
+use crate::domain::SubscriberEmail;
+use crate::email_client::{EmailSender, SendEmailError};
+
+/// Why an address was suppressed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SuppressionReason {
+    /// The subscriber asked for their data to be erased.
+    Erasure,
+    /// The recipient marked one of our emails as spam.
+    Complaint,
+    /// An admin suppressed the address, e.g. importing another tool's list.
+    Manual,
+}
+
+impl SuppressionReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SuppressionReason::Erasure => "erasure",
+            SuppressionReason::Complaint => "complaint",
+            SuppressionReason::Manual => "manual",
+        }
+    }
+
+    pub fn parse(s: &str) -> Result<Self, String> {
+        match s {
+            "erasure" => Ok(SuppressionReason::Erasure),
+            "complaint" => Ok(SuppressionReason::Complaint),
+            "manual" => Ok(SuppressionReason::Manual),
+            other => Err(format!(
+                "`{}` is not a suppression reason. Use `erasure`, `complaint` or `manual`.",
+                other
+            )),
+        }
+    }
+}
+
+pub fn hash_email(email: &SubscriberEmail) -> String {
+    Sha256::digest(email.as_ref().to_lowercase().as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Suppress `email`. `source` tells who asked for it, e.g. `subscriber` or `admin`.
+///
+/// Returns whether the address was not suppressed already.
+#[tracing::instrument(skip(transaction, email))]
+pub async fn suppress(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &SubscriberEmail,
+    reason: SuppressionReason,
+    source: &str,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO suppressions (email_hash, reason, source, created_at)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (email_hash) DO NOTHING
+        "#,
+        hash_email(email),
+        reason.as_str(),
+        source,
+        Utc::now()
+    )
+    .execute(transaction)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Returns whether the address was suppressed.
+#[tracing::instrument(skip(pool, email))]
+pub async fn lift(pool: &PgPool, email: &SubscriberEmail) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"DELETE FROM suppressions WHERE email_hash = $1"#,
+        hash_email(email)
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+#[tracing::instrument(skip(pool, email))]
+pub async fn is_suppressed(pool: &PgPool, email: &SubscriberEmail) -> Result<bool, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"SELECT email_hash FROM suppressions WHERE email_hash = $1"#,
+        hash_email(email)
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(row.is_some())
+}
+
+/// An `EmailSender` refusing to email suppressed addresses.
+pub struct SuppressionFilter {
+    inner: Arc<dyn EmailSender>,
+    pool: PgPool,
+}
+
+impl SuppressionFilter {
+    pub fn new(inner: Arc<dyn EmailSender>, pool: PgPool) -> Self {
+        Self { inner, pool }
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailSender for SuppressionFilter {
+    async fn send_email_with_headers(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[(&str, &str)],
+    ) -> Result<(), SendEmailError> {
+        if is_suppressed(&self.pool, recipient)
+            .await
+            .map_err(SendEmailError::SuppressionLookup)?
+        {
+            return Err(SendEmailError::Suppressed);
+        }
+        self.inner
+            .send_email_with_headers(recipient, subject, html_content, text_content, headers)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::SubscriberEmail;
+
+    use super::{hash_email, SuppressionReason};
+
+    #[test]
+    fn the_hash_ignores_the_case_of_the_address() {
+        let lower = SubscriberEmail::parse("ursula@example.com".into()).unwrap();
+        let mixed = SubscriberEmail::parse("Ursula@Example.com".into()).unwrap();
+        assert_eq!(hash_email(&lower), hash_email(&mixed));
+    }
+
+    #[test]
+    fn the_hash_does_not_contain_the_address() {
+        let email = SubscriberEmail::parse("ursula@example.com".into()).unwrap();
+        let hash = hash_email(&email);
+        assert_eq!(hash.len(), 64);
+        assert!(!hash.contains("ursula"));
+    }
+
+    #[test]
+    fn reasons_round_trip_through_their_names() {
+        for reason in [
+            SuppressionReason::Erasure,
+            SuppressionReason::Complaint,
+            SuppressionReason::Manual,
+        ] {
+            assert_eq!(SuppressionReason::parse(reason.as_str()), Ok(reason));
+        }
+        assert!(SuppressionReason::parse("bounce").is_err());
+    }
+}
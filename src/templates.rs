@@ -0,0 +1,240 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::Context as _;
+use tera::{Context, Tera};
+
+/// The emails we send, each made of three templates under `email/`:
+/// `{name}.subject.txt`, `{name}.html` and `{name}.txt`.
+const EMAILS: [&str; 6] = [
+    "confirmation",
+    "already_subscribed",
+    "newsletter",
+    "preferences_link",
+    "data_export_link",
+    "erasure_link",
+];
+
+/// The public archive pages and feeds, and the preference center.
+const PAGES: [&str; 5] = [
+    "archive/index.html",
+    "archive/issue.html",
+    "feeds/atom.xml",
+    "feeds/rss.xml",
+    "preferences/index.html",
+];
+
+pub struct RenderedEmail {
+    pub subject: String,
+    pub html: String,
+    pub text: String,
+}
+
+/// The templates used to build email bodies and web pages, loaded once
+/// at startup.
+///
+/// `.html` and `.xml` templates are escaped automatically, `.txt` ones are not.
+#[derive(Debug)]
+pub struct Templates {
+    tera: Tera,
+}
+
+impl Templates {
+    /// Load every template found in `directory`.
+    ///
+    /// Each email and page is rendered once with a sample context, so that a
+    /// missing template, a syntax error or an unknown variable is caught here
+    /// rather than when a subscriber is waiting for their email.
+    pub fn load(directory: impl AsRef<Path>) -> Result<Self, anyhow::Error> {
+        let glob = directory.as_ref().join("**").join("*");
+        let glob = glob
+            .to_str()
+            .context("The templates directory is not a valid UTF8 path.")?;
+        let tera = Tera::new(glob).context("Failed to parse the templates.")?;
+        let templates = Self { tera };
+        for email in EMAILS {
+            templates
+                .render_email(email, &sample_context(email))
+                .with_context(|| format!("Failed to render the `{}` email templates.", email))?;
+        }
+        for page in PAGES {
+            templates
+                .render_page(page, &sample_page_context())
+                .with_context(|| format!("Failed to render the `{}` template.", page))?;
+        }
+        Ok(templates)
+    }
+
+    pub fn render_page(&self, name: &str, context: &Context) -> Result<String, tera::Error> {
+        self.tera.render(name, context)
+    }
+
+    pub fn render_email(&self, name: &str, context: &Context) -> Result<RenderedEmail, tera::Error> {
+        let render = |extension: &str| {
+            self.tera
+                .render(&format!("email/{}.{}", name, extension), context)
+        };
+        Ok(RenderedEmail {
+            subject: render("subject.txt")?.trim().to_owned(),
+            html: render("html")?,
+            text: render("txt")?,
+        })
+    }
+}
+
+/// All the variables each email can rely on.
+fn sample_context(email: &str) -> Context {
+    let mut context = Context::new();
+    context.insert("base_url", "http://127.0.0.1");
+    context.insert("name", "Ursula Le Guin");
+    context.insert("list_name", "Newsletter");
+    match email {
+        "confirmation" => {
+            context.insert(
+                "confirmation_link",
+                "http://127.0.0.1/subscriptions/confirm?subscription_token=token",
+            );
+        }
+        "newsletter" => {
+            context.insert("title", "Newsletter title");
+            context.insert("html_content", "<p>Newsletter content</p>");
+            context.insert("text_content", "Newsletter content");
+            context.insert(
+                "unsubscribe_link",
+                "http://127.0.0.1/subscriptions/unsubscribe?token=token",
+            );
+        }
+        "preferences_link" => {
+            context.insert("preferences_link", "http://127.0.0.1/preferences?token=token");
+            context.insert("link_ttl_minutes", &60);
+        }
+        "data_export_link" | "erasure_link" => {
+            context.insert("link", "http://127.0.0.1/privacy/export?token=token");
+            context.insert("link_ttl_minutes", &60);
+        }
+        _ => {}
+    }
+    context
+}
+
+/// All the variables pages and feeds can rely on.
+fn sample_page_context() -> Context {
+    let issue: HashMap<&str, &str> = [
+        ("id", "1f0e3c5a-2b4d-4e6f-8a9b-0c1d2e3f4a5b"),
+        ("slug", "newsletter-title-1f0e3c5a"),
+        ("title", "Newsletter title"),
+        ("author", "Newsletter author"),
+        ("published_on", "2022-06-12"),
+        ("published_at_rfc3339", "2022-06-12T09:47:33+00:00"),
+        ("published_at_rfc2822", "Sun, 12 Jun 2022 09:47:33 +0000"),
+        ("html_content", "<p>Newsletter content</p>"),
+    ]
+    .into_iter()
+    .collect();
+    let mut context = Context::new();
+    context.insert("base_url", "http://127.0.0.1");
+    context.insert("updated", "2022-06-12T09:47:33+00:00");
+    context.insert("issues", &[&issue]);
+    context.insert("issue", &issue);
+    let list: HashMap<&str, tera::Value> = [
+        ("slug", "newsletter".into()),
+        ("name", "Newsletter".into()),
+        ("selected", true.into()),
+    ]
+    .into_iter()
+    .collect();
+    let frequency: HashMap<&str, tera::Value> = [
+        ("value", "every_issue".into()),
+        ("label", "Every issue".into()),
+        ("selected", true.into()),
+    ]
+    .into_iter()
+    .collect();
+    context.insert("token", "token");
+    context.insert("name", "Ursula Le Guin");
+    context.insert("saved", &true);
+    context.insert("lists", &[&list]);
+    context.insert("frequencies", &[&frequency]);
+    context.insert("unsubscribe_link", "/subscriptions/unsubscribe?token=token");
+    context
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use claim::{assert_err, assert_ok};
+    use tera::Context;
+    use uuid::Uuid;
+
+    use super::{Templates, EMAILS, PAGES};
+
+    /// A copy of the bundled templates in a scratch directory, for tests to break.
+    fn copy_of_bundled_templates() -> PathBuf {
+        let directory = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        for subdirectory in ["email", "archive", "feeds", "preferences"] {
+            std::fs::create_dir_all(directory.join(subdirectory)).unwrap();
+            for entry in std::fs::read_dir(Path::new("templates").join(subdirectory)).unwrap() {
+                let path = entry.unwrap().path();
+                std::fs::copy(
+                    &path,
+                    directory.join(subdirectory).join(path.file_name().unwrap()),
+                )
+                .unwrap();
+            }
+        }
+        directory
+    }
+
+    #[test]
+    fn the_bundled_templates_are_valid() {
+        assert_ok!(Templates::load("templates"));
+    }
+
+    #[test]
+    fn a_missing_template_is_rejected() {
+        for email in EMAILS {
+            let directory = copy_of_bundled_templates();
+            std::fs::remove_file(directory.join(format!("email/{}.txt", email))).unwrap();
+            assert_err!(Templates::load(&directory));
+        }
+    }
+
+    #[test]
+    fn a_missing_page_template_is_rejected() {
+        for page in PAGES {
+            let directory = copy_of_bundled_templates();
+            std::fs::remove_file(directory.join(page)).unwrap();
+            assert_err!(Templates::load(&directory));
+        }
+    }
+
+    #[test]
+    fn a_template_with_a_syntax_error_is_rejected() {
+        let directory = copy_of_bundled_templates();
+        std::fs::write(directory.join("email/confirmation.html"), "{{ name ").unwrap();
+        assert_err!(Templates::load(&directory));
+    }
+
+    #[test]
+    fn a_template_using_an_unknown_variable_is_rejected() {
+        let directory = copy_of_bundled_templates();
+        std::fs::write(directory.join("email/confirmation.txt"), "{{ nickname }}").unwrap();
+        assert_err!(Templates::load(&directory));
+    }
+
+    #[test]
+    fn html_templates_escape_variables() {
+        let templates = Templates::load("templates").unwrap();
+        let mut context = Context::new();
+        context.insert("base_url", "http://127.0.0.1");
+        context.insert("name", "<script>alert(1)</script>");
+        context.insert("list_name", "Newsletter");
+        context.insert("confirmation_link", "http://127.0.0.1");
+
+        let email = templates.render_email("confirmation", &context).unwrap();
+
+        assert!(!email.html.contains("<script>"));
+        assert!(email.text.contains("<script>"));
+    }
+}
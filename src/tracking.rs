@@ -0,0 +1,380 @@
+//! Per-recipient engagement tracking for newsletter issues.
+//!
+//! Tracking URLs carry a `signed_token` naming the issue and the recipient,
+//! so nothing has to be stored when an email goes out, and nobody can
+//! record an open or a click on someone else's behalf.
+use std::ops::Range;
+
+use chrono::{Duration, Utc};
+use secrecy::Secret;
+use uuid::Uuid;
+
+use crate::domain::ContentFormat;
+use crate::signed_token::{self, SignedTokenError};
+
+/// What open tracking pixels are signed for, see `signed_token`.
+const OPEN_PURPOSE: &str = "open";
+/// What click tracking links are signed for, see `signed_token`.
+const CLICK_PURPOSE: &str = "click";
+
+/// A 1x1 transparent GIF.
+pub const PIXEL_GIF: &[u8] = &[
+    0x47, 0x49, 0x46, 0x38, 0x39, 0x61, 0x01, 0x00, 0x01, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0xff, 0xff, 0xff, 0x21, 0xf9, 0x04, 0x01, 0x00, 0x00, 0x00, 0x00, 0x2c, 0x00, 0x00, 0x00, 0x00,
+    0x01, 0x00, 0x01, 0x00, 0x00, 0x02, 0x02, 0x44, 0x01, 0x00, 0x3b,
+];
+
+/// Who received which issue, as carried by a tracking URL.
+#[derive(Debug, PartialEq)]
+pub struct Delivery {
+    pub newsletter_issue_id: Uuid,
+    pub subscriber_id: Uuid,
+}
+
+impl Delivery {
+    fn subject(&self) -> String {
+        format!("{}:{}", self.newsletter_issue_id, self.subscriber_id)
+    }
+
+    fn parse(subject: &str) -> Result<Self, SignedTokenError> {
+        let (newsletter_issue_id, subscriber_id) =
+            subject.split_once(':').ok_or(SignedTokenError::Invalid)?;
+        Ok(Self {
+            newsletter_issue_id: Uuid::parse_str(newsletter_issue_id)
+                .map_err(|_| SignedTokenError::Invalid)?,
+            subscriber_id: Uuid::parse_str(subscriber_id).map_err(|_| SignedTokenError::Invalid)?,
+        })
+    }
+}
+
+pub struct Tracking {
+    hmac_secret: Secret<String>,
+    /// How long after sending an issue its tracking URLs keep working.
+    link_ttl: Duration,
+}
+
+impl Tracking {
+    pub fn new(hmac_secret: Secret<String>, link_ttl: Duration) -> Self {
+        Self {
+            hmac_secret,
+            link_ttl,
+        }
+    }
+
+    /// The URL of the pixel recording that `delivery` was opened.
+    pub fn open_pixel_url(&self, base_url: &str, delivery: &Delivery) -> String {
+        let token = signed_token::sign(
+            &self.hmac_secret,
+            OPEN_PURPOSE,
+            &delivery.subject(),
+            Utc::now() + self.link_ttl,
+        );
+        format!("{}/t/o/{}.gif", base_url, token)
+    }
+
+    /// The delivery an open tracking pixel was issued for.
+    pub fn verify_open(&self, token: &str) -> Result<Delivery, SignedTokenError> {
+        Delivery::parse(&signed_token::verify(
+            &self.hmac_secret,
+            OPEN_PURPOSE,
+            token,
+        )?)
+    }
+
+    /// A link recording that `delivery` was clicked through to `target`,
+    /// then redirecting there.
+    pub fn click_url(&self, base_url: &str, delivery: &Delivery, target: &str) -> String {
+        let token = signed_token::sign(
+            &self.hmac_secret,
+            CLICK_PURPOSE,
+            &format!("{}:{}", delivery.subject(), target),
+            Utc::now() + self.link_ttl,
+        );
+        format!("{}/t/c/{}", base_url, token)
+    }
+
+    /// The delivery a click tracking link was issued for, and its target.
+    pub fn verify_click(&self, token: &str) -> Result<(Delivery, String), SignedTokenError> {
+        let subject = signed_token::verify(&self.hmac_secret, CLICK_PURPOSE, token)?;
+        // The target follows the issue and subscriber ids, and may contain colons itself.
+        let (end_of_delivery, _) = subject
+            .match_indices(':')
+            .nth(1)
+            .ok_or(SignedTokenError::Invalid)?;
+        Ok((
+            Delivery::parse(&subject[..end_of_delivery])?,
+            subject[end_of_delivery + 1..].to_owned(),
+        ))
+    }
+}
+
+/// Add an invisible `<img>` loading `pixel_url` at the end of the body of
+/// `html`, or at the very end if it has no `</body>`.
+pub fn inject_open_pixel(html: &str, pixel_url: &str) -> String {
+    let pixel = format!(
+        r#"<img src="{}" width="1" height="1" alt="" style="display:block;border:0;">"#,
+        pixel_url
+    );
+    match html.to_ascii_lowercase().rfind("</body>") {
+        Some(end_of_body) => format!("{}{}{}", &html[..end_of_body], pixel, &html[end_of_body..]),
+        None => format!("{}{}", html, pixel),
+    }
+}
+
+/// Replace the URL of every link we track in `content` with `track(url)`:
+/// `<a href>`s in HTML, bare URLs in plain text.
+///
+/// Only web links are tracked, and not those built with merge tags: they
+/// would not match the issue anymore once expanded.
+pub fn rewrite_links(
+    content: &str,
+    format: ContentFormat,
+    track: impl Fn(&str) -> String,
+) -> String {
+    let mut rewritten = String::with_capacity(content.len());
+    let mut position = 0;
+    for link in links(content, format) {
+        rewritten.push_str(&content[position..link.range.start]);
+        rewritten.push_str(&track(&link.url));
+        position = link.range.end;
+    }
+    rewritten.push_str(&content[position..]);
+    rewritten
+}
+
+/// The URLs `rewrite_links` tracks in an issue, i.e. the only places its
+/// click tracking links may redirect to.
+pub fn issue_links(html: &str, text: &str) -> Vec<String> {
+    links(html, ContentFormat::Html)
+        .into_iter()
+        .chain(links(text, ContentFormat::Text))
+        .map(|link| link.url)
+        .collect()
+}
+
+/// A link of an issue: where its URL sits in the content, and the URL itself.
+struct Link {
+    range: Range<usize>,
+    url: String,
+}
+
+fn links(content: &str, format: ContentFormat) -> Vec<Link> {
+    let links = match format {
+        ContentFormat::Html => html_links(content),
+        ContentFormat::Text => text_links(content),
+    };
+    links
+        .into_iter()
+        .filter(|link| {
+            let url = link.url.to_ascii_lowercase();
+            (url.starts_with("http://") || url.starts_with("https://")) && !url.contains("{{")
+        })
+        .collect()
+}
+
+fn html_links(html: &str) -> Vec<Link> {
+    let lowercase = html.to_ascii_lowercase();
+    let mut links = Vec::new();
+    let mut position = 0;
+    while let Some(start) = lowercase[position..].find("<a").map(|i| position + i) {
+        let end = lowercase[start..]
+            .find('>')
+            .map_or(html.len(), |i| start + i);
+        position = end;
+        // `<abbr>`, `<aside>`... are not links
+        if !lowercase[start + 2..].starts_with(|c: char| c.is_ascii_whitespace()) {
+            continue;
+        }
+        if let Some(range) = href_value(&lowercase, start + 2..end) {
+            let url = unescape_html(&html[range.clone()]);
+            links.push(Link { range, url });
+        }
+    }
+    links
+}
+
+/// Where the value of the `href` attribute of `tag`, a range of `html`, sits.
+fn href_value(html: &str, tag: Range<usize>) -> Option<Range<usize>> {
+    let mut position = tag.start;
+    loop {
+        let name = position + html[position..tag.end].find("href")?;
+        position = name + 4;
+        // Not `data-href`
+        if !html[..name].ends_with(|c: char| c.is_ascii_whitespace()) {
+            continue;
+        }
+        let value = match html[position..tag.end].trim_start().strip_prefix('=') {
+            Some(value) => value.trim_start(),
+            None => continue,
+        };
+        let value_start = tag.end - value.len();
+        return match value.chars().next()? {
+            quote @ ('"' | '\'') => {
+                let length = value[1..].find(quote)?;
+                Some(value_start + 1..value_start + 1 + length)
+            }
+            _ => {
+                let length = value
+                    .find(|c: char| c.is_ascii_whitespace())
+                    .unwrap_or(value.len());
+                Some(value_start..value_start + length)
+            }
+        };
+    }
+}
+
+fn text_links(text: &str) -> Vec<Link> {
+    let lowercase = text.to_ascii_lowercase();
+    let mut links = Vec::new();
+    let mut position = 0;
+    while let Some(start) = lowercase[position..].find("http").map(|i| position + i) {
+        let length = text[start..]
+            .find(|c: char| c.is_whitespace() || matches!(c, '<' | '>' | '"'))
+            .unwrap_or(text.len() - start);
+        position = start + length;
+        // Punctuation ending a sentence is not part of the link
+        let url = text[start..position].trim_end_matches(['.', ',', ';', ':', '!', '?', ')', '\'']);
+        links.push(Link {
+            range: start..start + url.len(),
+            url: url.to_owned(),
+        });
+    }
+    links
+}
+
+fn unescape_html(s: &str) -> String {
+    s.replace("&quot;", "\"")
+        .replace("&#x27;", "'")
+        .replace("&#39;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use claim::assert_err;
+    use secrecy::Secret;
+    use uuid::Uuid;
+
+    use super::{inject_open_pixel, issue_links, rewrite_links, Delivery, Tracking};
+    use crate::domain::ContentFormat;
+
+    fn tracking() -> Tracking {
+        Tracking::new(Secret::new("secret".into()), Duration::days(1))
+    }
+
+    fn delivery() -> Delivery {
+        Delivery {
+            newsletter_issue_id: Uuid::new_v4(),
+            subscriber_id: Uuid::new_v4(),
+        }
+    }
+
+    #[test]
+    fn the_pixel_is_added_at_the_end_of_the_body() {
+        let html = "<html><BODY><p>Hi!</p></BODY></html>";
+
+        let html = inject_open_pixel(html, "https://example.com/t/o/token.gif");
+
+        assert!(html.starts_with(r#"<html><BODY><p>Hi!</p><img src="https://example.com/t/o/"#));
+        assert!(html.ends_with(r#"style="display:block;border:0;"></BODY></html>"#));
+    }
+
+    #[test]
+    fn the_pixel_is_appended_to_fragments() {
+        let html = inject_open_pixel("<p>Hi!</p>", "https://example.com/t/o/token.gif");
+
+        assert!(html.starts_with("<p>Hi!</p><img "));
+    }
+
+    #[test]
+    fn pixel_urls_carry_the_delivery() {
+        let tracking = tracking();
+        let delivery = delivery();
+
+        let url = tracking.open_pixel_url("https://example.com", &delivery);
+
+        let token = url
+            .strip_prefix("https://example.com/t/o/")
+            .and_then(|t| t.strip_suffix(".gif"))
+            .unwrap();
+        assert_eq!(tracking.verify_open(token).unwrap(), delivery);
+    }
+
+    #[test]
+    fn tokens_signed_for_another_purpose_are_rejected() {
+        let token = crate::signed_token::sign(
+            &Secret::new("secret".into()),
+            "preferences",
+            &delivery().subject(),
+            chrono::Utc::now() + Duration::days(1),
+        );
+
+        assert_err!(tracking().verify_open(&token));
+    }
+
+    #[test]
+    fn html_links_are_rewritten_whatever_their_quotes() {
+        let html = r#"<p><a href="https://a.example.com">A</a> <A class='b' HREF='https://b.example.com'>B</A> <a href=https://c.example.com>C</a></p>"#;
+
+        let html = rewrite_links(html, ContentFormat::Html, |url| format!("[{}]", url));
+
+        assert_eq!(
+            html,
+            r#"<p><a href="[https://a.example.com]">A</a> <A class='b' HREF='[https://b.example.com]'>B</A> <a href=[https://c.example.com]>C</a></p>"#
+        );
+    }
+
+    #[test]
+    fn html_links_are_unescaped() {
+        let html = r#"<a href="https://example.com/?a=1&amp;b=2">Link</a>"#;
+
+        assert_eq!(
+            issue_links(html, ""),
+            vec!["https://example.com/?a=1&b=2".to_string()]
+        );
+    }
+
+    #[test]
+    fn only_web_links_without_merge_tags_are_tracked() {
+        let html = r##"<a href="mailto:ursula@example.com">Mail</a> <a href="#top">Top</a>
+            <a data-href="https://example.com">Data</a> <abbr href="https://example.com">Abbr</abbr>
+            <a href="https://example.com/?email={{ email }}">Personal</a>"##;
+        let text = "Reply to ursula@example.com, or see http://example.com/{{email}}";
+
+        assert!(issue_links(html, text).is_empty());
+    }
+
+    #[test]
+    fn bare_urls_are_rewritten_in_plain_text() {
+        let text = "Read https://example.com/post?id=1. Or (https://example.com/other), http://example.com";
+
+        let text = rewrite_links(text, ContentFormat::Text, |url| format!("[{}]", url));
+
+        assert_eq!(
+            text,
+            "Read [https://example.com/post?id=1]. Or ([https://example.com/other]), [http://example.com]"
+        );
+    }
+
+    #[test]
+    fn click_urls_carry_the_delivery_and_the_target() {
+        let tracking = tracking();
+        let delivery = delivery();
+
+        let url = tracking.click_url(
+            "https://example.com",
+            &delivery,
+            "https://a.example.com:8080/",
+        );
+
+        let token = url.strip_prefix("https://example.com/t/c/").unwrap();
+        assert_eq!(
+            tracking.verify_click(token).unwrap(),
+            (delivery, "https://a.example.com:8080/".to_string())
+        );
+        assert_err!(tracking.verify_open(token));
+    }
+}
@@ -0,0 +1,204 @@
+use reqwest::header::{CONTENT_TYPE, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+
+use crate::helpers::{spawn_app, TestApp};
+
+async fn publish_issue(app: &TestApp, title: &str) {
+    let body = serde_json::json!({
+        "title": title,
+        "content": {
+            "text": "Hi {{ name }}, here is our latest news.",
+            "html": "<p>Hi{{ name }}, here is our latest news.</p>",
+        }
+    });
+    let response = app.post_newsletter(&body).await;
+    assert_eq!(response.status().as_u16(), 202);
+}
+
+async fn get(app: &TestApp, path: &str) -> reqwest::Response {
+    reqwest::get(format!("{}{}", app.address, path))
+        .await
+        .expect("Failed to execute request.")
+}
+
+#[tokio::test]
+async fn the_archive_links_to_every_published_issue() {
+    // Arrange
+    let app = spawn_app().await;
+    publish_issue(&app, "Our first issue").await;
+
+    // Act
+    let response = get(&app, "/archive").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.headers()[CONTENT_TYPE]
+        .to_str()
+        .unwrap()
+        .starts_with("text/html"));
+    let page = response.text().await.unwrap();
+    assert!(page.contains("Our first issue"));
+    assert!(page.contains(r#"href="/archive/our-first-issue-"#));
+}
+
+#[tokio::test]
+async fn an_archived_issue_is_shown_without_merge_tags() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_display_name("Ursula Le Guin").await;
+    publish_issue(&app, "Our first issue").await;
+    let index = get(&app, "/archive").await.text().await.unwrap();
+    let start = index.find("/archive/our-first-issue-").unwrap();
+    let end = start + index[start..].find('"').unwrap();
+    let issue_path = &index[start..end];
+
+    // Act
+    let response = get(&app, issue_path).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let page = response.text().await.unwrap();
+    assert!(page.contains("<h1>Our first issue</h1>"));
+    assert!(page.contains("<p>Hi, here is our latest news.</p>"));
+    assert!(page.contains("by Ursula Le Guin"));
+    assert!(!page.contains(&app.test_user.username));
+}
+
+#[tokio::test]
+async fn an_unknown_slug_returns_a_404() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = get(&app, "/archive/not-an-issue").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn feeds_list_published_issues() {
+    // Arrange
+    let app = spawn_app().await;
+    publish_issue(&app, "Fish & chips").await;
+
+    for (path, content_type, item) in [
+        ("/feed.atom", "application/atom+xml", "<entry>"),
+        ("/feed.rss", "application/rss+xml", "<item>"),
+    ] {
+        // Act
+        let response = get(&app, path).await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 200);
+        assert!(response.headers()[CONTENT_TYPE]
+            .to_str()
+            .unwrap()
+            .starts_with(content_type));
+        let feed = response.text().await.unwrap();
+        assert!(feed.contains(item));
+        // Content is escaped to keep the feed well-formed
+        assert!(feed.contains("Fish &amp; chips"));
+        assert!(feed.contains("&lt;p&gt;Hi, here is our latest news.&lt;"));
+    }
+}
+
+#[tokio::test]
+async fn feeds_show_the_display_name_of_authors_rather_than_their_login() {
+    // Arrange
+    let app = spawn_app().await;
+    publish_issue(&app, "Our first issue").await;
+
+    // Act - Part 1 - Without a display name
+    let feed = get(&app, "/feed.atom").await.text().await.unwrap();
+    assert!(!feed.contains(&app.test_user.username));
+
+    // Act - Part 2 - With a display name
+    app.set_display_name("Ursula Le Guin").await;
+    let feed = get(&app, "/feed.atom").await.text().await.unwrap();
+    assert!(feed.contains("<author><name>Ursula Le Guin</name></author>"));
+    assert!(!feed.contains(&app.test_user.username));
+}
+
+#[tokio::test]
+async fn unchanged_feeds_are_not_downloaded_again() {
+    // Arrange
+    let app = spawn_app().await;
+    publish_issue(&app, "Our first issue").await;
+    let response = get(&app, "/feed.atom").await;
+    let etag = response.headers()[ETAG].clone();
+    let last_modified = response.headers()[LAST_MODIFIED].clone();
+    let client = reqwest::Client::new();
+    let url = format!("{}/feed.atom", app.address);
+
+    // Act
+    let with_etag = client
+        .get(&url)
+        .header(IF_NONE_MATCH, etag.clone())
+        .send()
+        .await
+        .unwrap();
+    let with_date = client
+        .get(&url)
+        .header(IF_MODIFIED_SINCE, last_modified.clone())
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(with_etag.status().as_u16(), 304);
+    assert_eq!(with_etag.headers()[ETAG], etag);
+    assert_eq!(with_date.status().as_u16(), 304);
+}
+
+#[tokio::test]
+async fn feeds_are_downloaded_again_once_a_new_issue_is_published() {
+    // Arrange
+    let app = spawn_app().await;
+    publish_issue(&app, "Our first issue").await;
+    let etag = get(&app, "/feed.rss").await.headers()[ETAG].clone();
+    publish_issue(&app, "Our second issue").await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .get(format!("{}/feed.rss", app.address))
+        .header(IF_NONE_MATCH, etag.clone())
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_ne!(response.headers()[ETAG], etag);
+    assert!(response.text().await.unwrap().contains("Our second issue"));
+}
+
+#[tokio::test]
+async fn pages_are_downloaded_again_once_an_author_is_renamed() {
+    // Arrange
+    let app = spawn_app().await;
+    publish_issue(&app, "Our first issue").await;
+    let index = get(&app, "/archive").await.text().await.unwrap();
+    let start = index.find("/archive/our-first-issue-").unwrap();
+    let end = start + index[start..].find('"').unwrap();
+    let issue_path = &index[start..end];
+    let mut etags = Vec::new();
+    for path in ["/feed.atom", issue_path] {
+        etags.push(get(&app, path).await.headers()[ETAG].clone());
+    }
+    app.set_display_name("Ursula Le Guin").await;
+
+    for (path, etag) in ["/feed.atom", issue_path].into_iter().zip(etags) {
+        // Act
+        let response = reqwest::Client::new()
+            .get(format!("{}{}", app.address, path))
+            .header(IF_NONE_MATCH, etag.clone())
+            .send()
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 200, "{}", path);
+        assert_ne!(response.headers()[ETAG], etag);
+        assert!(response.text().await.unwrap().contains("Ursula Le Guin"));
+    }
+}
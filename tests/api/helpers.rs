@@ -1,11 +1,25 @@
+use std::sync::Arc;
+
+use argon2::password_hash::SaltString;
+use argon2::{Algorithm, Argon2, Params, PasswordHasher, Version};
 use once_cell::sync::Lazy;
+use secrecy::{ExposeSecret, Secret};
 use sqlx::{Connection, Executor, PgConnection, PgPool};
 use uuid::Uuid;
-use wiremock::MockServer;
+use wiremock::http::Method::Post;
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
 
-use zero2prod::configuration::{get_configuration, DatabaseSettings};
+use zero2prod::configuration::{
+    get_configuration, DatabaseSettings, EmailWebhookSettings, Settings,
+};
+use zero2prod::email_client::EmailSender;
+use zero2prod::issue_delivery_worker::{try_execute_task, ExecutionOutcome};
+use zero2prod::issue_scheduler::try_dispatch_due_issue;
 use zero2prod::startup::{get_connection_pool, Application};
 use zero2prod::telemetry::{get_subscriber, init_subscriber};
+use zero2prod::templates::Templates;
+use zero2prod::tracking::Tracking;
 
 // Ensure that the `tracing` stack is only initialised once using `once_cell`
 static TRACING: Lazy<()> = Lazy::new(|| {
@@ -25,6 +39,51 @@ pub struct TestApp {
     pub address: String,
     pub db_pool: PgPool,
     pub email_server: MockServer,
+    pub test_user: TestUser,
+    pub email_client: Arc<dyn EmailSender>,
+    pub templates: Templates,
+    pub hmac_secret: Secret<String>,
+    pub email_webhooks: EmailWebhookSettings,
+    pub tracking: Tracking,
+}
+
+pub struct TestUser {
+    pub user_id: Uuid,
+    pub username: String,
+    pub password: String,
+}
+
+impl TestUser {
+    pub fn generate() -> Self {
+        Self {
+            user_id: Uuid::new_v4(),
+            username: Uuid::new_v4().to_string(),
+            password: Uuid::new_v4().to_string(),
+        }
+    }
+
+    async fn store(&self, pool: &PgPool) {
+        let salt = SaltString::generate(&mut rand::thread_rng());
+        // Match the production parameters, we don't want tests to pass with weaker hashes
+        let password_hash = Argon2::new(
+            Algorithm::Argon2id,
+            Version::V0x13,
+            Params::new(15000, 2, 1, None).unwrap(),
+        )
+        .hash_password(self.password.as_bytes(), &salt)
+        .unwrap()
+        .to_string();
+        sqlx::query!(
+            "INSERT INTO users (user_id, username, password_hash)
+            VALUES ($1, $2, $3)",
+            self.user_id,
+            self.username,
+            password_hash,
+        )
+        .execute(pool)
+        .await
+        .expect("Failed to store test user.");
+    }
 }
 
 pub struct ConfirmationLinks {
@@ -32,9 +91,35 @@ pub struct ConfirmationLinks {
     pub plain_text: reqwest::Url,
 }
 impl TestApp {
+    /// Drain the delivery queue, the way the background worker would.
+    pub async fn dispatch_all_pending_emails(&self) {
+        loop {
+            if let ExecutionOutcome::EmptyQueue = try_execute_task(
+                &self.db_pool,
+                self.email_client.as_ref(),
+                &self.templates,
+                &self.address,
+                &self.tracking,
+            )
+            .await
+            .unwrap()
+            {
+                break;
+            }
+        }
+    }
+
+    /// Queue the delivery of every scheduled issue that is due, the way the
+    /// scheduler would.
+    pub async fn dispatch_due_issues(&self) {
+        while let ExecutionOutcome::TaskCompleted =
+            try_dispatch_due_issue(&self.db_pool).await.unwrap()
+        {}
+    }
+
     pub async fn post_subscriptions(&self, body: String) -> reqwest::Response {
         reqwest::Client::new()
-            .post(&format!("{}/subscriptions", &self.address))
+            .post(format!("{}/subscriptions", &self.address))
             .header("Content-Type", "application/x-www-form-urlencoded")
             .body(body)
             .send()
@@ -65,9 +150,142 @@ impl TestApp {
         ConfirmationLinks { html, plain_text }
     }
 
-    pub async  fn post_newsletter(&self, body: &serde_json::Value) -> reqwest::Response {
+    /// Extract the `List-Unsubscribe` link from a request to the email API.
+    pub fn get_unsubscribe_link(&self, email_request: &wiremock::Request) -> reqwest::Url {
+        let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+        let headers = body["Headers"].as_array().unwrap();
+        let header_value = |name: &str| {
+            headers
+                .iter()
+                .find(|h| h["Name"] == name)
+                .and_then(|h| h["Value"].as_str())
+                .unwrap()
+        };
+        assert_eq!(
+            header_value("List-Unsubscribe-Post"),
+            "List-Unsubscribe=One-Click"
+        );
+        let raw_link = header_value("List-Unsubscribe")
+            .strip_prefix('<')
+            .and_then(|l| l.strip_suffix('>'))
+            .unwrap();
+        let unsubscribe_link = reqwest::Url::parse(raw_link).unwrap();
+        // Let's make sure we don't call random APIs on the web
+        assert_eq!(unsubscribe_link.port(), Some(self.port));
+        unsubscribe_link
+    }
+
+    pub async fn post_preferences_link(&self, body: &str) -> reqwest::Response {
         reqwest::Client::new()
-            .post(&format!("{}/newsletters", &self.address))
+            .post(format!("{}/preferences/link", &self.address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(body.to_owned())
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// Ask for a link to the preference center and return the one emailed out.
+    pub async fn get_preferences_link(&self) -> reqwest::Url {
+        let _mock_guard = Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount_as_scoped(&self.email_server)
+            .await;
+        let n_received = self.email_server.received_requests().await.unwrap().len();
+        self.post_preferences_link("email=ursula_le_guin%40gmail.com")
+            .await
+            .error_for_status()
+            .unwrap();
+        let email_request = self.wait_for_emails(n_received + 1).await.pop().unwrap();
+        self.get_confirmation_links(&email_request).html
+    }
+
+    /// Wait until the email server has received `n` requests in total: some
+    /// emails are sent in the background, after the response.
+    pub async fn wait_for_emails(&self, n: usize) -> Vec<wiremock::Request> {
+        for _ in 0..50 {
+            let requests = self.email_server.received_requests().await.unwrap();
+            if requests.len() >= n {
+                return requests;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+        panic!("The email server did not receive {} requests.", n);
+    }
+
+    pub async fn post_preferences(&self, link: &reqwest::Url, body: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(link.clone())
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(body.to_owned())
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_list(&self, slug: &str, name: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/lists", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(&serde_json::json!({ "slug": slug, "name": name }))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// The name shown as the author of the test user's issues.
+    pub async fn set_display_name(&self, display_name: &str) {
+        sqlx::query!(
+            "UPDATE users SET display_name = $1 WHERE user_id = $2",
+            display_name,
+            self.test_user.user_id
+        )
+        .execute(&self.db_pool)
+        .await
+        .unwrap();
+    }
+
+    pub async fn post_bounce(&self, bounce: &serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/webhooks/email/bounce", &self.address))
+            .basic_auth(
+                &self.email_webhooks.username,
+                Some(self.email_webhooks.password.expose_secret()),
+            )
+            .json(bounce)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_complaint(&self, complaint: &serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/webhooks/email/complaint", &self.address))
+            .basic_auth(
+                &self.email_webhooks.username,
+                Some(self.email_webhooks.password.expose_secret()),
+            )
+            .json(complaint)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_newsletter(&self, body: &serde_json::Value) -> reqwest::Response {
+        self.post_newsletter_with_idempotency_key(body, &Uuid::new_v4().to_string())
+            .await
+    }
+
+    pub async fn post_newsletter_with_idempotency_key(
+        &self,
+        body: &serde_json::Value,
+        idempotency_key: &str,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/newsletters", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .header("Idempotency-Key", idempotency_key)
             .json(&body)
             .send()
             .await
@@ -75,7 +293,62 @@ impl TestApp {
     }
 }
 
+/// A bounce as Postmark's webhook sends it.
+pub fn bounce(id: i64, bounce_type: &str, email: &str) -> serde_json::Value {
+    serde_json::json!({
+        "RecordType": "Bounce",
+        "ID": id,
+        "Type": bounce_type,
+        "TypeCode": 1,
+        "Name": "Hard bounce",
+        "Tag": "",
+        "MessageID": "883953f4-6105-42a2-a16a-77a8eac79483",
+        "ServerID": 23,
+        "MessageStream": "outbound",
+        "Description": "The server was unable to deliver your message (ex: unknown user, mailbox not found).",
+        "Details": "smtp;550 5.1.1 The email account that you tried to reach does not exist.",
+        "Email": email,
+        "From": "something@gmail.com",
+        "BouncedAt": "2022-07-11T16:33:54.9070259Z",
+        "DumpAvailable": true,
+        "Inactive": true,
+        "CanActivate": true,
+        "Subject": "Newsletter title",
+        "Content": null
+    })
+}
+
+/// A spam complaint as Postmark's webhook sends it.
+pub fn complaint(id: i64, email: &str) -> serde_json::Value {
+    serde_json::json!({
+        "RecordType": "SpamComplaint",
+        "ID": id,
+        "Type": "SpamComplaint",
+        "TypeCode": 512,
+        "Name": "Spam complaint",
+        "Tag": "",
+        "MessageID": "00000000-0000-0000-0000-000000000000",
+        "ServerID": 23,
+        "MessageStream": "outbound",
+        "Description": "",
+        "Details": "Test spam complaint details",
+        "Email": email,
+        "From": "something@gmail.com",
+        "BouncedAt": "2022-07-12T09:12:41.0870259Z",
+        "DumpAvailable": true,
+        "Inactive": true,
+        "CanActivate": false,
+        "Subject": "Newsletter title",
+        "Content": null
+    })
+}
+
 pub async fn spawn_app() -> TestApp {
+    spawn_app_with(|_| {}).await
+}
+
+/// Spawn the application, after letting `customise` tweak its configuration.
+pub async fn spawn_app_with(customise: impl FnOnce(&mut Settings)) -> TestApp {
     Lazy::force(&TRACING);
 
     // Launch a mock server to stand in for Postmark's API
@@ -89,7 +362,8 @@ pub async fn spawn_app() -> TestApp {
         // Use a random OS port
         c.application.port = 0;
         // Use the mock server as email API
-        c.email_client.base_url = email_server.uri();
+        c.email_client.base_url = Some(email_server.uri());
+        customise(&mut c);
         c
     };
 
@@ -101,14 +375,70 @@ pub async fn spawn_app() -> TestApp {
         .await
         .expect("Failed to build application.");
     let application_port = application.port();
+    #[allow(clippy::let_underscore_future)]
     let _ = tokio::spawn(application.run_until_stopped());
 
-    TestApp {
+    let db_pool = get_connection_pool(&configuration.database);
+    let test_app = TestApp {
         address: format!("http://localhost:{}", application_port),
         port: application_port,
-        db_pool: get_connection_pool(&configuration.database),
+        email_client: configuration.email_client.client(&db_pool),
+        db_pool,
         email_server,
-    }
+        test_user: TestUser::generate(),
+        templates: configuration.templates.load().unwrap(),
+        tracking: configuration
+            .tracking
+            .tracking(configuration.application.hmac_secret.clone()),
+        hmac_secret: configuration.application.hmac_secret,
+        email_webhooks: configuration.email_webhooks,
+    };
+    test_app.test_user.store(&test_app.db_pool).await;
+    test_app
+}
+
+pub async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
+    create_unconfirmed_subscriber_with_email(app, "ursula_le_guin%40gmail.com").await
+}
+
+pub async fn create_unconfirmed_subscriber_with_email(
+    app: &TestApp,
+    email: &str,
+) -> ConfirmationLinks {
+    let body = format!("name=le%20guin&email={}", email);
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method(Post))
+        .respond_with(ResponseTemplate::new(200))
+        .named("Create unconfirmed subscriber")
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body)
+        .await
+        .error_for_status()
+        .unwrap();
+    let email_request = &app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    app.get_confirmation_links(email_request)
+}
+
+pub async fn create_confirmed_subscriber(app: &TestApp) {
+    create_confirmed_subscriber_with_email(app, "ursula_le_guin%40gmail.com").await
+}
+
+pub async fn create_confirmed_subscriber_with_email(app: &TestApp, email: &str) {
+    let confirmation_links = create_unconfirmed_subscriber_with_email(app, email).await;
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
 }
 
 async fn configure_database(config: &DatabaseSettings) -> PgPool {
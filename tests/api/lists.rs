@@ -0,0 +1,358 @@
+use wiremock::matchers::{any, body_string_contains};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{create_confirmed_subscriber, spawn_app, ConfirmationLinks, TestApp};
+
+fn issue_for_lists(lists: &[&str]) -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        },
+        "lists": lists,
+    })
+}
+
+impl TestApp {
+    /// Sign `email` up to `list` and return the confirmation links they received.
+    async fn join_list(&self, email: &str, list: &str) -> ConfirmationLinks {
+        let _mock_guard = Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount_as_scoped(&self.email_server)
+            .await;
+        self.post_subscriptions(format!("name=le%20guin&email={}&list={}", email, list))
+            .await
+            .error_for_status()
+            .unwrap();
+        let email_request = self
+            .email_server
+            .received_requests()
+            .await
+            .unwrap()
+            .pop()
+            .unwrap();
+        self.get_confirmation_links(&email_request)
+    }
+
+    async fn join_and_confirm_list(&self, email: &str, list: &str) {
+        let confirmation_links = self.join_list(email, list).await;
+        reqwest::get(confirmation_links.html)
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap();
+    }
+}
+
+#[tokio::test]
+async fn created_lists_are_listed_along_with_the_default_one() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    for (slug, name) in [
+        ("product-updates", "Product updates"),
+        ("engineering-blog", "Engineering blog"),
+    ] {
+        let response = app.post_list(slug, name).await;
+        assert_eq!(response.status().as_u16(), 201);
+    }
+
+    // Assert
+    let lists: serde_json::Value = reqwest::get(format!("{}/lists", app.address))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(
+        lists,
+        serde_json::json!([
+            { "slug": "engineering-blog", "name": "Engineering blog" },
+            { "slug": "newsletter", "name": "Newsletter" },
+            { "slug": "product-updates", "name": "Product updates" },
+        ])
+    );
+}
+
+#[tokio::test]
+async fn creating_a_list_requires_authentication() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!("{}/lists", app.address))
+        .json(&serde_json::json!({ "slug": "product-updates", "name": "Product updates" }))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+    assert_eq!(
+        r#"Basic realm="publish""#,
+        response.headers()["WWW-Authenticate"]
+    );
+}
+
+#[tokio::test]
+async fn creating_a_list_with_a_taken_slug_is_rejected_with_a_409() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.post_list("newsletter", "Another newsletter").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 409);
+}
+
+#[tokio::test]
+async fn creating_an_invalid_list_is_rejected_with_a_400() {
+    // Arrange
+    let app = spawn_app().await;
+    let test_cases = vec![
+        ("Product Updates", "Product updates", "an invalid slug"),
+        ("product-updates", "  ", "an empty name"),
+    ];
+
+    for (slug, name, description) in test_cases {
+        // Act
+        let response = app.post_list(slug, name).await;
+
+        // Assert
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The API did not fail with 400 Bad Request for {}.",
+            description
+        );
+    }
+}
+
+#[tokio::test]
+async fn subscribing_to_an_unknown_list_is_rejected_with_a_400() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com&list=unknown".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn the_confirmation_email_names_the_list() {
+    // Arrange
+    let app = spawn_app().await;
+    app.post_list("engineering-blog", "Engineering blog").await;
+    Mock::given(body_string_contains("Welcome to Engineering blog"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_subscriptions(
+            "name=le%20guin&email=ursula_le_guin%40gmail.com&list=engineering-blog".into(),
+        )
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn joining_another_list_requires_its_own_confirmation() {
+    // Arrange
+    let app = spawn_app().await;
+    app.post_list("engineering-blog", "Engineering blog").await;
+    create_confirmed_subscriber(&app).await;
+    let confirmation_links = app
+        .join_list("ursula_le_guin%40gmail.com", "engineering-blog")
+        .await;
+
+    // Act - Part 1 - Publish before confirming
+    let _mock_guard = Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_newsletter(&issue_for_lists(&["engineering-blog"]))
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+    drop(_mock_guard);
+
+    // Act - Part 2 - Publish after confirming
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.post_newsletter(&issue_for_lists(&["engineering-blog"]))
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+    // Mock verifies on Drop that only the second issue was delivered
+}
+
+#[tokio::test]
+async fn issues_are_only_delivered_to_the_lists_they_target() {
+    // Arrange
+    let app = spawn_app().await;
+    app.post_list("product-updates", "Product updates").await;
+    app.post_list("engineering-blog", "Engineering blog").await;
+    app.join_and_confirm_list("product%40example.com", "product-updates")
+        .await;
+    app.join_and_confirm_list("engineering%40example.com", "engineering-blog")
+        .await;
+    Mock::given(body_string_contains("product@example.com"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    Mock::given(body_string_contains("engineering@example.com"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_newsletter(&issue_for_lists(&["product-updates"]))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+    // Mocks verify on Drop that only the product list received the issue
+}
+
+#[tokio::test]
+async fn members_of_several_targeted_lists_receive_an_issue_once() {
+    // Arrange
+    let app = spawn_app().await;
+    app.post_list("product-updates", "Product updates").await;
+    app.post_list("engineering-blog", "Engineering blog").await;
+    app.join_and_confirm_list("ursula_le_guin%40gmail.com", "product-updates")
+        .await;
+    app.join_and_confirm_list("ursula_le_guin%40gmail.com", "engineering-blog")
+        .await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_newsletter(&issue_for_lists(&["product-updates", "engineering-blog"]))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+    // Mock verifies on Drop that the issue was delivered once
+}
+
+#[tokio::test]
+async fn publishing_to_unknown_or_no_lists_is_rejected_with_a_400() {
+    // Arrange
+    let app = spawn_app().await;
+    let test_cases = vec![
+        (issue_for_lists(&["unknown"]), "an unknown list"),
+        (issue_for_lists(&["Not A Slug"]), "an invalid slug"),
+        (issue_for_lists(&[]), "no list"),
+    ];
+
+    for (body, description) in test_cases {
+        // Act
+        let response = app.post_newsletter(&body).await;
+
+        // Assert
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The API did not fail with 400 Bad Request when publishing to {}.",
+            description
+        );
+    }
+}
+
+#[tokio::test]
+async fn unsubscribing_from_a_list_keeps_the_other_lists() {
+    // Arrange
+    let app = spawn_app().await;
+    app.post_list("product-updates", "Product updates").await;
+    app.post_list("engineering-blog", "Engineering blog").await;
+    app.join_and_confirm_list("ursula_le_guin%40gmail.com", "product-updates")
+        .await;
+    app.join_and_confirm_list("ursula_le_guin%40gmail.com", "engineering-blog")
+        .await;
+    let _mock_guard = Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_newsletter(&issue_for_lists(&["product-updates"]))
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let unsubscribe_link = app.get_unsubscribe_link(&email_request);
+    drop(_mock_guard);
+
+    // Act - Part 1 - Leave the product list
+    reqwest::Client::new()
+        .post(unsubscribe_link)
+        .body("List-Unsubscribe=One-Click")
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Act - Part 2 - Publish an issue to each list
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.post_newsletter(&issue_for_lists(&["product-updates"]))
+        .await
+        .error_for_status()
+        .unwrap();
+    app.post_newsletter(&issue_for_lists(&["engineering-blog"]))
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+    // Mock verifies on Drop that only the engineering issue was delivered
+}
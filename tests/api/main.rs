@@ -3,3 +3,14 @@ mod health_check;
 mod subscriptions;
 mod subscriptions_confirm;
 mod newsletters;
+mod subscriptions_unsubscribe;
+mod newsletters_archive;
+mod archive;
+mod newsletters_scheduled;
+mod lists;
+mod preferences;
+mod privacy;
+mod webhooks;
+mod suppressions;
+mod tracking;
+mod newsletters_stats;
@@ -1,12 +1,11 @@
-use reqwest::Response;
-use serde_json::Value;
-use wiremock::http::Method::Post;
-use wiremock::matchers::{any, method, path};
+use uuid::Uuid;
+use wiremock::matchers::{any, body_string_contains, method, path};
 use wiremock::{Mock, ResponseTemplate};
 
-use zero2prod::startup::Application;
-
-use crate::helpers::{spawn_app, ConfirmationLinks, TestApp};
+use crate::helpers::{
+    create_confirmed_subscriber, create_confirmed_subscriber_with_email,
+    create_unconfirmed_subscriber, spawn_app,
+};
 
 #[tokio::test]
 async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
@@ -29,15 +28,12 @@ async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
 "html" : "<p>Newsletter body as HTML</p>" ,
 }
 } );
-    let response = reqwest::Client::new()
-        .post(&format!("{}/newsletters", &app.address))
-        .json(&newsletter_request_body)
-        .send()
-        .await
-        .expect("Failed to execute request");
+    let response = app.post_newsletter(&newsletter_request_body).await;
 
     // Assert
-    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+    // Mock verifies on Drop that we haven't sent the newsletter email
 }
 
 #[tokio::test]
@@ -63,38 +59,9 @@ async fn newsletters_are_delivered_to_confirmed_subscribers() {
     let response = app.post_newsletter(&newsletter_request_body).await;
 
     // Assert
-    assert_eq!(response.status().as_u16(), 200);
-}
-async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
-    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
-    let _mock_guard = Mock::given(path("/email"))
-        .and(method(Post))
-        .respond_with(ResponseTemplate::new(200))
-        .named("Create unconfirmed subscriber")
-        .expect(1)
-        .mount_as_scoped(&app.email_server)
-        .await;
-
-    app.post_subscriptions(body.into())
-        .await
-        .error_for_status()
-        .unwrap();
-    let email_request = &app
-        .email_server
-        .received_requests()
-        .await
-        .unwrap()
-        .pop()
-        .unwrap();
-    app.get_confirmation_links(&email_request)
-}
-async fn create_confirmed_subscriber(app: &TestApp) {
-    let confirmation_links = create_unconfirmed_subscriber(app).await;
-    reqwest::get(confirmation_links.html)
-        .await
-        .unwrap()
-        .error_for_status()
-        .unwrap();
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+    // Mock verifies on Drop that we have sent the newsletter email
 }
 
 #[tokio::test]
@@ -117,13 +84,361 @@ async fn newsletters_returns_400_for_invalid_data() {
         ),
     ];
 
-    for (invalid_body, error_messages) in test_cases {
-        let response = app.post_newsletter( &invalid_body).await;
+    for (invalid_body, error_message) in test_cases {
+        let response = app.post_newsletter(&invalid_body).await;
 
         // Assert
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not fail with 400 Bad Request when the payload was {}.",
+            error_message
+        );
+    }
+}
+
+fn newsletter_request_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    })
+}
+
+#[tokio::test]
+async fn requests_missing_authorization_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletters", &app.address))
+        .json(&newsletter_request_body())
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(401, response.status().as_u16());
+    assert_eq!(
+        r#"Basic realm="publish""#,
+        response.headers()["WWW-Authenticate"]
+    );
+}
+
+#[tokio::test]
+async fn requests_with_malformed_authorization_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    let test_cases = vec![
+        ("Bearer some-token", "a non-Basic scheme"),
+        ("Basic not%base64", "invalid base64"),
+        // base64 of "no-colon-here"
+        ("Basic bm8tY29sb24taGVyZQ==", "no password separator"),
+        ("Basic", "no credentials at all"),
+    ];
+
+    for (header_value, description) in test_cases {
+        // Act
+        let response = reqwest::Client::new()
+            .post(format!("{}/newsletters", &app.address))
+            .header("Authorization", header_value)
+            .json(&newsletter_request_body())
+            .send()
+            .await
+            .expect("Failed to execute request.");
+
+        // Assert
+        assert_eq!(
+            401,
+            response.status().as_u16(),
+            "The API did not fail with 401 Unauthorized when the header had {}.",
+            description
+        );
+        assert_eq!(
+            r#"Basic realm="publish""#,
+            response.headers()["WWW-Authenticate"]
+        );
+    }
+}
+
+#[tokio::test]
+async fn non_existing_user_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    // Random credentials
+    let username = Uuid::new_v4().to_string();
+    let password = Uuid::new_v4().to_string();
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletters", &app.address))
+        .basic_auth(username, Some(password))
+        .json(&newsletter_request_body())
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(401, response.status().as_u16());
+    assert_eq!(
+        r#"Basic realm="publish""#,
+        response.headers()["WWW-Authenticate"]
+    );
+}
+
+#[tokio::test]
+async fn invalid_password_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    let username = &app.test_user.username;
+    // Random password
+    let password = Uuid::new_v4().to_string();
+    assert_ne!(app.test_user.password, password);
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletters", &app.address))
+        .basic_auth(username, Some(password))
+        .json(&newsletter_request_body())
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(401, response.status().as_u16());
+    assert_eq!(
+        r#"Basic realm="publish""#,
+        response.headers()["WWW-Authenticate"]
+    );
+}
+
+#[tokio::test]
+async fn newsletter_creation_is_idempotent() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let idempotency_key = Uuid::new_v4().to_string();
+
+    // Act - Part 1 - Publish the newsletter
+    let response = app
+        .post_newsletter_with_idempotency_key(&newsletter_request_body(), &idempotency_key)
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+    assert_eq!(
+        response.text().await.unwrap(),
+        "Newsletter issue accepted for delivery"
+    );
+
+    // Act - Part 2 - Retry the same request
+    let response = app
+        .post_newsletter_with_idempotency_key(&newsletter_request_body(), &idempotency_key)
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+    assert_eq!(
+        response.text().await.unwrap(),
+        "Newsletter issue accepted for delivery"
+    );
+
+    app.dispatch_all_pending_emails().await;
+
+    // Mock verifies on Drop that we have sent the newsletter email **once**
+}
+
+#[tokio::test]
+async fn concurrent_newsletter_requests_are_handled_gracefully() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let idempotency_key = Uuid::new_v4().to_string();
+
+    // Act - Submit two newsletter requests concurrently
+    let body = newsletter_request_body();
+    let response1 = app.post_newsletter_with_idempotency_key(&body, &idempotency_key);
+    let response2 = app.post_newsletter_with_idempotency_key(&body, &idempotency_key);
+    let (response1, response2) = tokio::join!(response1, response2);
+
+    // Assert
+    assert_eq!(response1.status(), response2.status());
+    assert_eq!(
+        response1.text().await.unwrap(),
+        response2.text().await.unwrap()
+    );
+    app.dispatch_all_pending_emails().await;
+
+    // Mock verifies on Drop that we have sent the newsletter email **once**
+}
+
+#[tokio::test]
+async fn different_idempotency_keys_publish_separately() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response1 = app.post_newsletter(&newsletter_request_body()).await;
+    let response2 = app.post_newsletter(&newsletter_request_body()).await;
 
-        assert_eq!(400, response.status().as_u16());
+    // Assert
+    assert_eq!(response1.status().as_u16(), 202);
+    assert_eq!(response2.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn requests_with_a_missing_or_invalid_idempotency_key_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act - Part 1 - No header at all
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletters", &app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .json(&newsletter_request_body())
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(400, response.status().as_u16());
+
+    // Act - Part 2 - Keys that do not satisfy our constraints
+    let test_cases = vec![
+        ("".to_string(), "an empty key"),
+        ("a".repeat(50), "a key that is too long"),
+    ];
+    for (idempotency_key, description) in test_cases {
+        let response = app
+            .post_newsletter_with_idempotency_key(&newsletter_request_body(), &idempotency_key)
+            .await;
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not fail with 400 Bad Request when the request had {}.",
+            description
+        );
     }
 }
 
+#[tokio::test]
+async fn a_failed_delivery_does_not_prevent_delivery_to_other_subscribers() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber_with_email(&app, "ursula_le_guin%40gmail.com").await;
+    create_confirmed_subscriber_with_email(&app, "octavia_butler%40gmail.com").await;
+    // Every delivery attempt to the first subscriber fails
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .and(body_string_contains("ursula_le_guin@gmail.com"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1..)
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .and(body_string_contains("octavia_butler@gmail.com"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_newsletter(&newsletter_request_body()).await;
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let n_pending = sqlx::query!("SELECT COUNT(*) as \"count!\" FROM issue_delivery_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_pending, 0);
+}
+
+#[tokio::test]
+async fn merge_tags_are_expanded_for_each_recipient() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber_with_email(&app, "ursula_le_guin%40gmail.com").await;
+    create_confirmed_subscriber_with_email(&app, "octavia_butler%40gmail.com").await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Hi {{ name }}, this issue was sent to {{ email }}.",
+            "html": "<p>Hi {{ name }}, this issue was sent to {{email}}.</p>",
+        }
+    });
+    let response = app.post_newsletter(&newsletter_request_body).await;
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let email_requests = app.email_server.received_requests().await.unwrap();
+    // Skip the confirmation emails
+    for email_request in &email_requests[2..] {
+        let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+        let recipient = body["To"].as_str().unwrap();
+        let expected = format!("Hi le guin, this issue was sent to {}.", recipient);
+        assert!(body["TextBody"].as_str().unwrap().contains(&expected));
+        assert!(body["HtmlBody"].as_str().unwrap().contains(&expected));
+    }
+}
 
+#[tokio::test]
+async fn newsletters_referencing_an_unknown_merge_tag_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Hi {{ nickname }}",
+            "html": "<p>Hi</p>",
+        }
+    });
+    let response = app.post_newsletter(&newsletter_request_body).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    assert!(response.text().await.unwrap().contains("nickname"));
+    app.dispatch_all_pending_emails().await;
+}
@@ -0,0 +1,176 @@
+use uuid::Uuid;
+
+use crate::helpers::{spawn_app, TestApp};
+
+async fn publish_issue(app: &TestApp, title: &str) {
+    let body = serde_json::json!({
+        "title": title,
+        "content": {
+            "text": format!("{} as plain text", title),
+            "html": format!("<p>{} as HTML</p>", title),
+        }
+    });
+    let response = app.post_newsletter(&body).await;
+    assert_eq!(response.status().as_u16(), 202);
+}
+
+async fn get_json(url: &str) -> (u16, serde_json::Value) {
+    let response = reqwest::get(url).await.expect("Failed to execute request.");
+    let status = response.status().as_u16();
+    let body = response.json().await.unwrap_or(serde_json::Value::Null);
+    (status, body)
+}
+
+#[tokio::test]
+async fn published_issues_can_be_retrieved() {
+    // Arrange
+    let app = spawn_app().await;
+    app.set_display_name("Ursula Le Guin").await;
+    publish_issue(&app, "First issue").await;
+
+    // Act - Part 1 - List
+    let (status, page) = get_json(&format!("{}/newsletters", app.address)).await;
+
+    // Assert
+    assert_eq!(status, 200);
+    assert_eq!(page["total"], 1);
+    let summary = &page["issues"][0];
+    assert_eq!(summary["title"], "First issue");
+    assert_eq!(summary["author"], "Ursula Le Guin");
+
+    // Act - Part 2 - Details
+    let (status, issue) = get_json(&format!(
+        "{}/newsletters/{}",
+        app.address,
+        summary["id"].as_str().unwrap()
+    ))
+    .await;
+
+    // Assert
+    assert_eq!(status, 200);
+    assert_eq!(issue["title"], "First issue");
+    assert_eq!(issue["author"], "Ursula Le Guin");
+    assert_eq!(issue["content"]["text"], "First issue as plain text");
+    assert_eq!(issue["content"]["html"], "<p>First issue as HTML</p>");
+    assert_eq!(issue["published_at"], summary["published_at"]);
+}
+
+#[tokio::test]
+async fn issues_are_retrieved_without_merge_tags() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = serde_json::json!({
+        "title": "Personal issue",
+        "content": {
+            "text": "Hi {{ name }}, this was sent to {{ email }}.",
+            "html": "<p>Hi {{ name }}, this was sent to {{ email }}.</p>",
+        }
+    });
+    let response = app.post_newsletter(&body).await;
+    assert_eq!(response.status().as_u16(), 202);
+    let (_, page) = get_json(&format!("{}/newsletters", app.address)).await;
+
+    // Act
+    let (status, issue) = get_json(&format!(
+        "{}/newsletters/{}",
+        app.address,
+        page["issues"][0]["id"].as_str().unwrap()
+    ))
+    .await;
+
+    // Assert
+    assert_eq!(status, 200);
+    assert_eq!(issue["content"]["text"], "Hi , this was sent to .");
+    assert_eq!(issue["content"]["html"], "<p>Hi , this was sent to .</p>");
+}
+
+#[tokio::test]
+async fn the_login_of_the_author_is_never_exposed() {
+    // Arrange
+    let app = spawn_app().await;
+    publish_issue(&app, "First issue").await;
+    let (_, page) = get_json(&format!("{}/newsletters", app.address)).await;
+    let summary = &page["issues"][0];
+
+    // Act
+    let (_, issue) = get_json(&format!(
+        "{}/newsletters/{}",
+        app.address,
+        summary["id"].as_str().unwrap()
+    ))
+    .await;
+
+    // Assert
+    assert!(summary["author"].is_null());
+    assert!(issue["author"].is_null());
+    assert!(!page.to_string().contains(&app.test_user.username));
+    assert!(!issue.to_string().contains(&app.test_user.username));
+}
+
+#[tokio::test]
+async fn issues_are_listed_most_recent_first_and_paginated() {
+    // Arrange
+    let app = spawn_app().await;
+    for title in ["First issue", "Second issue", "Third issue"] {
+        publish_issue(&app, title).await;
+    }
+
+    // Act
+    let (_, first_page) =
+        get_json(&format!("{}/newsletters?per_page=2", app.address)).await;
+    let (_, second_page) =
+        get_json(&format!("{}/newsletters?per_page=2&page=2", app.address)).await;
+
+    // Assert
+    assert_eq!(first_page["total"], 3);
+    let titles = |page: &serde_json::Value| -> Vec<String> {
+        page["issues"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|i| i["title"].as_str().unwrap().to_owned())
+            .collect()
+    };
+    assert_eq!(titles(&first_page), vec!["Third issue", "Second issue"]);
+    assert_eq!(titles(&second_page), vec!["First issue"]);
+}
+
+#[tokio::test]
+async fn invalid_pagination_parameters_are_rejected_with_a_400() {
+    // Arrange
+    let app = spawn_app().await;
+    let test_cases = vec![
+        ("page=0", "a zero page"),
+        ("per_page=0", "an empty page size"),
+        ("per_page=1000", "a page size that is too large"),
+        ("page=first", "a page that is not a number"),
+        (
+            "page=9223372036854775807",
+            "a page so far that its offset overflows",
+        ),
+    ];
+
+    for (query, description) in test_cases {
+        // Act
+        let (status, _) = get_json(&format!("{}/newsletters?{}", app.address, query)).await;
+
+        // Assert
+        assert_eq!(
+            status, 400,
+            "The API did not fail with 400 Bad Request when the request had {}.",
+            description
+        );
+    }
+}
+
+#[tokio::test]
+async fn an_unknown_issue_returns_a_404() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let (status, _) = get_json(&format!("{}/newsletters/{}", app.address, Uuid::new_v4())).await;
+
+    // Assert
+    assert_eq!(status, 404);
+}
@@ -0,0 +1,287 @@
+use chrono::{Duration, Utc};
+use uuid::Uuid;
+use wiremock::matchers::any;
+use wiremock::{Mock, ResponseTemplate};
+
+use zero2prod::issue_delivery_worker::ExecutionOutcome;
+use zero2prod::issue_scheduler::try_dispatch_due_issue;
+
+use crate::helpers::{create_confirmed_subscriber, spawn_app, TestApp};
+
+fn scheduled_issue(send_at: chrono::DateTime<Utc>) -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        },
+        "send_at": send_at,
+    })
+}
+
+impl TestApp {
+    async fn get_scheduled_issues(&self) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!("{}/newsletters/scheduled", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    async fn put_scheduled_issue(
+        &self,
+        id: &str,
+        send_at: chrono::DateTime<Utc>,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .put(format!("{}/newsletters/scheduled/{}", &self.address, id))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(&serde_json::json!({ "send_at": send_at }))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    async fn delete_scheduled_issue(&self, id: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .delete(format!("{}/newsletters/scheduled/{}", &self.address, id))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// The id of the only scheduled issue.
+    async fn scheduled_issue_id(&self) -> String {
+        let issues: serde_json::Value = self.get_scheduled_issues().await.json().await.unwrap();
+        assert_eq!(issues.as_array().unwrap().len(), 1);
+        issues[0]["id"].as_str().unwrap().to_owned()
+    }
+
+    /// Pretend time has passed until every scheduled issue is due.
+    async fn make_scheduled_issues_due(&self) {
+        sqlx::query!(
+            "UPDATE newsletter_issues SET send_at = now() - interval '1 minute' \
+            WHERE status = 'scheduled'"
+        )
+        .execute(&self.db_pool)
+        .await
+        .unwrap();
+    }
+}
+
+#[tokio::test]
+async fn scheduled_issues_are_not_sent_right_away() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_newsletter(&scheduled_issue(Utc::now() + Duration::hours(1)))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_due_issues().await;
+    app.dispatch_all_pending_emails().await;
+    // Not sent yet, so not in the archive either
+    let archive: serde_json::Value = reqwest::get(format!("{}/newsletters", app.address))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(archive["total"], 0);
+}
+
+#[tokio::test]
+async fn scheduled_issues_are_sent_once_due() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.post_newsletter(&scheduled_issue(Utc::now() + Duration::hours(1)))
+        .await
+        .error_for_status()
+        .unwrap();
+    app.make_scheduled_issues_due().await;
+
+    // Act
+    app.dispatch_due_issues().await;
+    app.dispatch_all_pending_emails().await;
+    app.dispatch_due_issues().await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let scheduled: serde_json::Value = app.get_scheduled_issues().await.json().await.unwrap();
+    assert!(scheduled.as_array().unwrap().is_empty());
+    let archive: serde_json::Value = reqwest::get(format!("{}/newsletters", app.address))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(archive["total"], 1);
+    // Mock verifies on Drop that the issue was sent exactly once
+}
+
+#[tokio::test]
+async fn concurrent_schedulers_dispatch_a_due_issue_only_once() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.post_newsletter(&scheduled_issue(Utc::now() + Duration::hours(1)))
+        .await
+        .error_for_status()
+        .unwrap();
+    app.make_scheduled_issues_due().await;
+
+    // Act
+    let (first, second) = tokio::join!(
+        try_dispatch_due_issue(&app.db_pool),
+        try_dispatch_due_issue(&app.db_pool)
+    );
+
+    // Assert
+    let dispatched = [first.unwrap(), second.unwrap()]
+        .iter()
+        .filter(|o| matches!(o, ExecutionOutcome::TaskCompleted))
+        .count();
+    assert_eq!(dispatched, 1);
+    let queued = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM issue_delivery_queue"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(queued.count, 1);
+}
+
+#[tokio::test]
+async fn send_at_must_be_in_the_future() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_newsletter(&scheduled_issue(Utc::now() - Duration::minutes(1)))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn scheduled_issues_can_be_rescheduled() {
+    // Arrange
+    let app = spawn_app().await;
+    app.post_newsletter(&scheduled_issue(Utc::now() + Duration::hours(1)))
+        .await
+        .error_for_status()
+        .unwrap();
+    let id = app.scheduled_issue_id().await;
+    let send_at = Utc::now() + Duration::days(2);
+
+    // Act
+    let response = app.put_scheduled_issue(&id, send_at).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let issues: serde_json::Value = app.get_scheduled_issues().await.json().await.unwrap();
+    let stored: chrono::DateTime<Utc> =
+        serde_json::from_value(issues[0]["send_at"].clone()).unwrap();
+    assert_eq!(stored.timestamp(), send_at.timestamp());
+    let response = app
+        .put_scheduled_issue(&id, Utc::now() - Duration::minutes(1))
+        .await;
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn cancelled_issues_are_never_sent() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    app.post_newsletter(&scheduled_issue(Utc::now() + Duration::hours(1)))
+        .await
+        .error_for_status()
+        .unwrap();
+    let id = app.scheduled_issue_id().await;
+
+    // Act
+    let response = app.delete_scheduled_issue(&id).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 204);
+    app.make_scheduled_issues_due().await;
+    app.dispatch_due_issues().await;
+    app.dispatch_all_pending_emails().await;
+    // It has left the schedule for good
+    let response = app.delete_scheduled_issue(&id).await;
+    assert_eq!(response.status().as_u16(), 409);
+    let response = app
+        .put_scheduled_issue(&id, Utc::now() + Duration::hours(1))
+        .await;
+    assert_eq!(response.status().as_u16(), 409);
+}
+
+#[tokio::test]
+async fn unknown_scheduled_issues_return_a_404() {
+    // Arrange
+    let app = spawn_app().await;
+    let id = Uuid::new_v4().to_string();
+
+    // Act
+    let cancel = app.delete_scheduled_issue(&id).await;
+    let reschedule = app
+        .put_scheduled_issue(&id, Utc::now() + Duration::hours(1))
+        .await;
+
+    // Assert
+    assert_eq!(cancel.status().as_u16(), 404);
+    assert_eq!(reschedule.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn managing_scheduled_issues_requires_authentication() {
+    // Arrange
+    let app = spawn_app().await;
+    let url = format!("{}/newsletters/scheduled", app.address);
+    let issue_url = format!("{}/{}", url, Uuid::new_v4());
+    let client = reqwest::Client::new();
+
+    // Act
+    let responses = [
+        client.get(&url).send().await.unwrap(),
+        client
+            .put(&issue_url)
+            .json(&serde_json::json!({ "send_at": Utc::now() + Duration::hours(1) }))
+            .send()
+            .await
+            .unwrap(),
+        client.delete(&issue_url).send().await.unwrap(),
+    ];
+
+    // Assert
+    for response in responses {
+        assert_eq!(response.status().as_u16(), 401);
+        assert_eq!(
+            r#"Basic realm="publish""#,
+            response.headers()["WWW-Authenticate"]
+        );
+    }
+}
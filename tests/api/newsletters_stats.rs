@@ -0,0 +1,364 @@
+use uuid::Uuid;
+use wiremock::matchers::{any, body_string_contains};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{
+    bounce, complaint, create_confirmed_subscriber, create_confirmed_subscriber_with_email,
+    spawn_app, TestApp,
+};
+
+impl TestApp {
+    async fn get_issue_stats(&self, id: &str, query: &[(&str, i64)]) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!("{}/newsletters/{}/stats", &self.address, id))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .query(query)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// The id of the only issue.
+    async fn issue_id(&self) -> String {
+        sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+            .fetch_one(&self.db_pool)
+            .await
+            .unwrap()
+            .newsletter_issue_id
+            .to_string()
+    }
+
+    /// Publish an issue and deliver it to every confirmed subscriber.
+    async fn deliver_issue(&self) -> String {
+        self.post_newsletter(&issue())
+            .await
+            .error_for_status()
+            .unwrap();
+        let _mock_guard = Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .mount_as_scoped(&self.email_server)
+            .await;
+        self.dispatch_all_pending_emails().await;
+        self.issue_id().await
+    }
+
+    async fn unsubscribed_total(&self, issue_id: &str) -> serde_json::Value {
+        let stats: serde_json::Value = self
+            .get_issue_stats(issue_id, &[])
+            .await
+            .error_for_status()
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        stats["totals"]["unsubscribed"].clone()
+    }
+
+    /// The last email sent to `recipient`, as received by the email API.
+    async fn email_to(&self, recipient: &str) -> wiremock::Request {
+        self.email_server
+            .received_requests()
+            .await
+            .unwrap()
+            .into_iter()
+            .rev()
+            .find(|r| {
+                let body: serde_json::Value = serde_json::from_slice(&r.body).unwrap();
+                body["To"] == recipient
+            })
+            .unwrap()
+    }
+}
+
+fn issue() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Read https://example.com/post",
+            "html": r#"<p><a href="https://example.com/post">Post</a></p>"#,
+        }
+    })
+}
+
+/// The first link to `path` in the html body of `email`.
+fn find_link(email: &wiremock::Request, path: &str) -> String {
+    let body: serde_json::Value = serde_json::from_slice(&email.body).unwrap();
+    let html = body["HtmlBody"].as_str().unwrap();
+    let start = html.find(path).unwrap();
+    let start = html[..start].rfind('"').unwrap() + 1;
+    let end = start + html[start..].find('"').unwrap();
+    html[start..end].to_owned()
+}
+
+#[tokio::test]
+async fn stats_count_what_happened_to_each_recipient() {
+    // Arrange
+    let app = spawn_app().await;
+    for email in [
+        "ada%40example.com",
+        "bob%40example.com",
+        "cyd%40example.com",
+    ] {
+        create_confirmed_subscriber_with_email(&app, email).await;
+    }
+    app.post_newsletter(&issue())
+        .await
+        .error_for_status()
+        .unwrap();
+    let issue_id = app.issue_id().await;
+
+    // Act - Part 1 - Before delivery
+    let stats: serde_json::Value = app
+        .get_issue_stats(&issue_id, &[])
+        .await
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(stats["totals"]["targeted"], 3);
+    assert_eq!(stats["totals"]["pending"], 3);
+
+    // Act - Part 2 - Delivery
+    Mock::given(body_string_contains("cyd@example.com"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&app.email_server)
+        .await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    // Act - Part 3 - Ada opens, clicks and unsubscribes, Bob bounces
+    let email = app.email_to("ada@example.com").await;
+    // Without following the click's redirect to the link
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+    for path in ["/t/o/", "/t/c/"] {
+        client
+            .get(find_link(&email, path))
+            .send()
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap();
+    }
+    client
+        .post(app.get_unsubscribe_link(&email))
+        .body("List-Unsubscribe=One-Click")
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    app.post_bounce(&bounce(1, "HardBounce", "bob@example.com"))
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // Assert
+    let stats: serde_json::Value = app
+        .get_issue_stats(&issue_id, &[])
+        .await
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(stats["title"], "Newsletter title");
+    assert_eq!(
+        stats["totals"],
+        serde_json::json!({
+            "targeted": 3,
+            "pending": 0,
+            "delivered": 2,
+            "failed": 1,
+            "skipped": 0,
+            "bounced": 1,
+            "opened": 1,
+            "clicked": 1,
+            "unsubscribed": 1,
+        })
+    );
+    let recipients = stats["recipients"].as_array().unwrap();
+    let summary: Vec<_> = recipients
+        .iter()
+        .map(|r| {
+            (
+                r["email"].as_str().unwrap(),
+                r["status"].as_str().unwrap(),
+                r["opened"].as_bool().unwrap(),
+                r["clicked"].as_bool().unwrap(),
+                r["bounced"].as_bool().unwrap(),
+                r["unsubscribed"].as_bool().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        summary,
+        vec![
+            ("ada@example.com", "delivered", true, true, false, true),
+            ("bob@example.com", "delivered", false, false, true, false),
+            ("cyd@example.com", "failed", false, false, false, false),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn leaving_lists_in_the_preference_center_counts_as_unsubscribing() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let link = app.get_preferences_link().await;
+    let issue_id = app.deliver_issue().await;
+
+    // Act - Part 1 - Saving preferences without leaving
+    app.post_preferences(
+        &link,
+        "name=le%20guin&list=newsletter&frequency=every_issue",
+    )
+    .await
+    .error_for_status()
+    .unwrap();
+    assert_eq!(app.unsubscribed_total(&issue_id).await, 0);
+
+    // Act - Part 2 - Leaving every list
+    app.post_preferences(&link, "name=le%20guin&frequency=every_issue")
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // Assert
+    assert_eq!(app.unsubscribed_total(&issue_id).await, 1);
+}
+
+#[tokio::test]
+async fn a_spam_complaint_counts_as_unsubscribing() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let issue_id = app.deliver_issue().await;
+
+    // Act
+    app.post_complaint(&complaint(1, "ursula_le_guin@gmail.com"))
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // Assert
+    assert_eq!(app.unsubscribed_total(&issue_id).await, 1);
+}
+
+#[tokio::test]
+async fn recipients_are_paginated() {
+    // Arrange
+    let app = spawn_app().await;
+    for email in [
+        "ada%40example.com",
+        "bob%40example.com",
+        "cyd%40example.com",
+    ] {
+        create_confirmed_subscriber_with_email(&app, email).await;
+    }
+    app.post_newsletter(&issue())
+        .await
+        .error_for_status()
+        .unwrap();
+    let issue_id = app.issue_id().await;
+
+    for (page, expected) in [
+        (1, vec!["ada@example.com", "bob@example.com"]),
+        (2, vec!["cyd@example.com"]),
+    ] {
+        // Act
+        let stats: serde_json::Value = app
+            .get_issue_stats(&issue_id, &[("page", page), ("per_page", 2)])
+            .await
+            .error_for_status()
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        // Assert
+        let emails: Vec<_> = stats["recipients"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|r| r["email"].as_str().unwrap())
+            .collect();
+        assert_eq!(emails, expected);
+        assert_eq!(stats["totals"]["targeted"], 3);
+    }
+}
+
+#[tokio::test]
+async fn invalid_pagination_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    app.post_newsletter(&issue())
+        .await
+        .error_for_status()
+        .unwrap();
+    let issue_id = app.issue_id().await;
+    let test_cases = vec![
+        (vec![("page", 0)], "a page of zero"),
+        (vec![("per_page", 0)], "an empty page"),
+        (vec![("per_page", 101)], "a page that is too large"),
+        (
+            vec![("page", i64::MAX)],
+            "a page so far that its offset overflows",
+        ),
+    ];
+
+    for (query, description) in test_cases {
+        // Act
+        let response = app.get_issue_stats(&issue_id, &query).await;
+
+        // Assert
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The API did not reject {}.",
+            description
+        );
+    }
+}
+
+#[tokio::test]
+async fn stats_of_an_unknown_issue_return_a_404() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.get_issue_stats(&Uuid::new_v4().to_string(), &[]).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn stats_require_authentication() {
+    // Arrange
+    let app = spawn_app().await;
+    app.post_newsletter(&issue())
+        .await
+        .error_for_status()
+        .unwrap();
+    let issue_id = app.issue_id().await;
+
+    // Act
+    let response = reqwest::get(format!("{}/newsletters/{}/stats", &app.address, issue_id))
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+    assert_eq!(
+        r#"Basic realm="publish""#,
+        response.headers()["WWW-Authenticate"]
+    );
+}
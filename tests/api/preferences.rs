@@ -0,0 +1,407 @@
+use chrono::{Duration, Utc};
+use wiremock::matchers::any;
+use wiremock::{Mock, ResponseTemplate};
+
+use zero2prod::signed_token;
+
+use crate::helpers::{
+    create_confirmed_subscriber, create_unconfirmed_subscriber, spawn_app, spawn_app_with, TestApp,
+};
+
+impl TestApp {
+    /// The slugs of the lists the subscriber is a confirmed member of.
+    async fn confirmed_lists(&self) -> Vec<String> {
+        sqlx::query!(
+            r#"
+            SELECT l.slug
+            FROM list_memberships m
+            JOIN lists l ON l.list_id = m.list_id
+            WHERE m.status = 'confirmed'
+            ORDER BY l.slug
+            "#
+        )
+        .fetch_all(&self.db_pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| r.slug)
+        .collect()
+    }
+}
+
+fn newsletter_request_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    })
+}
+
+#[tokio::test]
+async fn a_confirmed_subscriber_is_emailed_a_link_to_the_preference_center() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    // Act
+    let link = app.get_preferences_link().await;
+
+    // Assert
+    assert_eq!(link.path(), "/preferences");
+    assert!(link
+        .query_pairs()
+        .any(|(key, value)| key == "token" && !value.is_empty()));
+}
+
+#[tokio::test]
+async fn unknown_and_unconfirmed_addresses_get_the_same_answer_but_no_email() {
+    // Arrange
+    let app = spawn_app().await;
+    create_unconfirmed_subscriber(&app).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    for body in [
+        "email=ursula_le_guin%40gmail.com",
+        "email=someone_else%40gmail.com",
+    ] {
+        // Act
+        let response = app.post_preferences_link(body).await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 200);
+    }
+    // Links are emailed in the background: give them time to be sent
+    tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+    // Mock verifies on Drop that we haven't sent any email
+}
+
+#[tokio::test]
+async fn a_failing_email_provider_does_not_change_the_answer() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let n_received = app.email_server.received_requests().await.unwrap().len();
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_preferences_link("email=ursula_le_guin%40gmail.com")
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    // The email was still attempted, after the response
+    app.wait_for_emails(n_received + 1).await;
+}
+
+#[tokio::test]
+async fn asking_for_too_many_links_is_rejected_with_a_429() {
+    // Arrange
+    let app = spawn_app_with(|c| c.rate_limit.per_email.capacity = 1).await;
+    app.post_preferences_link("email=ursula_le_guin%40gmail.com")
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // Act
+    let response = app
+        .post_preferences_link("email=ursula_le_guin%40gmail.com")
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 429);
+}
+
+#[tokio::test]
+async fn asking_for_a_link_with_an_invalid_email_is_rejected_with_a_400() {
+    // Arrange
+    let app = spawn_app().await;
+
+    for body in ["email=definitely-not-an-email", ""] {
+        // Act
+        let response = app.post_preferences_link(body).await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 400);
+    }
+}
+
+#[tokio::test]
+async fn the_link_opens_the_preference_center() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let link = app.get_preferences_link().await;
+
+    // Act
+    let response = reqwest::get(link).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let page = response.text().await.unwrap();
+    assert!(page.contains(r#"name="name" value="le guin""#));
+    assert!(page.contains(r#"value="newsletter" checked"#));
+    assert!(page.contains(r#"value="every_issue" checked"#));
+}
+
+#[tokio::test]
+async fn subscribers_can_update_their_name_lists_and_frequency() {
+    // Arrange
+    let app = spawn_app().await;
+    app.post_list("engineering-blog", "Engineering blog")
+        .await
+        .error_for_status()
+        .unwrap();
+    create_confirmed_subscriber(&app).await;
+    let link = app.get_preferences_link().await;
+
+    // Act
+    let response = app
+        .post_preferences(
+            &link,
+            "name=Ursula%20Le%20Guin&list=engineering-blog&frequency=weekly",
+        )
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("Your preferences have been saved."));
+    let saved = sqlx::query!("SELECT name, frequency FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.name, "Ursula Le Guin");
+    assert_eq!(saved.frequency, "weekly");
+    assert_eq!(app.confirmed_lists().await, vec!["engineering-blog"]);
+}
+
+#[tokio::test]
+async fn invalid_preferences_are_rejected_with_a_400() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let link = app.get_preferences_link().await;
+    let test_cases = vec![
+        ("name=&list=newsletter&frequency=weekly", "an empty name"),
+        ("name=%3Cscript%3E&list=newsletter&frequency=weekly", "an invalid name"),
+        ("name=Ursula&list=newsletter&frequency=monthly", "an unknown frequency"),
+        ("name=Ursula&list=newsletter", "a missing frequency"),
+        ("name=Ursula&list=unknown&frequency=weekly", "an unknown list"),
+    ];
+
+    for (body, description) in test_cases {
+        // Act
+        let response = app.post_preferences(&link, body).await;
+
+        // Assert
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The API did not fail with 400 Bad Request for {}.",
+            description
+        );
+    }
+    let saved = sqlx::query!("SELECT name, frequency FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.name, "le guin");
+    assert_eq!(saved.frequency, "every_issue");
+}
+
+#[tokio::test]
+async fn a_tampered_link_is_rejected_with_a_404() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let mut link = app.get_preferences_link().await;
+    let token = link.query_pairs().next().unwrap().1.into_owned();
+    link.set_query(Some(&format!("token={}x", token)));
+
+    // Act
+    let get_response = reqwest::get(link.clone()).await.unwrap();
+    let post_response = app
+        .post_preferences(&link, "name=Mallory&frequency=weekly")
+        .await;
+
+    // Assert
+    assert_eq!(get_response.status().as_u16(), 404);
+    assert_eq!(post_response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn an_expired_link_is_rejected_with_a_410() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let subscriber_id = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .id;
+    let token = signed_token::sign(
+        &app.hmac_secret,
+        "preferences",
+        &subscriber_id.to_string(),
+        Utc::now() - Duration::minutes(1),
+    );
+
+    // Act
+    let response = reqwest::get(format!("{}/preferences?token={}", app.address, token))
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 410);
+}
+
+#[tokio::test]
+async fn the_preference_center_lets_subscribers_unsubscribe() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let link = app.get_preferences_link().await;
+    let page = reqwest::get(link).await.unwrap().text().await.unwrap();
+    let unsubscribe_action = page
+        .split(r#"<form action=""#)
+        .nth(2)
+        .and_then(|form| form.split('"').next())
+        .unwrap();
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!("{}{}", app.address, unsubscribe_action))
+        .body("List-Unsubscribe=One-Click")
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "unsubscribed");
+}
+
+#[tokio::test]
+async fn weekly_subscribers_get_at_most_one_issue_a_week() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let link = app.get_preferences_link().await;
+    app.post_preferences(&link, "name=le%20guin&list=newsletter&frequency=weekly")
+        .await
+        .error_for_status()
+        .unwrap();
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    for _ in 0..2 {
+        app.post_newsletter(&newsletter_request_body())
+            .await
+            .error_for_status()
+            .unwrap();
+        app.dispatch_all_pending_emails().await;
+    }
+
+    // Assert
+    // Mock verifies on Drop that only the first issue was delivered
+}
+
+#[tokio::test]
+async fn only_the_newest_of_the_issues_held_back_by_the_frequency_is_delivered_later() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let link = app.get_preferences_link().await;
+    app.post_preferences(&link, "name=le%20guin&list=newsletter&frequency=weekly")
+        .await
+        .error_for_status()
+        .unwrap();
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let issue_subjects = || async {
+        let requests = app.email_server.received_requests().await.unwrap();
+        // Skip the confirmation and preference center emails
+        requests[2..]
+            .iter()
+            .map(|r| {
+                let body: serde_json::Value = serde_json::from_slice(&r.body).unwrap();
+                body["Subject"].as_str().unwrap().to_owned()
+            })
+            .collect::<Vec<_>>()
+    };
+    for title in ["First issue", "Second issue", "Third issue", "Fourth issue"] {
+        let mut body = newsletter_request_body();
+        body["title"] = title.into();
+        app.post_newsletter(&body).await.error_for_status().unwrap();
+        app.dispatch_all_pending_emails().await;
+    }
+    assert_eq!(issue_subjects().await, vec!["First issue"]);
+    let queued = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM issue_delivery_queue"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(queued.count, 1);
+
+    // Act - A week goes by
+    sqlx::query!("UPDATE subscriptions SET last_emailed_at = last_emailed_at - interval '7 days'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    sqlx::query!(
+        "UPDATE issue_delivery_queue SET execute_after = execute_after - interval '7 days'"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(issue_subjects().await, vec!["First issue", "Fourth issue"]);
+    let deliveries = sqlx::query!(
+        r#"
+        SELECT i.title, d.status
+        FROM issue_deliveries d
+        JOIN newsletter_issues i ON i.newsletter_issue_id = d.newsletter_issue_id
+        ORDER BY i.published_at
+        "#
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    let statuses: Vec<_> = deliveries
+        .iter()
+        .map(|d| (d.title.as_str(), d.status.as_str()))
+        .collect();
+    assert_eq!(
+        statuses,
+        vec![
+            ("First issue", "delivered"),
+            ("Second issue", "skipped"),
+            ("Third issue", "skipped"),
+            ("Fourth issue", "delivered"),
+        ]
+    );
+}
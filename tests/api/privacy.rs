@@ -0,0 +1,349 @@
+use chrono::{Duration, Utc};
+use wiremock::matchers::any;
+use wiremock::{Mock, ResponseTemplate};
+
+use zero2prod::signed_token;
+
+use crate::helpers::{create_confirmed_subscriber, spawn_app, spawn_app_with, TestApp};
+
+impl TestApp {
+    async fn post_privacy_link(&self, kind: &str, body: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/privacy/{}/link", &self.address, kind))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(body.to_owned())
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// Ask for an `export` or `erasure` link and return the one emailed out.
+    async fn get_privacy_link(&self, kind: &str) -> reqwest::Url {
+        let _mock_guard = Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount_as_scoped(&self.email_server)
+            .await;
+        let n_received = self.email_server.received_requests().await.unwrap().len();
+        self.post_privacy_link(kind, "email=ursula_le_guin%40gmail.com")
+            .await
+            .error_for_status()
+            .unwrap();
+        let email_request = self.wait_for_emails(n_received + 1).await.pop().unwrap();
+        self.get_confirmation_links(&email_request).html
+    }
+
+    async fn admin_subscriber_data(
+        &self,
+        method: reqwest::Method,
+        email: &str,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .request(method, format!("{}/subscribers/data", &self.address))
+            .query(&[("email", email)])
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    async fn count_rows(&self, table: &str) -> i64 {
+        sqlx::query_scalar(&format!("SELECT COUNT(*) FROM {}", table))
+            .fetch_one(&self.db_pool)
+            .await
+            .unwrap()
+    }
+}
+
+/// Publish an issue to the confirmed subscriber, without delivering it.
+async fn enqueue_issue(app: &TestApp) {
+    app.post_newsletter(&serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+}
+
+#[tokio::test]
+async fn subscribers_can_download_everything_we_hold_about_them() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    enqueue_issue(&app).await;
+    let link = app.get_privacy_link("export").await;
+
+    // Act
+    let response = reqwest::get(link).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let data: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(data["subscription"]["email"], "ursula_le_guin@gmail.com");
+    assert_eq!(data["subscription"]["status"], "confirmed");
+    assert_eq!(data["list_memberships"][0]["list"], "newsletter");
+    assert_eq!(data["subscription_tokens"].as_array().unwrap().len(), 1);
+    assert_eq!(data["pending_deliveries"][0]["title"], "Newsletter title");
+}
+
+#[tokio::test]
+async fn unknown_addresses_get_the_same_answer_but_no_email() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    for kind in ["export", "erasure"] {
+        // Act
+        let response = app
+            .post_privacy_link(kind, "email=ursula_le_guin%40gmail.com")
+            .await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 200);
+    }
+    // Links are emailed in the background: give them time to be sent
+    tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+    // Mock verifies on Drop that we haven't sent any email
+}
+
+#[tokio::test]
+async fn a_failing_email_provider_does_not_change_the_answer() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let n_received = app.email_server.received_requests().await.unwrap().len();
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&app.email_server)
+        .await;
+
+    for (i, kind) in ["export", "erasure"].into_iter().enumerate() {
+        // Act
+        let response = app
+            .post_privacy_link(kind, "email=ursula_le_guin%40gmail.com")
+            .await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 200);
+        // The email was still attempted, after the response
+        app.wait_for_emails(n_received + i + 1).await;
+    }
+}
+
+#[tokio::test]
+async fn asking_for_too_many_links_is_rejected_with_a_429() {
+    // Arrange
+    let app = spawn_app_with(|c| c.rate_limit.per_email.capacity = 1).await;
+    app.post_privacy_link("export", "email=ursula_le_guin%40gmail.com")
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // Act
+    let response = app
+        .post_privacy_link("export", "email=ursula_le_guin%40gmail.com")
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 429);
+}
+
+#[tokio::test]
+async fn asking_for_a_link_with_an_invalid_email_is_rejected_with_a_400() {
+    // Arrange
+    let app = spawn_app().await;
+
+    for kind in ["export", "erasure"] {
+        // Act
+        let response = app
+            .post_privacy_link(kind, "email=definitely-not-an-email")
+            .await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 400);
+    }
+}
+
+#[tokio::test]
+async fn following_the_erasure_link_does_not_erase_anything() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let link = app.get_privacy_link("erasure").await;
+
+    // Act
+    let response = reqwest::get(link).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.text().await.unwrap().contains(r#"method="post""#));
+    assert_eq!(app.count_rows("subscriptions").await, 1);
+}
+
+#[tokio::test]
+async fn confirming_an_erasure_leaves_only_a_hashed_tombstone() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    enqueue_issue(&app).await;
+    let link = app.get_privacy_link("erasure").await;
+
+    // Act
+    let response = reqwest::Client::new().post(link).send().await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    for table in [
+        "subscriptions",
+        "subscription_tokens",
+        "list_memberships",
+        "issue_delivery_queue",
+    ] {
+        assert_eq!(app.count_rows(table).await, 0, "{} is not empty.", table);
+    }
+    let tombstone = sqlx::query!("SELECT email_hash, reason, source FROM suppressions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(!tombstone.email_hash.contains("ursula"));
+    assert_eq!(tombstone.reason, "erasure");
+    assert_eq!(tombstone.source, "subscriber");
+}
+
+#[tokio::test]
+async fn an_erased_address_is_not_subscribed_again() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let link = app.get_privacy_link("erasure").await;
+    reqwest::Client::new()
+        .post(link)
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_subscriptions("name=le%20guin&email=Ursula_Le_Guin%40gmail.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(app.count_rows("subscriptions").await, 0);
+}
+
+#[tokio::test]
+async fn an_export_link_cannot_be_used_to_erase() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let mut link = app.get_privacy_link("export").await;
+    link.set_path("/privacy/erasure");
+
+    // Act
+    let response = reqwest::Client::new().post(link).send().await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+    assert_eq!(app.count_rows("subscriptions").await, 1);
+}
+
+#[tokio::test]
+async fn an_expired_link_is_rejected_with_a_410() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let subscriber_id = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .id;
+    let token = signed_token::sign(
+        &app.hmac_secret,
+        "export",
+        &subscriber_id.to_string(),
+        Utc::now() - Duration::minutes(1),
+    );
+
+    // Act
+    let response = reqwest::get(format!("{}/privacy/export?token={}", app.address, token))
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 410);
+}
+
+#[tokio::test]
+async fn admins_can_export_and_erase_an_address() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    // Act - Part 1 - Export
+    let response = app
+        .admin_subscriber_data(reqwest::Method::GET, "ursula_le_guin@gmail.com")
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let data: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(data["subscription"]["email"], "ursula_le_guin@gmail.com");
+
+    // Act - Part 2 - Erase
+    let response = app
+        .admin_subscriber_data(reqwest::Method::DELETE, "ursula_le_guin@gmail.com")
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(app.count_rows("subscriptions").await, 0);
+    let source = sqlx::query!("SELECT source FROM suppressions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .source;
+    assert_eq!(source, "admin");
+
+    // Act - Part 3 - Nothing is left to export
+    let response = app
+        .admin_subscriber_data(reqwest::Method::GET, "ursula_le_guin@gmail.com")
+        .await;
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn admin_endpoints_require_authentication() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    for method in [reqwest::Method::GET, reqwest::Method::DELETE] {
+        // Act
+        let response = reqwest::Client::new()
+            .request(method, format!("{}/subscribers/data", &app.address))
+            .query(&[("email", "ursula_le_guin@gmail.com")])
+            .send()
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 401);
+        assert_eq!(
+            r#"Basic realm="publish""#,
+            response.headers()["WWW-Authenticate"]
+        );
+    }
+    assert_eq!(app.count_rows("subscriptions").await, 1);
+}
@@ -1,7 +1,42 @@
-use wiremock::matchers::{method, path};
-use wiremock::{Mock, ResponseTemplate};
+use secrecy::Secret;
+use wiremock::matchers::{body_string_contains, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
 
-use crate::helpers::spawn_app;
+use zero2prod::configuration::{ChallengeSettings, RateLimitBackend};
+
+use crate::helpers::{spawn_app, spawn_app_with, TestApp};
+
+impl TestApp {
+    async fn post_subscriptions_from(&self, ip: &str, body: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/subscriptions", &self.address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .header("X-Forwarded-For", ip)
+            .body(body.to_owned())
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    async fn get_form_token(&self) -> String {
+        let body: serde_json::Value =
+            reqwest::get(format!("{}/subscriptions/form_token", &self.address))
+                .await
+                .unwrap()
+                .json()
+                .await
+                .unwrap();
+        body["form_token"].as_str().unwrap().to_owned()
+    }
+}
+
+fn challenge_settings(challenge_server: &MockServer) -> ChallengeSettings {
+    ChallengeSettings {
+        verify_url: format!("{}/siteverify", challenge_server.uri()),
+        secret: Secret::new("challenge-secret".into()),
+        timeout_milliseconds: 1000,
+    }
+}
 
 #[tokio::test]
 async fn subscribe_sends_a_confirmation_email_with_a_link() {
@@ -19,7 +54,7 @@ async fn subscribe_sends_a_confirmation_email_with_a_link() {
 
     // Assert
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
-    let links = app.get_confirmation_links(&email_request);
+    let links = app.get_confirmation_links(email_request);
 
     // The two links should be identical
     assert_eq!(links.html, links.plain_text);
@@ -90,6 +125,34 @@ async fn subscribe_persists_the_new_subscriber() {
     assert_eq!(saved.status, "pending_confirmation");
 }
 
+#[tokio::test]
+async fn subscribe_stores_a_confirmation_token_expiring_after_the_configured_ttl() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_subscriptions(body.into()).await;
+
+    // Assert
+    let saved = sqlx::query!(
+        "SELECT created_at, expires_at, consumed_at FROM subscription_tokens",
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .expect("Failed to fetch saved subscription token.");
+    assert_eq!(
+        saved.expires_at - saved.created_at,
+        chrono::Duration::hours(24)
+    );
+    assert!(saved.consumed_at.is_none());
+}
+
 #[tokio::test]
 async fn subscribe_returns_a_400_when_data_is_missing() {
     // Arrange
@@ -153,3 +216,574 @@ async fn subscribe_fails_if_there_is_a_fatal_database_error() {
     // Assert
     assert_eq!(response.status().as_u16(), 500);
 }
+
+#[tokio::test]
+async fn subscribing_again_while_pending_resends_a_fresh_confirmation_link() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions(body.into()).await;
+
+    // Act
+    let response = app.post_subscriptions(body.into()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let email_requests = app.email_server.received_requests().await.unwrap();
+    let first_link = app.get_confirmation_links(&email_requests[0]).html;
+    let second_link = app.get_confirmation_links(&email_requests[1]).html;
+    assert_ne!(first_link, second_link);
+
+    // Only the latest link can be used
+    let response = reqwest::get(first_link).await.unwrap();
+    assert_eq!(response.status().as_u16(), 410);
+    let response = reqwest::get(second_link).await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn concurrent_signups_for_a_new_address_both_succeed() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let (first, second) = tokio::join!(
+        app.post_subscriptions(body.into()),
+        app.post_subscriptions(body.into())
+    );
+
+    // Assert
+    assert_eq!(first.status().as_u16(), 200);
+    assert_eq!(second.status().as_u16(), 200);
+    let saved = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.count, 1);
+}
+
+#[tokio::test]
+async fn subscribing_with_a_confirmed_email_succeeds_without_a_confirmation_link() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions(body.into()).await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_link = app.get_confirmation_links(email_request).html;
+    reqwest::get(confirmation_link)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Act
+    let response = app.post_subscriptions(body.into()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let email_requests = app.email_server.received_requests().await.unwrap();
+    assert_eq!(email_requests.len(), 2);
+    let note: serde_json::Value = serde_json::from_slice(&email_requests[1].body).unwrap();
+    assert!(!note["TextBody"]
+        .as_str()
+        .unwrap()
+        .contains("/subscriptions/confirm"));
+
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn the_confirmation_email_is_rendered_from_the_templates() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_subscriptions(body.into()).await;
+
+    // Assert
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let email: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(email["Subject"], "Welcome!");
+    assert!(email["HtmlBody"].as_str().unwrap().contains("le guin"));
+    assert!(email["TextBody"].as_str().unwrap().contains("le guin"));
+}
+
+#[tokio::test]
+async fn subscribe_rejects_too_many_requests_from_one_ip_with_a_429() {
+    // Arrange
+    let app = spawn_app_with(|c| c.rate_limit.per_ip.capacity = 2).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    for email in ["ursula_le_guin", "octavia_butler"] {
+        let body = format!("name=le%20guin&email={}%40gmail.com", email);
+        app.post_subscriptions(body)
+            .await
+            .error_for_status()
+            .unwrap();
+    }
+
+    // Act
+    let response = app
+        .post_subscriptions("name=le%20guin&email=n_k_jemisin%40gmail.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 429);
+    let retry_after: u64 = response.headers()["Retry-After"]
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!(retry_after > 0);
+    // Mock verifies on Drop that no email was sent to the last address
+}
+
+#[tokio::test]
+async fn subscribe_rejects_too_many_requests_for_one_email_with_a_429() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.rate_limit.trust_forwarded_for = true;
+        c.rate_limit.per_email.capacity = 1;
+    })
+    .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions_from(
+        "203.0.113.1",
+        "name=le%20guin&email=ursula_le_guin%40gmail.com",
+    )
+    .await
+    .error_for_status()
+    .unwrap();
+
+    // Act
+    let response = app
+        .post_subscriptions_from(
+            "203.0.113.2",
+            "name=le%20guin&email=Ursula_Le_Guin%40gmail.com",
+        )
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 429);
+    assert!(response.headers().contains_key("Retry-After"));
+}
+
+#[tokio::test]
+async fn each_email_has_its_own_bucket() {
+    // Arrange
+    let app = spawn_app_with(|c| c.rate_limit.per_email.capacity = 1).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    for email in ["ursula_le_guin", "octavia_butler"] {
+        // Act
+        let body = format!("name=le%20guin&email={}%40gmail.com", email);
+        let response = app.post_subscriptions(body).await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 200);
+    }
+}
+
+#[tokio::test]
+async fn a_request_refused_for_its_email_does_not_count_against_the_ip() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.rate_limit.per_ip.capacity = 2;
+        c.rate_limit.per_email.capacity = 1;
+    })
+    .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    app.post_subscriptions(body.into())
+        .await
+        .error_for_status()
+        .unwrap();
+    let response = app.post_subscriptions(body.into()).await;
+    assert_eq!(response.status().as_u16(), 429);
+
+    // Act
+    let response = app
+        .post_subscriptions("name=le%20guin&email=octavia_butler%40gmail.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn each_route_has_its_own_buckets() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.rate_limit.per_ip.capacity = 1;
+        c.rate_limit.per_email.capacity = 1;
+    })
+    .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!("{}/privacy/export/link", &app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body("email=ursula_le_guin%40gmail.com")
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn x_forwarded_for_is_ignored_unless_trusted() {
+    // Arrange
+    let app = spawn_app_with(|c| c.rate_limit.per_ip.capacity = 1).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions_from(
+        "203.0.113.1",
+        "name=le%20guin&email=ursula_le_guin%40gmail.com",
+    )
+    .await
+    .error_for_status()
+    .unwrap();
+
+    // Act
+    let response = app
+        .post_subscriptions_from(
+            "203.0.113.2",
+            "name=le%20guin&email=octavia_butler%40gmail.com",
+        )
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 429);
+}
+
+#[tokio::test]
+async fn the_postgres_backend_keeps_buckets_in_the_database() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.rate_limit.backend = RateLimitBackend::Postgres;
+        c.rate_limit.per_ip.capacity = 1;
+    })
+    .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // Act
+    let response = app
+        .post_subscriptions("name=le%20guin&email=octavia_butler%40gmail.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 429);
+    let buckets = sqlx::query!("SELECT key FROM rate_limit_buckets ORDER BY key")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    let keys: Vec<_> = buckets.into_iter().map(|b| b.key).collect();
+    assert_eq!(
+        keys,
+        vec![
+            "/subscriptions email:ursula_le_guin@gmail.com",
+            "/subscriptions ip:127.0.0.1"
+        ]
+    );
+}
+
+#[tokio::test]
+async fn the_postgres_backend_forgets_full_buckets_and_invalid_emails() {
+    // Arrange
+    let app = spawn_app_with(|c| c.rate_limit.backend = RateLimitBackend::Postgres).await;
+    sqlx::query!(
+        r#"
+        INSERT INTO rate_limit_buckets (key, tokens, updated_at, full_at)
+        VALUES ('/subscriptions email:ursula_le_guin@gmail.com', 0, now() - interval '1 day', now() - interval '1 hour')
+        "#
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    let response = app
+        .post_subscriptions("name=le%20guin&email=definitely-not-an-email".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    let buckets = sqlx::query!("SELECT key FROM rate_limit_buckets ORDER BY key")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    let keys: Vec<_> = buckets.into_iter().map(|b| b.key).collect();
+    assert_eq!(keys, vec!["/subscriptions ip:127.0.0.1"]);
+}
+
+#[tokio::test]
+async fn subscribe_rejects_a_filled_in_honeypot_with_a_400() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_subscriptions(
+            "name=le%20guin&email=ursula_le_guin%40gmail.com&website=http%3A%2F%2Fspam.com".into(),
+        )
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    let saved = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_optional(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(saved.is_none());
+}
+
+#[tokio::test]
+async fn subscribe_rejects_forms_sent_back_too_quickly_or_without_a_token() {
+    // Arrange
+    let app = spawn_app_with(|c| c.bot_protection.min_submit_seconds = 60).await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    let form_token = app.get_form_token().await;
+    let test_cases = vec![
+        (
+            format!(
+                "name=le%20guin&email=ursula_le_guin%40gmail.com&form_token={}",
+                form_token
+            ),
+            "a form sent back right away",
+        ),
+        (
+            "name=le%20guin&email=ursula_le_guin%40gmail.com".to_string(),
+            "a missing form token",
+        ),
+        (
+            "name=le%20guin&email=ursula_le_guin%40gmail.com&form_token=forged".to_string(),
+            "a forged form token",
+        ),
+    ];
+
+    for (body, description) in test_cases {
+        // Act
+        let response = app.post_subscriptions(body).await;
+
+        // Assert
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The API did not fail with 400 Bad Request for {}.",
+            description
+        );
+    }
+}
+
+#[tokio::test]
+async fn subscribe_accepts_a_form_sent_back_after_the_minimum_time() {
+    // Arrange
+    let app = spawn_app_with(|c| c.bot_protection.min_submit_seconds = 1).await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let form_token = app.get_form_token().await;
+    tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+
+    // Act
+    let response = app
+        .post_subscriptions(format!(
+            "name=le%20guin&email=ursula_le_guin%40gmail.com&form_token={}",
+            form_token
+        ))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn subscribe_verifies_the_challenge_response_with_the_provider() {
+    // Arrange
+    let challenge_server = MockServer::start().await;
+    let app = spawn_app_with(|c| {
+        c.bot_protection.challenge = Some(challenge_settings(&challenge_server))
+    })
+    .await;
+    Mock::given(path("/siteverify"))
+        .and(method("POST"))
+        .and(body_string_contains("secret=challenge-secret"))
+        .and(body_string_contains("response=solved"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "success": true
+        })))
+        .expect(1)
+        .mount(&challenge_server)
+        .await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_subscriptions(
+            "name=le%20guin&email=ursula_le_guin%40gmail.com&h-captcha-response=solved".into(),
+        )
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn subscribe_rejects_a_failed_or_missing_challenge_with_a_400() {
+    // Arrange
+    let challenge_server = MockServer::start().await;
+    let app = spawn_app_with(|c| {
+        c.bot_protection.challenge = Some(challenge_settings(&challenge_server))
+    })
+    .await;
+    Mock::given(path("/siteverify"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "success": false
+        })))
+        .expect(1)
+        .mount(&challenge_server)
+        .await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    for body in [
+        "name=le%20guin&email=ursula_le_guin%40gmail.com&cf-turnstile-response=wrong",
+        "name=le%20guin&email=ursula_le_guin%40gmail.com",
+    ] {
+        // Act
+        let response = app.post_subscriptions(body.into()).await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 400);
+    }
+}
+
+#[tokio::test]
+async fn rejections_do_not_say_which_check_failed() {
+    // Arrange
+    let challenge_server = MockServer::start().await;
+    let app = spawn_app_with(|c| {
+        c.bot_protection.min_submit_seconds = 60;
+        c.bot_protection.challenge = Some(challenge_settings(&challenge_server));
+    })
+    .await;
+    Mock::given(path("/siteverify"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "success": false
+        })))
+        .mount(&challenge_server)
+        .await;
+    // Handed out an hour ago, so that only the challenge fails
+    let old_enough_token = zero2prod::signed_token::sign(
+        &app.hmac_secret,
+        "signup_form",
+        &(chrono::Utc::now() - chrono::Duration::hours(1))
+            .timestamp_millis()
+            .to_string(),
+        chrono::Utc::now() + chrono::Duration::hours(1),
+    );
+    let bodies = [
+        "name=le%20guin&email=ursula_le_guin%40gmail.com&website=spam".to_string(),
+        "name=le%20guin&email=ursula_le_guin%40gmail.com".to_string(),
+        format!(
+            "name=le%20guin&email=ursula_le_guin%40gmail.com&form_token={}&cf-turnstile-response=wrong",
+            old_enough_token
+        ),
+    ];
+
+    // Act
+    let mut answers = Vec::new();
+    for body in bodies {
+        let response = app.post_subscriptions(body).await;
+        answers.push((response.status().as_u16(), response.text().await.unwrap()));
+    }
+
+    // Assert
+    assert_eq!(answers[0].0, 400);
+    assert!(answers.iter().all(|answer| answer == &answers[0]));
+}
@@ -0,0 +1,191 @@
+use wiremock::matchers::any;
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{create_confirmed_subscriber, spawn_app, TestApp};
+
+fn newsletter_request_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    })
+}
+
+/// Publish an issue to the single confirmed subscriber and return
+/// the unsubscribe link it was delivered with.
+async fn deliver_issue_and_get_unsubscribe_link(app: &TestApp) -> reqwest::Url {
+    let _mock_guard = Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_newsletter(&newsletter_request_body())
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    app.get_unsubscribe_link(&email_request)
+}
+
+async fn subscriber_status(app: &TestApp) -> String {
+    sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.")
+        .status
+}
+
+async fn membership_status(app: &TestApp) -> String {
+    sqlx::query!("SELECT status FROM list_memberships")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved list membership.")
+        .status
+}
+
+#[tokio::test]
+async fn newsletters_carry_a_one_click_unsubscribe_link() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    // Act
+    let unsubscribe_link = deliver_issue_and_get_unsubscribe_link(&app).await;
+
+    // Assert
+    assert_eq!(unsubscribe_link.path(), "/subscriptions/unsubscribe");
+    assert!(unsubscribe_link
+        .query_pairs()
+        .any(|(key, value)| key == "token" && !value.is_empty()));
+}
+
+#[tokio::test]
+async fn a_one_click_post_unsubscribes_the_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let unsubscribe_link = deliver_issue_and_get_unsubscribe_link(&app).await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(unsubscribe_link)
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body("List-Unsubscribe=One-Click")
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(membership_status(&app).await, "unsubscribed");
+}
+
+#[tokio::test]
+async fn unsubscribing_twice_is_not_an_error() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let unsubscribe_link = deliver_issue_and_get_unsubscribe_link(&app).await;
+
+    for _ in 0..2 {
+        // Act
+        let response = reqwest::Client::new()
+            .post(unsubscribe_link.clone())
+            .body("List-Unsubscribe=One-Click")
+            .send()
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 200);
+    }
+}
+
+#[tokio::test]
+async fn unsubscribed_subscribers_do_not_receive_later_issues() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let unsubscribe_link = deliver_issue_and_get_unsubscribe_link(&app).await;
+    reqwest::Client::new()
+        .post(unsubscribe_link)
+        .body("List-Unsubscribe=One-Click")
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_newsletter(&newsletter_request_body()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+    // Mock verifies on Drop that we haven't sent the newsletter email
+}
+
+#[tokio::test]
+async fn the_unsubscribe_page_does_not_unsubscribe() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let unsubscribe_link = deliver_issue_and_get_unsubscribe_link(&app).await;
+
+    // Act
+    let response = reqwest::get(unsubscribe_link).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let page = response.text().await.unwrap();
+    assert!(page.contains(r#"method="post""#));
+    assert!(page.contains(r#"name="List-Unsubscribe" value="One-Click""#));
+    assert_eq!(subscriber_status(&app).await, "confirmed");
+}
+
+#[tokio::test]
+async fn an_unknown_token_is_rejected_with_a_404() {
+    // Arrange
+    let app = spawn_app().await;
+    let client = reqwest::Client::new();
+    let url = format!("{}/subscriptions/unsubscribe?token=unknown", app.address);
+
+    // Act
+    let get_response = client.get(&url).send().await.unwrap();
+    let post_response = client.post(&url).send().await.unwrap();
+
+    // Assert
+    assert_eq!(get_response.status().as_u16(), 404);
+    assert_eq!(post_response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn requests_without_a_token_are_rejected_with_a_400() {
+    // Arrange
+    let app = spawn_app().await;
+    let client = reqwest::Client::new();
+    let url = format!("{}/subscriptions/unsubscribe", app.address);
+
+    // Act
+    let get_response = client.get(&url).send().await.unwrap();
+    let post_response = client.post(&url).send().await.unwrap();
+
+    // Assert
+    assert_eq!(get_response.status().as_u16(), 400);
+    assert_eq!(post_response.status().as_u16(), 400);
+}
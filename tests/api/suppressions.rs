@@ -0,0 +1,292 @@
+use wiremock::matchers::any;
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{create_confirmed_subscriber, spawn_app, TestApp};
+
+impl TestApp {
+    async fn get_suppressions(&self, email: Option<&str>) -> reqwest::Response {
+        let mut request = reqwest::Client::new()
+            .get(format!("{}/suppressions", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password));
+        if let Some(email) = email {
+            request = request.query(&[("email", email)]);
+        }
+        request.send().await.expect("Failed to execute request.")
+    }
+
+    async fn import_suppressions(&self, body: &serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/suppressions", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    async fn lift_suppression(&self, email: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .delete(format!("{}/suppressions", &self.address))
+            .query(&[("email", email)])
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+}
+
+#[tokio::test]
+async fn imported_suppressions_are_listed_without_the_addresses() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act - Part 1 - Import
+    let response = app
+        .import_suppressions(&serde_json::json!({
+            "emails": ["ursula_le_guin@gmail.com", "octavia_butler@gmail.com"]
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let outcome: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(outcome["suppressed"], 2);
+    assert_eq!(outcome["already_suppressed"], 0);
+
+    // Act - Part 2 - List
+    let suppressions: serde_json::Value = app
+        .get_suppressions(None)
+        .await
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let suppressions = suppressions.as_array().unwrap();
+    assert_eq!(suppressions.len(), 2);
+    for suppression in suppressions {
+        assert_eq!(suppression["reason"], "manual");
+        assert_eq!(suppression["source"], "admin");
+        assert!(!suppression.to_string().contains("gmail.com"));
+    }
+
+    // Act - Part 3 - Look up a single address
+    let suppressions: serde_json::Value = app
+        .get_suppressions(Some("Ursula_Le_Guin@gmail.com"))
+        .await
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(suppressions.as_array().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn importing_an_address_twice_suppresses_it_once() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = serde_json::json!({
+        "emails": ["ursula_le_guin@gmail.com"],
+        "reason": "complaint"
+    });
+    app.import_suppressions(&body)
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // Act
+    let outcome: serde_json::Value = app
+        .import_suppressions(&body)
+        .await
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(outcome["suppressed"], 0);
+    assert_eq!(outcome["already_suppressed"], 1);
+}
+
+#[tokio::test]
+async fn invalid_imports_are_rejected_as_a_whole() {
+    // Arrange
+    let app = spawn_app().await;
+    let test_cases = vec![
+        (
+            serde_json::json!({"emails": ["ursula_le_guin@gmail.com", "not-an-email"]}),
+            "an invalid address",
+        ),
+        (
+            serde_json::json!({"emails": ["ursula_le_guin@gmail.com"], "reason": "boredom"}),
+            "an unknown reason",
+        ),
+    ];
+
+    for (body, description) in test_cases {
+        // Act
+        let response = app.import_suppressions(&body).await;
+
+        // Assert
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The API did not reject an import with {}.",
+            description
+        );
+    }
+    let suppressions: serde_json::Value = app.get_suppressions(None).await.json().await.unwrap();
+    assert!(suppressions.as_array().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn suppressed_subscribers_do_not_receive_newsletters() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.import_suppressions(&serde_json::json!({"emails": ["ursula_le_guin@gmail.com"]}))
+        .await
+        .error_for_status()
+        .unwrap();
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_newsletter(&serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    // Mock verifies on Drop that we haven't sent the newsletter email
+}
+
+#[tokio::test]
+async fn suppressed_addresses_get_no_email_but_the_usual_answer() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.import_suppressions(&serde_json::json!({"emails": ["ursula_le_guin@gmail.com"]}))
+        .await
+        .error_for_status()
+        .unwrap();
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    let test_cases = vec![
+        ("/subscriptions", "subscribing"),
+        ("/preferences/link", "asking for a preference center link"),
+        ("/privacy/export/link", "asking for a data export link"),
+    ];
+
+    for (path, description) in test_cases {
+        // Act
+        let response = reqwest::Client::new()
+            .post(format!("{}{}", &app.address, path))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body("name=le%20guin&email=ursula_le_guin%40gmail.com")
+            .send()
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(
+            response.status().as_u16(),
+            200,
+            "The API did not answer as usual when {}.",
+            description
+        );
+    }
+    // Links are emailed in the background: give them time to be sent
+    tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+    // Mock verifies on Drop that we haven't sent any email
+}
+
+#[tokio::test]
+async fn a_lifted_address_can_subscribe_again() {
+    // Arrange
+    let app = spawn_app().await;
+    app.import_suppressions(&serde_json::json!({"emails": ["ursula_le_guin@gmail.com"]}))
+        .await
+        .error_for_status()
+        .unwrap();
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.lift_suppression("ursula_le_guin@gmail.com").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+    // Mock verifies on Drop that we have sent the confirmation email
+}
+
+#[tokio::test]
+async fn lifting_an_address_that_is_not_suppressed_returns_a_404() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.lift_suppression("ursula_le_guin@gmail.com").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn managing_suppressions_requires_authentication() {
+    // Arrange
+    let app = spawn_app().await;
+    let url = format!("{}/suppressions", &app.address);
+    let test_cases = vec![
+        (reqwest::Client::new().get(&url), "listing"),
+        (
+            reqwest::Client::new()
+                .post(&url)
+                .json(&serde_json::json!({"emails": ["ursula_le_guin@gmail.com"]})),
+            "importing",
+        ),
+        (
+            reqwest::Client::new()
+                .delete(&url)
+                .query(&[("email", "ursula_le_guin@gmail.com")]),
+            "lifting",
+        ),
+    ];
+
+    for (request, description) in test_cases {
+        // Act
+        let response = request.send().await.unwrap();
+
+        // Assert
+        assert_eq!(
+            response.status().as_u16(),
+            401,
+            "The API did not require authentication for {}.",
+            description
+        );
+        assert_eq!(
+            r#"Basic realm="publish""#,
+            response.headers()["WWW-Authenticate"]
+        );
+    }
+}
@@ -0,0 +1,256 @@
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use zero2prod::tracking::Delivery;
+
+use crate::helpers::{create_confirmed_subscriber, spawn_app, TestApp};
+
+/// The html and plain text bodies of an email.
+struct SentEmail {
+    html: String,
+    text: String,
+}
+
+impl TestApp {
+    /// Publish an issue to the confirmed subscriber, deliver it and return
+    /// the email that was sent.
+    async fn send_issue(&self, body: &serde_json::Value) -> SentEmail {
+        let _mock_guard = Mock::given(path("/email"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount_as_scoped(&self.email_server)
+            .await;
+        self.post_newsletter(body).await.error_for_status().unwrap();
+        self.dispatch_all_pending_emails().await;
+        let email_request = self
+            .email_server
+            .received_requests()
+            .await
+            .unwrap()
+            .pop()
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+        SentEmail {
+            html: body["HtmlBody"].as_str().unwrap().to_owned(),
+            text: body["TextBody"].as_str().unwrap().to_owned(),
+        }
+    }
+
+    /// The first tracking URL under `path` in `content`, if any.
+    fn find_tracking_url(&self, content: &str, path: &str) -> Option<String> {
+        let start = content.find(&format!("{}{}", &self.address, path))?;
+        let end = content[start..]
+            .find(|c: char| c == '"' || c.is_whitespace())
+            .map_or(content.len(), |i| start + i);
+        // Like email clients, leave out the punctuation ending a sentence
+        Some(content[start..end].trim_end_matches('.').to_owned())
+    }
+
+    async fn get_opens(&self) -> Vec<(i32, chrono::DateTime<chrono::Utc>)> {
+        sqlx::query!("SELECT open_count, first_opened_at FROM email_opens")
+            .fetch_all(&self.db_pool)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|r| (r.open_count, r.first_opened_at))
+            .collect()
+    }
+
+    async fn get_clicks(&self) -> Vec<(String, i32)> {
+        sqlx::query!("SELECT url, click_count FROM email_clicks")
+            .fetch_all(&self.db_pool)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|r| (r.url, r.click_count))
+            .collect()
+    }
+}
+
+fn issue(html: &str, text: &str) -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": text,
+            "html": html,
+        }
+    })
+}
+
+/// Requests the click tracking endpoint without following its redirects.
+async fn click(url: &str) -> reqwest::Response {
+    reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap()
+        .get(url)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+#[tokio::test]
+async fn opening_an_issue_is_recorded_once_per_recipient() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let email = app
+        .send_issue(&issue("<p>Newsletter body as HTML</p>", "Newsletter body"))
+        .await;
+    let pixel_url = app
+        .find_tracking_url(&email.html, "/t/o/")
+        .expect("No tracking pixel in the issue.");
+
+    // Act - Part 1 - First open
+    let response = reqwest::get(&pixel_url).await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.headers()["Content-Type"], "image/gif");
+    assert_eq!(response.headers()["Cache-Control"], "no-store");
+    assert!(response.bytes().await.unwrap().starts_with(b"GIF89a"));
+    let opens = app.get_opens().await;
+    assert_eq!(opens.len(), 1);
+    let (_, first_opened_at) = opens[0];
+
+    // Act - Part 2 - Opened again
+    reqwest::get(&pixel_url)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    assert_eq!(app.get_opens().await, vec![(2, first_opened_at)]);
+}
+
+#[tokio::test]
+async fn issues_published_without_tracking_are_sent_as_they_are() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let mut body = issue(
+        r#"<p><a href="https://example.com/post">Post</a></p>"#,
+        "Read https://example.com/post",
+    );
+    body["track_opens"] = false.into();
+    body["track_clicks"] = false.into();
+
+    // Act
+    let email = app.send_issue(&body).await;
+
+    // Assert
+    assert!(email
+        .html
+        .contains(r#"<a href="https://example.com/post">"#));
+    assert!(email.text.contains("Read https://example.com/post"));
+    assert_eq!(app.find_tracking_url(&email.html, "/t/"), None);
+    assert_eq!(app.find_tracking_url(&email.text, "/t/"), None);
+}
+
+#[tokio::test]
+async fn invalid_pixel_urls_serve_the_pixel_without_recording_anything() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let email = app
+        .send_issue(&issue("<p>Newsletter body as HTML</p>", "Newsletter body"))
+        .await;
+    let pixel_url = app.find_tracking_url(&email.html, "/t/o/").unwrap();
+    let test_cases = vec![
+        (pixel_url.replace("/t/o/", "/t/o/x"), "a tampered token"),
+        (format!("{}/t/o/garbage.gif", &app.address), "garbage"),
+    ];
+
+    for (url, description) in test_cases {
+        // Act
+        let response = reqwest::get(&url).await.unwrap();
+
+        // Assert
+        assert_eq!(
+            response.status().as_u16(),
+            200,
+            "The API did not serve the pixel for {}.",
+            description
+        );
+        assert_eq!(response.headers()["Content-Type"], "image/gif");
+    }
+    assert!(app.get_opens().await.is_empty());
+}
+
+#[tokio::test]
+async fn clicking_a_link_redirects_to_it_and_is_recorded() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let email = app
+        .send_issue(&issue(
+            r#"<p><a href="https://example.com/post?a=1&amp;b=2">Post</a></p>"#,
+            "Read https://example.com/post?a=1&b=2.",
+        ))
+        .await;
+    assert!(!email.html.contains("example.com"));
+    assert!(!email.text.contains("example.com"));
+    let html_link = app.find_tracking_url(&email.html, "/t/c/").unwrap();
+    let text_link = app.find_tracking_url(&email.text, "/t/c/").unwrap();
+
+    for link in [html_link, text_link] {
+        // Act
+        let response = click(&link).await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 302);
+        assert_eq!(
+            response.headers()["Location"],
+            "https://example.com/post?a=1&b=2"
+        );
+    }
+    assert_eq!(
+        app.get_clicks().await,
+        vec![("https://example.com/post?a=1&b=2".to_string(), 2)]
+    );
+}
+
+#[tokio::test]
+async fn the_click_endpoint_only_redirects_to_links_of_the_issue() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let email = app
+        .send_issue(&issue(
+            r#"<p><a href="https://example.com/post">Post</a></p>"#,
+            "Read https://example.com/post",
+        ))
+        .await;
+    let link = app.find_tracking_url(&email.html, "/t/c/").unwrap();
+    let delivery = sqlx::query!(
+        r#"SELECT newsletter_issue_id AS "newsletter_issue_id!", id AS "id!"
+        FROM newsletter_issues, subscriptions"#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    let delivery = Delivery {
+        newsletter_issue_id: delivery.newsletter_issue_id,
+        subscriber_id: delivery.id,
+    };
+    let test_cases = vec![
+        (link.replace("/t/c/", "/t/c/x"), "a tampered token"),
+        (
+            app.tracking
+                .click_url(&app.address, &delivery, "https://evil.example.com"),
+            "a genuine token for a link missing from the issue",
+        ),
+    ];
+
+    for (url, description) in test_cases {
+        // Act
+        let response = click(&url).await;
+
+        // Assert
+        assert_eq!(
+            response.status().as_u16(),
+            404,
+            "The API did not reject {}.",
+            description
+        );
+    }
+    assert!(app.get_clicks().await.is_empty());
+}
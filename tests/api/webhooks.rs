@@ -0,0 +1,330 @@
+use wiremock::matchers::any;
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{bounce, complaint, create_confirmed_subscriber, spawn_app, TestApp};
+
+impl TestApp {
+    async fn subscriber_status(&self) -> (String, i32) {
+        let saved = sqlx::query!("SELECT status, soft_bounce_count FROM subscriptions")
+            .fetch_one(&self.db_pool)
+            .await
+            .unwrap();
+        (saved.status, saved.soft_bounce_count)
+    }
+}
+
+#[tokio::test]
+async fn a_hard_bounce_marks_the_subscriber_as_bounced() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    // Act
+    let response = app
+        .post_bounce(&bounce(1, "HardBounce", "ursula_le_guin@gmail.com"))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(app.subscriber_status().await.0, "bounced");
+    let recorded = sqlx::query!("SELECT bounce_id, kind, bounce_type FROM email_bounces")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(recorded.bounce_id, "1");
+    assert_eq!(recorded.kind, "hard");
+    assert_eq!(recorded.bounce_type, "HardBounce");
+}
+
+#[tokio::test]
+async fn bounces_match_addresses_ignoring_case_and_whitespace() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    // Act
+    let response = app
+        .post_bounce(&bounce(1, "HardBounce", " Ursula_Le_Guin@Gmail.com "))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(app.subscriber_status().await.0, "bounced");
+}
+
+#[tokio::test]
+async fn bounced_subscribers_do_not_receive_newsletters() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.post_bounce(&bounce(1, "HardBounce", "ursula_le_guin@gmail.com"))
+        .await
+        .error_for_status()
+        .unwrap();
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_newsletter(&serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    // Mock verifies on Drop that we haven't sent the newsletter email
+}
+
+#[tokio::test]
+async fn soft_bounces_mark_the_subscriber_as_bounced_past_the_threshold() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let threshold = app.email_webhooks.soft_bounce_threshold as i64;
+
+    // Act - Part 1 - Below the threshold
+    for id in 1..threshold {
+        app.post_bounce(&bounce(id, "SoftBounce", "ursula_le_guin@gmail.com"))
+            .await
+            .error_for_status()
+            .unwrap();
+    }
+    assert_eq!(
+        app.subscriber_status().await,
+        ("confirmed".into(), threshold as i32 - 1)
+    );
+
+    // Act - Part 2 - Reaching it
+    app.post_bounce(&bounce(threshold, "Transient", "ursula_le_guin@gmail.com"))
+        .await
+        .error_for_status()
+        .unwrap();
+    assert_eq!(
+        app.subscriber_status().await,
+        ("bounced".into(), threshold as i32)
+    );
+}
+
+#[tokio::test]
+async fn a_webhook_delivered_twice_is_counted_once() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    for _ in 0..app.email_webhooks.soft_bounce_threshold {
+        // Act
+        let response = app
+            .post_bounce(&bounce(1, "SoftBounce", "ursula_le_guin@gmail.com"))
+            .await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 200);
+    }
+    assert_eq!(app.subscriber_status().await, ("confirmed".into(), 1));
+}
+
+#[tokio::test]
+async fn bounces_we_do_not_act_on_are_acknowledged() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let mut spam_complaint = bounce(3, "SpamComplaint", "ursula_le_guin@gmail.com");
+    spam_complaint["RecordType"] = "SpamComplaint".into();
+    let test_cases = vec![
+        (
+            bounce(1, "HardBounce", "someone_else@gmail.com"),
+            "an unknown address",
+        ),
+        (
+            bounce(2, "AutoResponder", "ursula_le_guin@gmail.com"),
+            "an auto-responder",
+        ),
+        (spam_complaint, "another kind of record"),
+    ];
+
+    for (body, description) in test_cases {
+        // Act
+        let response = app.post_bounce(&body).await;
+
+        // Assert
+        assert_eq!(
+            response.status().as_u16(),
+            200,
+            "The API did not acknowledge {}.",
+            description
+        );
+    }
+    assert_eq!(app.subscriber_status().await, ("confirmed".into(), 0));
+}
+
+#[tokio::test]
+async fn the_webhook_rejects_requests_without_valid_credentials() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let body = bounce(1, "HardBounce", "ursula_le_guin@gmail.com");
+    let url = format!("{}/webhooks/email/bounce", &app.address);
+    let test_cases = vec![
+        (reqwest::Client::new().post(&url), "missing credentials"),
+        (
+            reqwest::Client::new()
+                .post(&url)
+                .basic_auth(&app.email_webhooks.username, Some("wrong-password")),
+            "a wrong password",
+        ),
+        (
+            reqwest::Client::new()
+                .post(&url)
+                .basic_auth(&app.test_user.username, Some(&app.test_user.password)),
+            "the credentials of a user",
+        ),
+    ];
+
+    for (request, description) in test_cases {
+        // Act
+        let response = request.json(&body).send().await.unwrap();
+
+        // Assert
+        assert_eq!(
+            response.status().as_u16(),
+            401,
+            "The API did not reject {}.",
+            description
+        );
+        assert_eq!(
+            r#"Basic realm="webhooks""#,
+            response.headers()["WWW-Authenticate"]
+        );
+    }
+    assert_eq!(app.subscriber_status().await.0, "confirmed");
+}
+
+#[tokio::test]
+async fn a_bounced_subscriber_can_subscribe_again() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.post_bounce(&bounce(1, "SoftBounce", "ursula_le_guin@gmail.com"))
+        .await
+        .error_for_status()
+        .unwrap();
+    app.post_bounce(&bounce(2, "HardBounce", "ursula_le_guin@gmail.com"))
+        .await
+        .error_for_status()
+        .unwrap();
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        app.subscriber_status().await,
+        ("pending_confirmation".into(), 0)
+    );
+}
+
+#[tokio::test]
+async fn a_spam_complaint_suppresses_the_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    // Act
+    let response = app
+        .post_complaint(&complaint(1, "ursula_le_guin@gmail.com"))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(app.subscriber_status().await.0, "complained");
+    let suppression = sqlx::query!("SELECT reason, source FROM suppressions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(suppression.reason, "complaint");
+    assert_eq!(suppression.source, "webhook");
+}
+
+#[tokio::test]
+async fn subscribers_who_complained_cannot_subscribe_again() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.post_complaint(&complaint(1, "ursula_le_guin@gmail.com"))
+        .await
+        .error_for_status()
+        .unwrap();
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(app.subscriber_status().await.0, "complained");
+    // Mock verifies on Drop that we haven't sent a confirmation email
+}
+
+#[tokio::test]
+async fn complaints_about_unknown_addresses_suppress_them_all_the_same() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    for _ in 0..2 {
+        let response = app
+            .post_complaint(&complaint(1, "someone_else@gmail.com"))
+            .await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+
+    // Assert
+    let suppressions = sqlx::query!("SELECT email_hash FROM suppressions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(suppressions.len(), 1);
+}
+
+#[tokio::test]
+async fn the_complaint_webhook_rejects_requests_without_valid_credentials() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!("{}/webhooks/email/complaint", &app.address))
+        .basic_auth(&app.email_webhooks.username, Some("wrong-password"))
+        .json(&complaint(1, "ursula_le_guin@gmail.com"))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+    assert_eq!(app.subscriber_status().await.0, "confirmed");
+}